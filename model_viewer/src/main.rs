@@ -26,48 +26,66 @@ use bevy_egui::{
 };
 use bevy_obj::ObjPlugin;
 use egui_extras::TableBuilder;
-use wow_vr_lib::mpq::MpqCollection;
+#[cfg(feature = "file_watcher")]
+use wow_vr_lib::mpq::overlay::OverlayWatcher;
+use wow_vr_lib::mpq::{
+    MpqCollection,
+    client::ClientData,
+    overlay::{LooseIndex, OverlayAssetReader},
+    provenance::FileProvenance,
+};
 use wow_vr_lib::{
-    m2::{M2Asset, M2Plugin},
+    m2::{M2Asset, M2Plugin, M2RelatedAsset, material::M2Material},
     mpq::MpqAssetReader,
 };
 
-const MPQ_FILES: [&str; 13] = [
-    "common.MPQ",
-    "common-2.MPQ",
-    "expansion.MPQ",
-    "lichking.MPQ",
-    "patch.MPQ",
-    "patch-2.MPQ",
-    "patch-3.MPQ",
-    "enUS/locale-enUS.MPQ",
-    "enUS/expansion-locale-enUS.MPQ",
-    "enUS/lichking-locale-enUS.MPQ",
-    "enUS/patch-enUS.MPQ",
-    "enUS/patch-enUS-2.MPQ",
-    "enUS/patch-enUS-3.MPQ",
-];
+const CLIENT_DIR_ENV: &str = "WOW_CLIENT_DIR";
+const LOOSE_DIR_ENV: &str = "WOW_LOOSE_DIR";
+const META_DIR_ENV: &str = "WOW_META_DIR";
+const INDEX_CACHE_FILE: &str = "wow_vr_mpq_index.cache";
 
 #[derive(Resource)]
 pub struct MpqFileList(Vec<String>);
 
+#[derive(Resource)]
+pub struct MpqIndex(MpqCollection);
+
 #[derive(Resource)]
 pub struct SelectedModel {
     path: String,
     // entity: Option<Entity>,
 }
 
+/// Archive entries of the selected model and the files it loads, refreshed by
+/// `update_provenance` when the selection changes or its asset finishes loading.
+#[derive(Resource, Default)]
+pub struct ModelProvenance {
+    path: String,
+    asset_loaded: bool,
+    files: Vec<(String, Vec<FileProvenance>)>,
+}
+
 fn main() {
     let mut plugin = AssetPlugin::default();
     plugin.mode = AssetMode::Unprocessed;
     plugin.unapproved_path_mode = UnapprovedPathMode::Allow;
 
-    let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("..")
-        .join("Data");
+    let client_dir = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var(CLIENT_DIR_ENV).ok())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(".."));
+
+    let client_data = ClientData::discover(&client_dir).unwrap();
+    let loose_dir = std::env::var(LOOSE_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| client_data.data_dir.clone());
+
+    let meta_dir = std::env::var(META_DIR_ENV).map(PathBuf::from).ok();
 
-    let mpq_files: Vec<PathBuf> = MPQ_FILES.iter().map(|p| base_path.join(p)).collect();
-    let mpq_collection = MpqCollection::load(mpq_files).unwrap();
+    let mpq_files = client_data.load_order();
+    let index_cache = std::env::temp_dir().join(INDEX_CACHE_FILE);
+    let mpq_collection = MpqCollection::load_cached(mpq_files.clone(), &index_cache).unwrap();
     let mut file_list: Vec<String> = mpq_collection
         .file_list()
         .iter()
@@ -76,20 +94,41 @@ fn main() {
         .collect();
     file_list.sort();
 
+    let loose_index = LooseIndex::scan(&loose_dir);
+    let mpq_source = {
+        let loose_index = loose_index.clone();
+        AssetSource::build().with_reader(move || {
+            let mut mpq_reader = MpqAssetReader::from_collection(
+                MpqCollection::load_cached(mpq_files.clone(), &index_cache).unwrap(),
+            );
+            if let Some(meta_dir) = &meta_dir {
+                mpq_reader = mpq_reader.with_meta_dir(meta_dir);
+            }
+            Box::new(OverlayAssetReader::with_index(
+                loose_index.clone(),
+                mpq_reader,
+            ))
+        })
+    };
+    #[cfg(feature = "file_watcher")]
+    let mpq_source = mpq_source.with_watcher(move |sender| {
+        OverlayWatcher::new(
+            loose_index.clone(),
+            sender,
+            std::time::Duration::from_millis(300),
+        )
+        .map(|watcher| Box::new(watcher) as Box<dyn bevy_asset::io::AssetWatcher>)
+    });
+
     App::new()
         .insert_resource(MpqFileList(file_list))
+        .insert_resource(MpqIndex(mpq_collection))
         .insert_resource(SelectedModel {
             path: "".into(),
             // entity: None,
         })
-        .register_asset_source(
-            AssetSourceId::Name("mpq".into()),
-            AssetSource::build().with_reader(move || {
-                Box::new(MpqAssetReader::new(
-                    MPQ_FILES.iter().map(|p| base_path.join(p)).collect(),
-                ))
-            }),
-        )
+        .init_resource::<ModelProvenance>()
+        .register_asset_source(AssetSourceId::Name("mpq".into()), mpq_source)
         .add_plugins((
             DefaultPlugins
                 .set(ImagePlugin::default_linear())
@@ -104,7 +143,10 @@ fn main() {
             EguiPlugin::default(),
         ))
         .add_systems(Startup, setup)
-        .add_systems(EguiPrimaryContextPass, draw_ui)
+        .add_systems(
+            EguiPrimaryContextPass,
+            (draw_ui, update_provenance, draw_provenance, draw_animations).chain(),
+        )
         .add_systems(
             Update,
             keyboard_input_system.run_if(not(egui_wants_any_keyboard_input)),
@@ -190,6 +232,7 @@ fn rotate(
 fn spawn_model(
     mut m2component: Single<&mut M2Component>,
     mut m2s: ResMut<Assets<M2Asset>>,
+    mut materials: ResMut<Assets<M2Material>>,
     mut commands: Commands,
 ) {
     if m2component.m2.is_none() || m2component.entity.is_some() {
@@ -198,7 +241,7 @@ fn spawn_model(
 
     if let Some(m2) = m2s.get_mut(m2component.m2.as_mut().unwrap()) {
         dbg!(&m2);
-        let meshes = &m2.meshes[&(m2component.skin_id as u32)];
+        m2component.skin_id = m2.default_skin as usize;
         let spawned = commands
             .spawn((
                 Transform::from_xyz(0., 0., 0.)
@@ -207,16 +250,25 @@ fn spawn_model(
                 Visibility::default(),
                 Shape,
             ))
-            .with_children(|parent| {
-                for mesh in meshes {
-                    parent.spawn((
-                        Mesh3d(mesh.mesh.clone()),
-                        MeshMaterial3d(m2.materials[m2component.skin_id][&mesh.material].clone()),
-                    ));
-                }
-            })
             .id();
 
+        if let Some(graph) = &m2.animation_graph {
+            let mut player = AnimationPlayer::default();
+            if let Some(animation) = m2.animation("Stand").or(m2.animations.first()) {
+                player.play(animation.node).repeat();
+            }
+            commands
+                .entity(spawned)
+                .insert((player, AnimationGraphHandle(graph.clone())));
+        }
+
+        m2.spawn(
+            &mut commands,
+            &mut materials,
+            spawned,
+            m2component.skin_id as u32,
+        );
+
         m2component.entity = Some(spawned);
     }
 }
@@ -347,3 +399,125 @@ fn draw_ui(
 
     Ok(())
 }
+
+fn update_provenance(
+    mpq_index: Res<MpqIndex>,
+    selected: Res<SelectedModel>,
+    m2component: Single<&M2Component>,
+    m2s: Res<Assets<M2Asset>>,
+    mut provenance: ResMut<ModelProvenance>,
+) {
+    let m2 = m2component.m2.as_ref().and_then(|handle| m2s.get(handle));
+    if provenance.path == selected.path && provenance.asset_loaded == m2.is_some() {
+        return;
+    }
+
+    let mut files = Vec::new();
+    if !selected.path.is_empty() {
+        files.push(selected.path.clone());
+    }
+    if let Some(m2) = m2 {
+        for i in 0..m2.skins.len() {
+            let skin_path = M2RelatedAsset::Skin(i as u32).from_asset(selected.path.clone());
+            files.push(skin_path.path().to_string_lossy().into_owned());
+        }
+        files.extend(m2.textures.iter().map(|(name, _)| name.clone()));
+    }
+
+    *provenance = ModelProvenance {
+        path: selected.path.clone(),
+        asset_loaded: m2.is_some(),
+        files: files
+            .into_iter()
+            .map(|file| {
+                let entries = mpq_index.0.provenance(&file);
+                (file, entries)
+            })
+            .collect(),
+    };
+}
+
+fn draw_provenance(
+    mut contexts: EguiContexts,
+    selected: Res<SelectedModel>,
+    provenance: Res<ModelProvenance>,
+) -> Result {
+    if selected.path.is_empty() {
+        return Ok(());
+    }
+
+    let ctx = contexts.ctx_mut()?;
+
+    egui::Window::new("Provenance")
+        .default_pos([420.0, 20.0])
+        .default_width(480.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (file, entries) in &provenance.files {
+                    ui.strong(file);
+                    egui::Grid::new(file).striped(true).show(ui, |ui| {
+                        ui.label("Archive");
+                        ui.label("Size");
+                        ui.label("Stored");
+                        ui.label("Flags");
+                        ui.label("Role");
+                        ui.end_row();
+
+                        for entry in entries {
+                            let archive_name = entry.archive_path.file_name().unwrap_or_default();
+                            ui.label(archive_name.to_string_lossy().into_owned());
+                            ui.label(entry.file_size.to_string());
+                            ui.label(entry.compressed_size.to_string());
+                            ui.label(format!("0x{:08X}", entry.flags));
+                            ui.label(format!("{:?}", entry.role));
+                            ui.end_row();
+                        }
+                    });
+                    ui.separator();
+                }
+            });
+        });
+
+    Ok(())
+}
+
+fn draw_animations(
+    mut contexts: EguiContexts,
+    m2component: Single<&M2Component>,
+    m2s: Res<Assets<M2Asset>>,
+    mut players: Query<&mut AnimationPlayer>,
+) -> Result {
+    let Some(m2) = m2component.m2.as_ref().and_then(|handle| m2s.get(handle)) else {
+        return Ok(());
+    };
+    let Some(mut player) = m2component
+        .entity
+        .and_then(|entity| players.get_mut(entity).ok())
+    else {
+        return Ok(());
+    };
+
+    let ctx = contexts.ctx_mut()?;
+
+    egui::Window::new("Animations")
+        .default_pos([420.0, 420.0])
+        .default_width(240.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for animation in &m2.animations {
+                    let playing = player.is_playing_animation(animation.node);
+                    let label = format!(
+                        "{} ({:.2}s)",
+                        animation.name,
+                        animation.sequence.duration as f32 / 1000.
+                    );
+                    if ui.selectable_label(playing, label).clicked() {
+                        player.stop_all();
+                        player.play(animation.node).repeat();
+                    }
+                }
+            });
+        });
+
+    Ok(())
+}
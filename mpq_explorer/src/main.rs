@@ -0,0 +1,271 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use clap::{Parser, Subcommand};
+use glob::{MatchOptions, Pattern};
+use regex::RegexBuilder;
+use wow_vr_lib::mpq::{
+    MpqCollection,
+    client::ClientData,
+    format_file_name,
+    verify::{VerifyOptions, verify_archive},
+};
+
+const CLIENT_DIR_ENV: &str = "WOW_CLIENT_DIR";
+
+#[derive(Parser)]
+#[command(about = "List, inspect and extract files from the client MPQ archives")]
+struct Cli {
+    /// Client root or Data directory, used when no archives are given.
+    #[arg(long, env = CLIENT_DIR_ENV)]
+    client: Option<PathBuf>,
+
+    /// Archives to load instead of the client load order, lowest priority first.
+    #[arg(long = "archive")]
+    archives: Vec<PathBuf>,
+
+    /// External listfiles merged into the index.
+    #[arg(long = "listfile")]
+    listfiles: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List files matching a glob, or a regex with --regex.
+    List {
+        pattern: Option<String>,
+        #[arg(long)]
+        regex: bool,
+    },
+    /// Show every archive that holds a file and which copy is read.
+    Provenance { files: Vec<String> },
+    /// Write a file to stdout.
+    Cat { file: String },
+    /// Extract matching files, keeping their directory structure.
+    Extract {
+        pattern: String,
+        #[arg(long)]
+        regex: bool,
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Check tables, sector checksums, file checksums and strong signatures of every archive.
+    Verify {
+        /// Raw big endian RSA modulus to check strong signatures with.
+        #[arg(long)]
+        strong_key: Option<PathBuf>,
+    },
+}
+
+fn archive_paths(cli: &Cli) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if cli.archives.is_empty() {
+        let client_dir = cli
+            .client
+            .clone()
+            .ok_or("either --client, --archive or WOW_CLIENT_DIR is required")?;
+        Ok(ClientData::discover(client_dir)?.load_order())
+    } else {
+        Ok(cli.archives.clone())
+    }
+}
+
+fn load_collection(cli: &Cli) -> Result<MpqCollection, Box<dyn Error>> {
+    let mut collection = MpqCollection::load(archive_paths(cli)?)?;
+    for listfile in &cli.listfiles {
+        collection.load_listfile(listfile)?;
+    }
+
+    Ok(collection)
+}
+
+fn matching_files(
+    collection: &MpqCollection,
+    pattern: Option<&str>,
+    regex: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let matcher: Box<dyn Fn(&str) -> bool> = match pattern {
+        None => Box::new(|_| true),
+        Some(pattern) if regex => {
+            let regex = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+            Box::new(move |name| regex.is_match(name))
+        }
+        Some(pattern) => {
+            let pattern = Pattern::new(&format_file_name(pattern))?;
+            let options = MatchOptions {
+                case_sensitive: false,
+                require_literal_separator: false,
+                require_literal_leading_dot: false,
+            };
+            Box::new(move |name| pattern.matches_with(name, options))
+        }
+    };
+
+    let mut files: Vec<String> = collection
+        .file_list()
+        .into_iter()
+        .filter(|name| matcher(name.as_str()))
+        .cloned()
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+/// Where `name` is extracted below `out`, `None` for names that would land outside of it, like
+/// `../x` or absolute paths from a hostile listfile.
+fn extract_path(out: &Path, name: &str) -> Option<PathBuf> {
+    let escapes = Path::new(name).components().any(|component| {
+        matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if escapes { None } else { Some(out.join(name)) }
+}
+
+/// Verifies the archives one by one, so a damaged archive that can't be loaded still gets a
+/// report. Names come from the loaded index when possible, otherwise only from the listfiles.
+fn verify(cli: &Cli, strong_key: Option<&PathBuf>) -> Result<(), Box<dyn Error>> {
+    let options = VerifyOptions {
+        strong_signature_key: strong_key.map(fs::read).transpose()?,
+    };
+    let names: Vec<String> = match load_collection(cli) {
+        Ok(collection) => collection.file_map.into_keys().collect(),
+        Err(err) => {
+            eprintln!(
+                "failed to load the archives, only listfile names are used: {}",
+                err
+            );
+            let mut names = Vec::new();
+            for listfile in &cli.listfiles {
+                names.extend(fs::read_to_string(listfile)?.lines().map(str::to_owned));
+            }
+            names
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    let mut failed = 0;
+    for path in archive_paths(cli)? {
+        let report = verify_archive(&path, &names, &options);
+        writeln!(
+            stdout,
+            "{}: {} ({} files checked, {} skipped, signature {:?})",
+            path.display(),
+            if report.is_ok() { "OK" } else { "FAILED" },
+            report.files_checked,
+            report.files_skipped,
+            report.signature,
+        )?;
+        for issue in &report.issues {
+            writeln!(
+                stdout,
+                "  {:?} {}: {}",
+                issue.kind,
+                issue.file.as_deref().unwrap_or("-"),
+                issue.detail,
+            )?;
+        }
+        if !report.is_ok() {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} archives failed verification", failed).into());
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    if let Command::Verify { strong_key } = &cli.command {
+        return verify(&cli, strong_key.as_ref());
+    }
+
+    let collection = load_collection(&cli)?;
+    let mut stdout = io::stdout().lock();
+
+    match &cli.command {
+        Command::List { pattern, regex } => {
+            for name in matching_files(&collection, pattern.as_deref(), *regex)? {
+                writeln!(stdout, "{}", name)?;
+            }
+        }
+        Command::Provenance { files } => {
+            for file in files {
+                writeln!(stdout, "{}", format_file_name(file))?;
+                let entries = collection.provenance(file);
+                if entries.is_empty() {
+                    writeln!(stdout, "  not found")?;
+                }
+                for entry in entries {
+                    writeln!(
+                        stdout,
+                        "  [{}] {} size={} stored={} flags=0x{:08X} {:?}",
+                        entry.archive_index,
+                        entry.archive_path.display(),
+                        entry.file_size,
+                        entry.compressed_size,
+                        entry.flags,
+                        entry.role,
+                    )?;
+                }
+            }
+        }
+        Command::Cat { file } => {
+            stdout.write_all(&collection.read_file(file)?)?;
+        }
+        Command::Extract {
+            pattern,
+            regex,
+            out,
+        } => {
+            let files = matching_files(&collection, Some(pattern), *regex)?;
+            for name in &files {
+                let Some(target) = extract_path(out, name) else {
+                    eprintln!("skipping {}: the name leaves the output directory", name);
+                    continue;
+                };
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                match collection.read_file(name) {
+                    Ok(data) => {
+                        fs::write(&target, data)?;
+                        writeln!(stdout, "{}", target.display())?;
+                    }
+                    Err(err) => eprintln!("failed to extract {}: {:?}", name, err),
+                }
+            }
+        }
+        Command::Verify { .. } => unreachable!("handled before loading the collection"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracted_names_stay_in_the_output_directory() {
+        let out = Path::new("out");
+        assert_eq!(
+            extract_path(out, "world/generic/bottle01.m2"),
+            Some(PathBuf::from("out/world/generic/bottle01.m2"))
+        );
+        assert_eq!(extract_path(out, "../../.bashrc"), None);
+        assert_eq!(extract_path(out, "world/../../etc/passwd"), None);
+        assert_eq!(extract_path(out, "/etc/passwd"), None);
+    }
+}
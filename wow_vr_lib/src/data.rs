@@ -0,0 +1,225 @@
+pub mod casc;
+pub mod loose;
+pub mod memory;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    result::Result as StdResult,
+};
+
+use bevy::tasks::futures_lite::stream;
+use bevy_asset::io::{AssetReader, AssetReaderError, PathStream, Reader, VecReader};
+
+use crate::errors::{Error, Result};
+use crate::mpq::{
+    MpqCollection, format_file_name, provenance::FileProvenance, stream::to_io_error,
+};
+
+/// A mountable collection of game files, such as the client MPQs, CASC storage or an extracted
+/// directory. Names are matched the way `format_file_name` normalizes them, so callers can pass
+/// paths with either separator and any case.
+pub trait GameData: Send + Sync + 'static {
+    fn contains(&self, name: &str) -> bool;
+
+    fn read_file(&self, name: &str) -> Result<Vec<u8>>;
+
+    /// Opens a file for reading. Backends that can decode incrementally override this, the
+    /// default reads the whole file up front.
+    fn open_file(&self, name: &str) -> Result<Box<dyn Reader + '_>> {
+        Ok(Box::new(VecReader::new(self.read_file(name)?)))
+    }
+
+    /// Every file name known to the collection, normalized.
+    fn file_names(&self) -> Vec<String>;
+
+    fn is_directory(&self, name: &str) -> bool;
+
+    fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>>;
+
+    /// Where the copies of a file come from, lowest priority first.
+    fn provenance(&self, name: &str) -> Vec<FileProvenance>;
+}
+
+impl GameData for MpqCollection {
+    fn contains(&self, name: &str) -> bool {
+        self.file_map.contains_key(&format_file_name(name)) || self.find_archive(name).is_some()
+    }
+
+    fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        MpqCollection::read_file(self, name)
+    }
+
+    fn open_file(&self, name: &str) -> Result<Box<dyn Reader + '_>> {
+        Ok(Box::new(MpqCollection::open_file(self, name)?))
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.file_map.keys().cloned().collect()
+    }
+
+    fn is_directory(&self, name: &str) -> bool {
+        MpqCollection::is_directory(self, name)
+    }
+
+    fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>> {
+        MpqCollection::read_directory(self, name)
+    }
+
+    fn provenance(&self, name: &str) -> Vec<FileProvenance> {
+        MpqCollection::provenance(self, name)
+    }
+}
+
+/// Serves any `GameData` backend as a Bevy asset source. Archives can't hold `.meta` files, so
+/// they are looked up in an optional sidecar directory instead, keyed by the normalized path:
+/// settings for `World\Generic\Bottle01.M2` go in `<meta_dir>/world/generic/bottle01.m2.meta`.
+pub struct GameDataAssetReader<D: GameData> {
+    collection: D,
+    meta_dir: Option<PathBuf>,
+}
+
+impl<D: GameData> GameDataAssetReader<D> {
+    pub fn from_collection(collection: D) -> Self {
+        Self {
+            collection,
+            meta_dir: None,
+        }
+    }
+
+    pub fn with_meta_dir(mut self, meta_dir: impl Into<PathBuf>) -> Self {
+        self.meta_dir = Some(meta_dir.into());
+        self
+    }
+
+    pub fn collection(&self) -> &D {
+        &self.collection
+    }
+
+    pub fn meta_dir(&self) -> Option<&Path> {
+        self.meta_dir.as_deref()
+    }
+
+    /// Where the sidecar meta file for `name` would be.
+    pub fn meta_path(&self, name: &str) -> Option<PathBuf> {
+        let meta_dir = self.meta_dir.as_ref()?;
+        Some(meta_dir.join(format!("{}.meta", format_file_name(name))))
+    }
+}
+
+impl<D: GameData> AssetReader for GameDataAssetReader<D> {
+    async fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<dyn Reader + 'a>, AssetReaderError> {
+        self.collection
+            .open_file(&path.to_string_lossy())
+            .map_err(|err| to_reader_error(err, path))
+    }
+
+    async fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<dyn Reader + 'a>, AssetReaderError> {
+        let Some(meta_path) = self.meta_path(&path.to_string_lossy()) else {
+            return Err(AssetReaderError::NotFound(path.into()));
+        };
+
+        match fs::read(&meta_path) {
+            Ok(bytes) => Ok(Box::new(VecReader::new(bytes))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(AssetReaderError::NotFound(meta_path))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<PathStream>, AssetReaderError> {
+        match self.collection.read_directory(&path.to_string_lossy()) {
+            Some(children) => Ok(Box::new(stream::iter(children))),
+            None => Err(AssetReaderError::NotFound(path.into())),
+        }
+    }
+
+    async fn is_directory<'a>(&'a self, path: &'a Path) -> StdResult<bool, AssetReaderError> {
+        Ok(self.collection.is_directory(&path.to_string_lossy()))
+    }
+}
+
+// Missing files have to be reported as `NotFound` for Bevy to fall back and to log them properly.
+pub(crate) fn to_reader_error(err: Error, path: &Path) -> AssetReaderError {
+    let err = to_io_error(err);
+    if err.kind() == io::ErrorKind::NotFound {
+        AssetReaderError::NotFound(path.into())
+    } else {
+        err.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::block_on;
+
+    use super::*;
+    use memory::MemoryData;
+
+    #[test]
+    fn asset_reader_serves_any_backend() {
+        let mut data = MemoryData::default();
+        data.insert("World\\Generic\\Bottle01.m2", b"MD20".to_vec());
+        let reader = GameDataAssetReader::from_collection(data);
+
+        let mut bytes = Vec::new();
+        block_on(async {
+            let mut file = reader
+                .read(Path::new("world/generic/bottle01.m2"))
+                .await
+                .unwrap();
+            file.read_to_end(&mut bytes).await.unwrap();
+        });
+        assert_eq!(bytes, b"MD20");
+
+        assert!(block_on(reader.is_directory(Path::new("world/generic"))).unwrap());
+        assert!(matches!(
+            block_on(reader.read(Path::new("world/missing.m2"))),
+            Err(AssetReaderError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn meta_files_come_from_the_sidecar_directory() {
+        let meta_dir = std::env::temp_dir().join(format!("wow_vr_meta_{}", std::process::id()));
+        fs::create_dir_all(meta_dir.join("world/generic")).unwrap();
+        fs::write(
+            meta_dir.join("world/generic/bottle01.m2.meta"),
+            b"(settings)",
+        )
+        .unwrap();
+
+        let mut data = MemoryData::default();
+        data.insert("world/generic/bottle01.m2", b"MD20".to_vec());
+        data.insert("world/generic/bottle02.m2", b"MD20".to_vec());
+        let reader = GameDataAssetReader::from_collection(data);
+        assert!(block_on(reader.read_meta(Path::new("world/generic/bottle01.m2"))).is_err());
+
+        let reader = reader.with_meta_dir(&meta_dir);
+        let mut bytes = Vec::new();
+        block_on(async {
+            let mut meta = reader
+                .read_meta(Path::new("World\\Generic\\Bottle01.M2"))
+                .await
+                .unwrap();
+            meta.read_to_end(&mut bytes).await.unwrap();
+        });
+        assert_eq!(bytes, b"(settings)");
+        assert!(matches!(
+            block_on(reader.read_meta(Path::new("world/generic/bottle02.m2"))),
+            Err(AssetReaderError::NotFound(_))
+        ));
+
+        fs::remove_dir_all(&meta_dir).unwrap();
+    }
+}
@@ -0,0 +1,497 @@
+pub mod blte;
+pub mod encoding;
+pub mod index;
+pub mod jenkins;
+pub mod root;
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use custom_debug::Debug;
+
+use super::GameData;
+use crate::errors::{Error, Result};
+use crate::mpq::{
+    directory::DirectoryIndex,
+    format_file_name,
+    provenance::{EntryRole, FileProvenance},
+};
+use encoding::{ContentKey, EncodingEntry, EncodingKey};
+use index::{IndexEntry, IndexKey};
+use root::RootFile;
+
+const BUILD_INFO_FILE: &str = ".build.info";
+
+// Every entry in a data archive starts with its reversed encoding key, size, flags and checksums.
+const DATA_HEADER_SIZE: u64 = 30;
+
+/// Local CASC storage, as installed by the Battle.net launcher for newer and classic clients.
+/// Files are looked up by name hash or file data ID in the root file, mapped to an encoding key
+/// through the encoding file and located in the `data.NNN` archives through the local indices.
+///
+/// Root files only store name hashes, so `file_names` and directory listings only know the
+/// names added with `add_file_names` or `load_listfile`.
+#[derive(Debug)]
+pub struct CascStorage {
+    data_dir: PathBuf,
+
+    #[debug(skip)]
+    indices: HashMap<IndexKey, IndexEntry>,
+
+    #[debug(skip)]
+    encoding: HashMap<ContentKey, EncodingEntry>,
+
+    #[debug(skip)]
+    root: RootFile,
+
+    #[debug(skip)]
+    names: HashMap<String, ContentKey>,
+
+    #[debug(skip)]
+    directories: DirectoryIndex,
+}
+
+impl CascStorage {
+    /// Opens the storage of the install at `root`, which holds `.build.info` and `Data`.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_locale(root, root::LOCALE_ENUS)
+    }
+
+    pub fn open_with_locale(root: impl AsRef<Path>, locale_mask: u32) -> Result<Self> {
+        let root = root.as_ref();
+        let data_dir = root.join("Data");
+        let build_key = active_build_key(&fs::read_to_string(root.join(BUILD_INFO_FILE))?)?;
+        let build_config = parse_config(&fs::read_to_string(config_path(&data_dir, &build_key))?);
+
+        let mut indices = HashMap::new();
+        for path in index::find_index_files(&data_dir.join("data"))? {
+            index::parse_index(&fs::read(path)?, &mut indices)?;
+        }
+
+        let mut storage = Self {
+            data_dir,
+            indices,
+            encoding: HashMap::new(),
+            root: RootFile::default(),
+            names: HashMap::new(),
+            directories: DirectoryIndex::default(),
+        };
+
+        // The encoding file is referenced by encoding key, everything else by content key.
+        let encoding_ekey = config_key(&build_config, "encoding", 1)?;
+        storage.encoding = encoding::parse_encoding(&storage.read_encoded(&encoding_ekey)?)?;
+
+        let root_ckey = config_key(&build_config, "root", 0)?;
+        storage.root = RootFile::parse(&storage.read_content(&root_ckey)?, locale_mask)?;
+
+        Ok(storage)
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn load_listfile(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        let data = fs::read_to_string(path)?;
+        // Listfiles for CASC are usually `id;name`, plain name lists work too.
+        let names = data
+            .lines()
+            .map(|line| line.rsplit(';').next().unwrap_or(line).trim())
+            .filter(|name| !name.is_empty());
+        Ok(self.add_file_names(names))
+    }
+
+    /// Registers names for listing, keeping the ones present in the root file.
+    pub fn add_file_names<S: AsRef<str>>(&mut self, names: impl IntoIterator<Item = S>) -> usize {
+        let mut added = 0;
+        for name in names {
+            let fname = format_file_name(name.as_ref());
+            if self.names.contains_key(&fname) {
+                continue;
+            }
+            if let Some(ckey) = self.root.by_name_hash.get(&jenkins::name_hash(&fname)) {
+                self.directories.insert_file(&fname);
+                self.names.insert(fname, *ckey);
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    pub fn read_file_by_id(&self, file_id: u32) -> Result<Vec<u8>> {
+        let ckey = self
+            .root
+            .by_file_id
+            .get(&file_id)
+            .ok_or_else(|| Error::AssetNotFound(format!("file data id {}", file_id)))?;
+        self.read_content(ckey)
+    }
+
+    fn find_content_key(&self, name: &str) -> Option<ContentKey> {
+        let fname = format_file_name(name);
+        self.names
+            .get(&fname)
+            .or_else(|| self.root.by_name_hash.get(&jenkins::name_hash(&fname)))
+            .copied()
+    }
+
+    fn locate(&self, ckey: &ContentKey) -> Result<(EncodingEntry, IndexEntry)> {
+        let encoding = *self
+            .encoding
+            .get(ckey)
+            .ok_or(Error::Generic("content key missing from the encoding file"))?;
+        let location =
+            *self
+                .indices
+                .get(&index::index_key(&encoding.ekey))
+                .ok_or(Error::Generic(
+                    "encoding key missing from the local indices",
+                ))?;
+        Ok((encoding, location))
+    }
+
+    fn read_content(&self, ckey: &ContentKey) -> Result<Vec<u8>> {
+        let (_, location) = self.locate(ckey)?;
+        self.read_location(&location)
+    }
+
+    fn read_encoded(&self, ekey: &EncodingKey) -> Result<Vec<u8>> {
+        let location = *self
+            .indices
+            .get(&index::index_key(ekey))
+            .ok_or(Error::Generic(
+                "encoding key missing from the local indices",
+            ))?;
+        self.read_location(&location)
+    }
+
+    fn archive_path(&self, archive: u32) -> PathBuf {
+        self.data_dir
+            .join("data")
+            .join(format!("data.{:03}", archive))
+    }
+
+    fn read_location(&self, location: &IndexEntry) -> Result<Vec<u8>> {
+        let size = (location.size as u64)
+            .checked_sub(DATA_HEADER_SIZE)
+            .ok_or(Error::Generic("CASC entry smaller than its header"))?;
+
+        let mut file = File::open(self.archive_path(location.archive))?;
+        file.seek(SeekFrom::Start(location.offset + DATA_HEADER_SIZE))?;
+        let mut data = vec![0u8; size as usize];
+        file.read_exact(&mut data)?;
+
+        blte::decode(&data)
+    }
+}
+
+impl GameData for CascStorage {
+    fn contains(&self, name: &str) -> bool {
+        self.find_content_key(name).is_some()
+    }
+
+    fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        let ckey = self
+            .find_content_key(name)
+            .ok_or_else(|| Error::AssetNotFound(format_file_name(name)))?;
+        self.read_content(&ckey)
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.names.keys().cloned().collect()
+    }
+
+    fn is_directory(&self, name: &str) -> bool {
+        self.directories.is_directory(&format_file_name(name))
+    }
+
+    fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>> {
+        self.directories
+            .read_directory(&format_file_name(name))
+            .map(|children| children.map(PathBuf::from).collect())
+    }
+
+    fn provenance(&self, name: &str) -> Vec<FileProvenance> {
+        self.find_content_key(name)
+            .and_then(|ckey| self.locate(&ckey).ok())
+            .and_then(|(encoding, location)| {
+                Some(FileProvenance {
+                    archive_index: location.archive as usize,
+                    archive_path: self.archive_path(location.archive),
+                    file_size: encoding.file_size,
+                    compressed_size: (location.size as u64).checked_sub(DATA_HEADER_SIZE)?,
+                    flags: 0,
+                    role: EntryRole::Base,
+                })
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Finds the build key of the active product in `.build.info`, a `|` separated table whose
+/// header names columns as `Name!TYPE:SIZE`. The key names the build config, so it has to be
+/// a 32 character hex key.
+fn active_build_key(build_info: &str) -> Result<String> {
+    let mut lines = build_info.lines().filter(|line| !line.trim().is_empty());
+    let columns: Vec<&str> = lines
+        .next()
+        .ok_or(Error::Generic("empty .build.info"))?
+        .split('|')
+        .map(|column| column.split('!').next().unwrap_or(column))
+        .collect();
+
+    let column = |name: &str| columns.iter().position(|column| *column == name);
+    let build_key = column("Build Key").ok_or(Error::Generic(".build.info has no build key"))?;
+    let active = column("Active");
+
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split('|').collect()).collect();
+    rows.iter()
+        .find(|row| active.is_none_or(|active| row.get(active) == Some(&"1")))
+        .or(rows.first())
+        .and_then(|row| row.get(build_key))
+        .ok_or(Error::Generic(".build.info has no builds"))
+        .and_then(|key| match parse_hex_key(key) {
+            Some(_) => Ok(key.to_string()),
+            None => Err(Error::Generic(".build.info has an invalid build key")),
+        })
+}
+
+fn config_path(data_dir: &Path, key: &str) -> PathBuf {
+    data_dir
+        .join("config")
+        .join(&key[..2])
+        .join(&key[2..4])
+        .join(key)
+}
+
+fn parse_config(config: &str) -> HashMap<String, Vec<String>> {
+    config
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let values = value.split_whitespace().map(str::to_owned).collect();
+            (key.trim().to_owned(), values)
+        })
+        .collect()
+}
+
+fn config_key(
+    config: &HashMap<String, Vec<String>>,
+    name: &str,
+    position: usize,
+) -> Result<[u8; 16]> {
+    config
+        .get(name)
+        .and_then(|values| values.get(position))
+        .and_then(|value| parse_hex_key(value))
+        .ok_or(Error::Generic("build config is missing a key"))
+}
+
+fn parse_hex_key(value: &str) -> Option<[u8; 16]> {
+    if value.len() != 32 {
+        return None;
+    }
+
+    let mut key = [0u8; 16];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(value.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+    use super::*;
+
+    #[test]
+    fn reads_build_info_and_config() {
+        let build_info = "Branch!STRING:0|Active!DEC:1|Build Key!HEX:16|CDN Key!HEX:16\n\
+            eu|0|00000000000000000000000000000000|11\n\
+            us|1|0123456789abcdef0123456789abcdef|22\n";
+        assert_eq!(
+            active_build_key(build_info).unwrap(),
+            "0123456789abcdef0123456789abcdef"
+        );
+
+        let config = parse_config(
+            "# Build Configuration\n\
+             root = 000102030405060708090a0b0c0d0e0f\n\
+             encoding = ffffffffffffffffffffffffffffffff 0f0e0d0c0b0a09080706050403020100\n",
+        );
+        assert_eq!(config_key(&config, "root", 0).unwrap()[1], 1);
+        assert_eq!(config_key(&config, "encoding", 1).unwrap()[0], 0x0f);
+        assert!(config_key(&config, "install", 0).is_err());
+
+        for key in [
+            "",
+            "ab",
+            "0123456789abcdef0123456789abcdé",
+            "xyz3456789abcdef0123456789abcdef",
+        ] {
+            let build_info = format!("Active!DEC:1|Build Key!HEX:16\n1|{}\n", key);
+            assert!(active_build_key(&build_info).is_err());
+        }
+    }
+
+    fn hex(key: &[u8]) -> String {
+        key.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn blte(payload: &[u8]) -> Vec<u8> {
+        [blte::BLTE_MAGIC.as_slice(), &[0, 0, 0, 0], b"N", payload].concat()
+    }
+
+    fn encoding_file(entries: &[(ContentKey, EncodingKey, u64)]) -> Vec<u8> {
+        let mut data = b"EN".to_vec();
+        data.extend_from_slice(&[1, 16, 16]);
+        data.write_u16::<BigEndian>(1).unwrap();
+        data.write_u16::<BigEndian>(1).unwrap();
+        data.write_u32::<BigEndian>(1).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.write_u8(0).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.extend_from_slice(&[0; 32]);
+
+        let mut page = Vec::new();
+        for (ckey, ekey, size) in entries {
+            page.write_u8(1).unwrap();
+            page.write_u8(0).unwrap();
+            page.write_u32::<BigEndian>(*size as u32).unwrap();
+            page.extend_from_slice(ckey);
+            page.extend_from_slice(ekey);
+        }
+        page.resize(1024, 0);
+        data.extend_from_slice(&page);
+        data
+    }
+
+    fn index_file(entries: &[(EncodingKey, u64, u32)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(16).unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.write_u16::<LittleEndian>(7).unwrap();
+        data.extend_from_slice(&[0, 0, 4, 5, 9, 30]);
+        data.write_u64::<LittleEndian>(0x4000_0000).unwrap();
+        data.resize(32, 0);
+
+        data.write_u32::<LittleEndian>(entries.len() as u32 * 18)
+            .unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        for (ekey, offset, size) in entries {
+            data.extend_from_slice(&ekey[..index::INDEX_KEY_SIZE]);
+            // Archive 0, so the packed offset is the offset itself.
+            data.write_u8(0).unwrap();
+            data.write_u32::<BigEndian>(*offset as u32).unwrap();
+            data.write_u32::<LittleEndian>(*size).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn opens_and_reads_local_storage() {
+        let dir = std::env::temp_dir().join(format!("wow_vr_casc_{}", std::process::id()));
+        let data_dir = dir.join("Data");
+        let build_key = [0x12; 16];
+        let (root_ckey, root_ekey) = ([1; 16], [2; 16]);
+        let encoding_ekey = [3; 16];
+        let (file_ckey, file_ekey) = ([4; 16], [5; 16]);
+        let (short_ckey, short_ekey) = ([6; 16], [7; 16]);
+        let file_name = "World\\Generic\\Bottle01.m2";
+        let short_name = "world/generic/short.m2";
+
+        let mut root = Vec::new();
+        root.write_u32::<LittleEndian>(2).unwrap();
+        root.write_u32::<LittleEndian>(0).unwrap();
+        root.write_u32::<LittleEndian>(root::LOCALE_ENUS).unwrap();
+        root.write_i32::<LittleEndian>(7).unwrap();
+        root.write_i32::<LittleEndian>(0).unwrap();
+        for (ckey, name) in [(file_ckey, file_name), (short_ckey, short_name)] {
+            root.extend_from_slice(&ckey);
+            root.write_u64::<LittleEndian>(jenkins::name_hash(&format_file_name(name)))
+                .unwrap();
+        }
+
+        let contents = b"bottle".to_vec();
+        let encoding = encoding_file(&[
+            (root_ckey, root_ekey, root.len() as u64),
+            (file_ckey, file_ekey, contents.len() as u64),
+            (short_ckey, short_ekey, 1),
+        ]);
+
+        // Every blob is stored behind a data header, which the reader skips.
+        let mut archive = Vec::new();
+        let mut index_entries = Vec::new();
+        for (ekey, blob) in [
+            (encoding_ekey, blte(&encoding)),
+            (root_ekey, blte(&root)),
+            (file_ekey, blte(&contents)),
+        ] {
+            let size = DATA_HEADER_SIZE as u32 + blob.len() as u32;
+            index_entries.push((ekey, archive.len() as u64, size));
+            archive.extend_from_slice(&[0; DATA_HEADER_SIZE as usize]);
+            archive.extend_from_slice(&blob);
+        }
+        index_entries.push((short_ekey, 0, 10));
+
+        let config_dir = data_dir.join("config/12/12");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::create_dir_all(data_dir.join("data")).unwrap();
+        fs::write(
+            dir.join(BUILD_INFO_FILE),
+            format!(
+                "Branch!STRING:0|Active!DEC:1|Build Key!HEX:16\nus|1|{}\n",
+                hex(&build_key)
+            ),
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join(hex(&build_key)),
+            format!(
+                "root = {}\nencoding = {} {}\n",
+                hex(&root_ckey),
+                hex(&[9; 16]),
+                hex(&encoding_ekey)
+            ),
+        )
+        .unwrap();
+        fs::write(
+            data_dir.join("data/0000000001.idx"),
+            index_file(&index_entries),
+        )
+        .unwrap();
+        fs::write(data_dir.join("data/data.000"), archive).unwrap();
+
+        let storage = CascStorage::open(&dir).unwrap();
+        assert!(storage.contains("WORLD/GENERIC/BOTTLE01.M2"));
+        assert_eq!(storage.read_file(file_name).unwrap(), contents);
+        assert_eq!(storage.read_file_by_id(7).unwrap(), contents);
+
+        let provenance = storage.provenance(file_name);
+        assert_eq!(provenance.len(), 1);
+        assert_eq!(provenance[0].archive_path, data_dir.join("data/data.000"));
+        assert_eq!(provenance[0].file_size, contents.len() as u64);
+        assert_eq!(provenance[0].compressed_size, blte(&contents).len() as u64);
+
+        // An index entry too short for its data header can neither be read nor described.
+        assert!(storage.read_file(short_name).is_err());
+        assert!(storage.provenance(short_name).is_empty());
+
+        fs::write(
+            dir.join(BUILD_INFO_FILE),
+            "Active!DEC:1|Build Key!HEX:16\n1|1\n",
+        )
+        .unwrap();
+        assert!(CascStorage::open(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Decoder for BLTE, the chunked container every file in CASC storage is wrapped in.
+
+use std::io::{Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+
+use crate::errors::{Error, Result};
+
+pub const BLTE_MAGIC: &[u8; 4] = b"BLTE";
+
+const CHUNK_INFO_SIZE: usize = 24;
+
+/// Decodes a complete BLTE stream into the original file contents.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 8 || &data[..4] != BLTE_MAGIC {
+        return Err(Error::Generic("missing BLTE signature"));
+    }
+
+    let mut cursor = Cursor::new(&data[4..]);
+    let header_size = cursor.read_u32::<BigEndian>()? as usize;
+
+    // Without a chunk table the rest of the stream is a single chunk.
+    if header_size == 0 {
+        let mut output = Vec::new();
+        decode_chunk(&data[8..], &mut output)?;
+        return Ok(output);
+    }
+
+    let _flags = cursor.read_u8()?;
+    let chunk_count = cursor.read_u24::<BigEndian>()? as usize;
+    if header_size > data.len() || 12 + chunk_count * CHUNK_INFO_SIZE > header_size {
+        return Err(Error::Generic("truncated BLTE header"));
+    }
+
+    let mut output = Vec::new();
+    let mut offset = header_size;
+    for _ in 0..chunk_count {
+        let compressed_size = cursor.read_u32::<BigEndian>()? as usize;
+        let decompressed_size = cursor.read_u32::<BigEndian>()? as usize;
+        let mut _checksum = [0u8; 16];
+        cursor.read_exact(&mut _checksum)?;
+
+        let chunk = data
+            .get(offset..offset + compressed_size)
+            .ok_or(Error::Generic("truncated BLTE chunk"))?;
+        let start = output.len();
+        decode_chunk(chunk, &mut output)?;
+        if output.len() - start != decompressed_size {
+            return Err(Error::Generic("BLTE chunk has the wrong size"));
+        }
+        offset += compressed_size;
+    }
+
+    Ok(output)
+}
+
+fn decode_chunk(chunk: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    let (mode, payload) = chunk
+        .split_first()
+        .ok_or(Error::Generic("empty BLTE chunk"))?;
+
+    match mode {
+        b'N' => output.extend_from_slice(payload),
+        b'Z' => {
+            ZlibDecoder::new(payload).read_to_end(output)?;
+        }
+        b'F' => output.extend(decode(payload)?),
+        b'E' => return Err(Error::Generic("encrypted BLTE chunks are not supported")),
+        _ => return Err(Error::Generic("unknown BLTE chunk mode")),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use byteorder::WriteBytesExt;
+    use flate2::{Compression, write::ZlibEncoder};
+
+    use super::*;
+
+    #[test]
+    fn decodes_plain_and_zlib_chunks() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b" world").unwrap();
+        let chunks = [
+            [b"N".as_slice(), b"hello"].concat(),
+            [b"Z".as_slice(), &zlib.finish().unwrap()].concat(),
+        ];
+
+        let header_size = 12 + chunks.len() * CHUNK_INFO_SIZE;
+        let mut data = BLTE_MAGIC.to_vec();
+        data.write_u32::<BigEndian>(header_size as u32).unwrap();
+        data.write_u8(0x0F).unwrap();
+        data.write_u24::<BigEndian>(chunks.len() as u32).unwrap();
+        for (chunk, size) in chunks.iter().zip([5, 6]) {
+            data.write_u32::<BigEndian>(chunk.len() as u32).unwrap();
+            data.write_u32::<BigEndian>(size).unwrap();
+            data.extend_from_slice(&[0; 16]);
+        }
+        for chunk in &chunks {
+            data.extend_from_slice(chunk);
+        }
+
+        assert_eq!(decode(&data).unwrap(), b"hello world");
+
+        let single = [BLTE_MAGIC.as_slice(), &[0, 0, 0, 0], b"Nraw"].concat();
+        assert_eq!(decode(&single).unwrap(), b"raw");
+    }
+}
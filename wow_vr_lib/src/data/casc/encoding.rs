@@ -0,0 +1,121 @@
+//! Reader for the encoding file, which maps content keys (the MD5 of a file's contents) to the
+//! encoding keys the data is stored under.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::errors::{Error, Result};
+
+pub type ContentKey = [u8; 16];
+pub type EncodingKey = [u8; 16];
+
+const HEADER_SIZE: usize = 22;
+const PAGE_INDEX_ENTRY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingEntry {
+    pub ekey: EncodingKey,
+    pub file_size: u64,
+}
+
+/// Parses a decoded encoding file into a content key lookup. Only the first encoding key of
+/// each entry is kept, which is the one local storage uses.
+pub fn parse_encoding(data: &[u8]) -> Result<HashMap<ContentKey, EncodingEntry>> {
+    let mut cursor = Cursor::new(data);
+    let mut magic = [0u8; 2];
+    cursor.read_exact(&mut magic)?;
+    if &magic != b"EN" || cursor.read_u8()? != 1 {
+        return Err(Error::Generic("unsupported CASC encoding file"));
+    }
+
+    let ckey_size = cursor.read_u8()? as usize;
+    let ekey_size = cursor.read_u8()? as usize;
+    if ckey_size != 16 || ekey_size != 16 {
+        return Err(Error::Generic("unsupported CASC encoding key size"));
+    }
+
+    let ckey_page_size = cursor.read_u16::<BigEndian>()? as usize * 1024;
+    let _espec_page_size = cursor.read_u16::<BigEndian>()?;
+    let ckey_page_count = cursor.read_u32::<BigEndian>()? as usize;
+    let _espec_page_count = cursor.read_u32::<BigEndian>()?;
+    let _flags = cursor.read_u8()?;
+    let espec_block_size = cursor.read_u32::<BigEndian>()? as usize;
+
+    let pages_start = HEADER_SIZE + espec_block_size + ckey_page_count * PAGE_INDEX_ENTRY_SIZE;
+    let mut entries = HashMap::new();
+    for page in 0..ckey_page_count {
+        let start = pages_start + page * ckey_page_size;
+        let page = data
+            .get(start..start + ckey_page_size)
+            .ok_or(Error::Generic("truncated CASC encoding file"))?;
+        parse_page(page, ckey_size + ekey_size, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+fn parse_page(
+    page: &[u8],
+    key_sizes: usize,
+    entries: &mut HashMap<ContentKey, EncodingEntry>,
+) -> Result<()> {
+    let mut cursor = Cursor::new(page);
+    // Pages are zero padded, an entry without keys marks the end.
+    while (cursor.position() as usize) + 6 + key_sizes <= page.len() {
+        let key_count = cursor.read_u8()? as usize;
+        if key_count == 0 {
+            break;
+        }
+
+        let file_size = ((cursor.read_u8()? as u64) << 32) | cursor.read_u32::<BigEndian>()? as u64;
+        let mut ckey = [0u8; 16];
+        cursor.read_exact(&mut ckey)?;
+        let mut ekey = [0u8; 16];
+        cursor.read_exact(&mut ekey)?;
+        cursor.set_position(cursor.position() + (key_count as u64 - 1) * 16);
+
+        entries.insert(ckey, EncodingEntry { ekey, file_size });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    #[test]
+    fn maps_content_keys() {
+        let mut data = b"EN".to_vec();
+        data.extend_from_slice(&[1, 16, 16]);
+        data.write_u16::<BigEndian>(1).unwrap();
+        data.write_u16::<BigEndian>(1).unwrap();
+        data.write_u32::<BigEndian>(1).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.write_u8(0).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.extend_from_slice(&[0; PAGE_INDEX_ENTRY_SIZE]);
+
+        let mut page = vec![1, 0];
+        page.write_u32::<BigEndian>(1234).unwrap();
+        page.extend_from_slice(&[0xAA; 16]);
+        page.extend_from_slice(&[0xBB; 16]);
+        page.resize(1024, 0);
+        data.extend_from_slice(&page);
+
+        let entries = parse_encoding(&data).unwrap();
+        assert_eq!(
+            entries[&[0xAA; 16]],
+            EncodingEntry {
+                ekey: [0xBB; 16],
+                file_size: 1234
+            }
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Reader for the local `.idx` files, which map truncated encoding keys to locations inside the
+//! `data.NNN` archives.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::errors::{Error, Result};
+
+/// Local indices only store the first 9 bytes of each encoding key.
+pub const INDEX_KEY_SIZE: usize = 9;
+
+pub type IndexKey = [u8; INDEX_KEY_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub archive: u32,
+    pub offset: u64,
+    pub size: u32,
+}
+
+pub fn index_key(ekey: &[u8]) -> IndexKey {
+    ekey[..INDEX_KEY_SIZE].try_into().unwrap()
+}
+
+/// Picks the newest index file of every bucket in `data_dir`. Files are named after their bucket
+/// followed by a version, e.g. `0a0000002f.idx`.
+pub fn find_index_files(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut newest: HashMap<String, (u32, PathBuf)> = HashMap::new();
+    for entry in fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        let Some(stem) = path
+            .extension()
+            .filter(|ext| ext.eq_ignore_ascii_case("idx"))
+            .and(path.file_stem())
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+        else {
+            continue;
+        };
+        if stem.len() != 10 {
+            continue;
+        }
+        let Ok(version) = u32::from_str_radix(&stem[2..], 16) else {
+            continue;
+        };
+
+        let bucket = stem[..2].to_owned();
+        if newest
+            .get(&bucket)
+            .is_none_or(|(current, _)| version > *current)
+        {
+            newest.insert(bucket, (version, path));
+        }
+    }
+
+    let mut files: Vec<PathBuf> = newest.into_values().map(|(_, path)| path).collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Parses a version 7 index file, adding its entries to `entries`.
+pub fn parse_index(data: &[u8], entries: &mut HashMap<IndexKey, IndexEntry>) -> Result<()> {
+    let mut cursor = Cursor::new(data);
+    let header_size = cursor.read_u32::<LittleEndian>()? as u64;
+    let _header_hash = cursor.read_u32::<LittleEndian>()?;
+    let version = cursor.read_u16::<LittleEndian>()?;
+    if version != 7 {
+        return Err(Error::Generic("unsupported CASC index version"));
+    }
+
+    let _bucket = cursor.read_u8()?;
+    let _extra_bytes = cursor.read_u8()?;
+    let size_bytes = cursor.read_u8()? as usize;
+    let offset_bytes = cursor.read_u8()? as usize;
+    let key_bytes = cursor.read_u8()? as usize;
+    let offset_bits = cursor.read_u8()? as u32;
+    if key_bytes != INDEX_KEY_SIZE || size_bytes != 4 || offset_bytes > 8 || offset_bits >= 64 {
+        return Err(Error::Generic("unsupported CASC index layout"));
+    }
+
+    // The entry block starts at the next 16 byte boundary after the header.
+    let entries_start = (8 + header_size).next_multiple_of(16);
+    cursor.set_position(entries_start);
+    let entries_size = cursor.read_u32::<LittleEndian>()? as usize;
+    let _entries_hash = cursor.read_u32::<LittleEndian>()?;
+
+    let entry_size = key_bytes + offset_bytes + size_bytes;
+    let start = cursor.position() as usize;
+    let block = data
+        .get(start..start + entries_size)
+        .ok_or(Error::Generic("truncated CASC index"))?;
+
+    for entry in block.chunks_exact(entry_size) {
+        let key = index_key(entry);
+        let packed = entry[key_bytes..key_bytes + offset_bytes]
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+        let mut size = &entry[key_bytes + offset_bytes..];
+
+        // The first index containing a key wins, matching the client.
+        entries.entry(key).or_insert(IndexEntry {
+            archive: (packed >> offset_bits) as u32,
+            offset: packed & ((1 << offset_bits) - 1),
+            size: size.read_u32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(16).unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.write_u16::<LittleEndian>(7).unwrap();
+        data.extend_from_slice(&[0, 0, 4, 5, 9, 30]);
+        data.write_u64::<LittleEndian>(0x4000_0000).unwrap();
+        data.resize(32, 0);
+
+        data.write_u32::<LittleEndian>(18).unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        // Archive 3, offset 0x100.
+        data.write_u8(0).unwrap();
+        data.write_u32::<BigEndian>((3 << 30) | 0x100).unwrap();
+        data.write_u32::<LittleEndian>(0x40).unwrap();
+
+        let mut entries = HashMap::new();
+        parse_index(&data, &mut entries).unwrap();
+        assert_eq!(
+            entries[&[1, 2, 3, 4, 5, 6, 7, 8, 9]],
+            IndexEntry {
+                archive: 3,
+                offset: 0x100,
+                size: 0x40
+            }
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Bob Jenkins' lookup3 `hashlittle2`, which CASC root files use to hash file names.
+
+/// Hash of a file name as stored in the root file: the name is uppercased with `\` separators
+/// and the two 32 bit halves of `hashlittle2` are joined, primary hash first.
+pub fn name_hash(name: &str) -> u64 {
+    let normalized = name.to_uppercase().replace('/', "\\");
+    let (primary, secondary) = hashlittle2(normalized.as_bytes(), 0, 0);
+    ((primary as u64) << 32) | secondary as u64
+}
+
+/// Returns `(c, b)` for the given seeds, matching the `pc` and `pb` outputs of the C version.
+pub fn hashlittle2(key: &[u8], pc: u32, pb: u32) -> (u32, u32) {
+    let init = 0xdeadbeefu32
+        .wrapping_add(key.len() as u32)
+        .wrapping_add(pc);
+    let (mut a, mut b, mut c) = (init, init, init.wrapping_add(pb));
+
+    if key.is_empty() {
+        return (c, b);
+    }
+
+    let mut rest = key;
+    while rest.len() > 12 {
+        a = a.wrapping_add(read_u32(&rest[0..4]));
+        b = b.wrapping_add(read_u32(&rest[4..8]));
+        c = c.wrapping_add(read_u32(&rest[8..12]));
+        mix(&mut a, &mut b, &mut c);
+        rest = &rest[12..];
+    }
+
+    let mut tail = [0u8; 12];
+    tail[..rest.len()].copy_from_slice(rest);
+    a = a.wrapping_add(read_u32(&tail[0..4]));
+    b = b.wrapping_add(read_u32(&tail[4..8]));
+    c = c.wrapping_add(read_u32(&tail[8..12]));
+    final_mix(&mut a, &mut b, &mut c);
+
+    (c, b)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *a = a.wrapping_sub(*c);
+    *a ^= c.rotate_left(4);
+    *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a);
+    *b ^= a.rotate_left(6);
+    *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b);
+    *c ^= b.rotate_left(8);
+    *b = b.wrapping_add(*a);
+    *a = a.wrapping_sub(*c);
+    *a ^= c.rotate_left(16);
+    *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a);
+    *b ^= a.rotate_left(19);
+    *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b);
+    *c ^= b.rotate_left(4);
+    *b = b.wrapping_add(*a);
+}
+
+fn final_mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *c ^= *b;
+    *c = c.wrapping_sub(b.rotate_left(14));
+    *a ^= *c;
+    *a = a.wrapping_sub(c.rotate_left(11));
+    *b ^= *a;
+    *b = b.wrapping_sub(a.rotate_left(25));
+    *c ^= *b;
+    *c = c.wrapping_sub(b.rotate_left(16));
+    *a ^= *c;
+    *a = a.wrapping_sub(c.rotate_left(4));
+    *b ^= *a;
+    *b = b.wrapping_sub(a.rotate_left(14));
+    *c ^= *b;
+    *c = c.wrapping_sub(b.rotate_left(24));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_vectors() {
+        assert_eq!(hashlittle2(b"", 0, 0), (0xdeadbeef, 0xdeadbeef));
+        assert_eq!(
+            hashlittle2(b"Four score and seven years ago", 0, 0),
+            (0x17770551, 0xce7226e6)
+        );
+        assert_eq!(name_hash("world/foo.blp"), name_hash("WORLD\\FOO.BLP"));
+    }
+}
@@ -0,0 +1,170 @@
+//! Reader for the root file, which maps file names (as Jenkins hashes) and file data IDs to
+//! content keys.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::encoding::ContentKey;
+use crate::errors::{Error, Result};
+
+pub const LOCALE_ALL: u32 = 0xFFFF_FFFF;
+pub const LOCALE_ENUS: u32 = 0x2;
+pub const LOCALE_KOKR: u32 = 0x4;
+pub const LOCALE_FRFR: u32 = 0x10;
+pub const LOCALE_DEDE: u32 = 0x20;
+pub const LOCALE_ZHCN: u32 = 0x40;
+pub const LOCALE_ESES: u32 = 0x80;
+pub const LOCALE_ZHTW: u32 = 0x100;
+pub const LOCALE_ENGB: u32 = 0x200;
+pub const LOCALE_ESMX: u32 = 0x1000;
+pub const LOCALE_RURU: u32 = 0x2000;
+
+const CONTENT_LOW_VIOLENCE: u32 = 0x80;
+const CONTENT_NO_NAME_HASH: u32 = 0x1000_0000;
+
+const MFST_MAGIC: &[u8; 4] = b"TSFM";
+const MFST_HEADER_SIZE: u32 = 24;
+
+#[derive(Debug, Default, Clone)]
+pub struct RootFile {
+    pub by_name_hash: HashMap<u64, ContentKey>,
+    pub by_file_id: HashMap<u32, ContentKey>,
+}
+
+struct Block {
+    count: usize,
+    content_flags: u32,
+    locale_flags: u32,
+}
+
+impl RootFile {
+    /// Parses a decoded root file, keeping the entries for `locale_mask`. Both the original
+    /// interleaved layout and the `MFST` layout introduced in 8.2 are supported.
+    pub fn parse(data: &[u8], locale_mask: u32) -> Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let mut version = 0;
+        let mut interleaved = true;
+
+        if data.starts_with(MFST_MAGIC) {
+            interleaved = false;
+            cursor.set_position(4);
+            let first = cursor.read_u32::<LittleEndian>()?;
+            let second = cursor.read_u32::<LittleEndian>()?;
+            // Since 10.1.7 the counts are preceded by the header size and a version.
+            if first == MFST_HEADER_SIZE && second < 10 {
+                version = second;
+                cursor.set_position(MFST_HEADER_SIZE as u64);
+            }
+        }
+
+        let mut root = Self::default();
+        while (cursor.position() as usize) < data.len() {
+            let block = read_block(&mut cursor, version)?;
+            let mut file_ids = Vec::with_capacity(block.count);
+            let mut file_id = 0u32;
+            for index in 0..block.count {
+                let delta = cursor.read_i32::<LittleEndian>()?;
+                file_id = if index == 0 {
+                    delta as u32
+                } else {
+                    file_id.wrapping_add(1).wrapping_add_signed(delta)
+                };
+                file_ids.push(file_id);
+            }
+
+            let mut ckeys = vec![[0u8; 16]; block.count];
+            let mut name_hashes = vec![None; block.count];
+            if interleaved {
+                for (ckey, hash) in ckeys.iter_mut().zip(name_hashes.iter_mut()) {
+                    cursor.read_exact(ckey)?;
+                    *hash = Some(cursor.read_u64::<LittleEndian>()?);
+                }
+            } else {
+                for ckey in ckeys.iter_mut() {
+                    cursor.read_exact(ckey)?;
+                }
+                if block.content_flags & CONTENT_NO_NAME_HASH == 0 {
+                    for hash in name_hashes.iter_mut() {
+                        *hash = Some(cursor.read_u64::<LittleEndian>()?);
+                    }
+                }
+            }
+
+            if block.locale_flags & locale_mask == 0
+                || block.content_flags & CONTENT_LOW_VIOLENCE != 0
+            {
+                continue;
+            }
+
+            for ((file_id, ckey), hash) in file_ids.into_iter().zip(ckeys).zip(name_hashes) {
+                root.by_file_id.entry(file_id).or_insert(ckey);
+                if let Some(hash) = hash {
+                    root.by_name_hash.entry(hash).or_insert(ckey);
+                }
+            }
+        }
+
+        Ok(root)
+    }
+}
+
+fn read_block(cursor: &mut Cursor<&[u8]>, version: u32) -> Result<Block> {
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    let (content_flags, locale_flags) = if version >= 2 {
+        let locale_flags = cursor.read_u32::<LittleEndian>()?;
+        let flags1 = cursor.read_u32::<LittleEndian>()?;
+        let flags2 = cursor.read_u32::<LittleEndian>()?;
+        let flags3 = cursor.read_u8()? as u32;
+        (flags1 | flags2 | (flags3 << 17), locale_flags)
+    } else {
+        let content_flags = cursor.read_u32::<LittleEndian>()?;
+        (content_flags, cursor.read_u32::<LittleEndian>()?)
+    };
+
+    if count > cursor.get_ref().len() {
+        return Err(Error::Generic("corrupt CASC root block"));
+    }
+
+    Ok(Block {
+        count,
+        content_flags,
+        locale_flags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    fn write_block(data: &mut Vec<u8>, locale: u32, entries: &[(i32, u8, u64)]) {
+        data.write_u32::<LittleEndian>(entries.len() as u32)
+            .unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.write_u32::<LittleEndian>(locale).unwrap();
+        for (delta, _, _) in entries {
+            data.write_i32::<LittleEndian>(*delta).unwrap();
+        }
+        for (_, ckey, hash) in entries {
+            data.extend_from_slice(&[*ckey; 16]);
+            data.write_u64::<LittleEndian>(*hash).unwrap();
+        }
+    }
+
+    #[test]
+    fn filters_blocks_by_locale() {
+        let mut data = Vec::new();
+        write_block(&mut data, LOCALE_DEDE, &[(10, 1, 100)]);
+        write_block(&mut data, LOCALE_ENUS, &[(10, 2, 100), (4, 3, 200)]);
+
+        let root = RootFile::parse(&data, LOCALE_ENUS).unwrap();
+        assert_eq!(root.by_name_hash[&100], [2; 16]);
+        assert_eq!(root.by_file_id[&15], [3; 16]);
+        assert_eq!(root.by_file_id.len(), 2);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::GameData;
+use crate::errors::{Error, Result};
+use crate::mpq::{
+    directory::DirectoryIndex,
+    format_file_name,
+    provenance::{EntryRole, FileProvenance},
+};
+
+/// Game data extracted to a plain directory. The tree is walked once when it's opened, so names
+/// match case-insensitively like they do inside archives.
+#[derive(Debug, Clone)]
+pub struct LooseDirectory {
+    root: PathBuf,
+    files: HashMap<String, PathBuf>,
+    directories: DirectoryIndex,
+}
+
+impl LooseDirectory {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        let mut files = HashMap::new();
+        collect_files(&root, "", &mut files)?;
+
+        Ok(Self {
+            directories: DirectoryIndex::from_files(files.keys()),
+            root,
+            files,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn disk_path(&self, name: &str) -> Option<&Path> {
+        self.files
+            .get(&format_file_name(name))
+            .map(PathBuf::as_path)
+    }
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut HashMap<String, PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!(
+            "{}{}",
+            prefix,
+            format_file_name(&entry.file_name().to_string_lossy())
+        );
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", name), files)?;
+        } else {
+            files.insert(name, entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+impl GameData for LooseDirectory {
+    fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(&format_file_name(name))
+    }
+
+    fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        let path = self
+            .disk_path(name)
+            .ok_or_else(|| Error::AssetNotFound(format_file_name(name)))?;
+        Ok(fs::read(path)?)
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    fn is_directory(&self, name: &str) -> bool {
+        self.directories.is_directory(&format_file_name(name))
+    }
+
+    fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>> {
+        self.directories
+            .read_directory(&format_file_name(name))
+            .map(|children| children.map(PathBuf::from).collect())
+    }
+
+    fn provenance(&self, name: &str) -> Vec<FileProvenance> {
+        self.disk_path(name)
+            .and_then(|path| Some((path, fs::metadata(path).ok()?.len())))
+            .map(|(path, size)| FileProvenance {
+                archive_index: 0,
+                archive_path: path.to_path_buf(),
+                file_size: size,
+                compressed_size: size,
+                flags: 0,
+                role: EntryRole::Base,
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_files_case_insensitively() {
+        let root = std::env::temp_dir().join(format!("wow_vr_loose_{}", std::process::id()));
+        fs::create_dir_all(root.join("Creature/Bear")).unwrap();
+        fs::write(root.join("Creature/Bear/Bear.M2"), b"MD20").unwrap();
+
+        let data = LooseDirectory::open(&root).unwrap();
+        assert!(data.contains("CREATURE\\BEAR\\bear.m2"));
+        assert_eq!(data.read_file("creature/bear/bear.m2").unwrap(), b"MD20");
+        assert_eq!(
+            data.read_directory("creature"),
+            Some(vec![PathBuf::from("creature/bear")])
+        );
+        assert_eq!(data.provenance("creature/bear/bear.m2")[0].file_size, 4);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
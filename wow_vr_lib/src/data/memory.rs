@@ -0,0 +1,67 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use super::GameData;
+use crate::errors::{Error, Result};
+use crate::mpq::{
+    directory::DirectoryIndex,
+    format_file_name,
+    provenance::{EntryRole, FileProvenance},
+};
+
+/// Game data held in memory, mostly so tests can feed loaders without building archives.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryData {
+    files: HashMap<String, Vec<u8>>,
+    directories: DirectoryIndex,
+}
+
+impl MemoryData {
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) {
+        let fname = format_file_name(name);
+        self.directories.insert_file(&fname);
+        self.files.insert(fname, data);
+    }
+}
+
+impl GameData for MemoryData {
+    fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(&format_file_name(name))
+    }
+
+    fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        let fname = format_file_name(name);
+        self.files
+            .get(&fname)
+            .cloned()
+            .ok_or(Error::AssetNotFound(fname))
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    fn is_directory(&self, name: &str) -> bool {
+        self.directories.is_directory(&format_file_name(name))
+    }
+
+    fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>> {
+        self.directories
+            .read_directory(&format_file_name(name))
+            .map(|children| children.map(PathBuf::from).collect())
+    }
+
+    fn provenance(&self, name: &str) -> Vec<FileProvenance> {
+        self.files
+            .get(&format_file_name(name))
+            .map(|data| FileProvenance {
+                archive_index: 0,
+                archive_path: PathBuf::new(),
+                file_size: data.len() as u64,
+                compressed_size: data.len() as u64,
+                flags: 0,
+                role: EntryRole::Base,
+            })
+            .into_iter()
+            .collect()
+    }
+}
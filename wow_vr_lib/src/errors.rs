@@ -19,9 +19,15 @@ pub enum Error {
     #[error("Asset not found {0}")]
     AssetNotFound(String),
 
+    #[error("Client data directory not found in {0}")]
+    ClientDataNotFound(String),
+
     #[error("Generic error: {0}")]
     Generic(&'static str),
 
+    #[error("Patch error: {0}")]
+    PatchError(String),
+
     #[error("Unsupported asset label: {0}")]
     UnsupportedAssetLabel(String),
 
@@ -1,3 +1,4 @@
+pub mod data;
 pub mod errors;
 pub mod m2;
 pub mod mpq;
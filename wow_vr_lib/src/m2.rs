@@ -1,3 +1,15 @@
+pub mod anim;
+pub mod animation;
+pub mod attachments;
+pub mod color_animation;
+pub mod dynamic_draw;
+pub mod lights;
+pub mod material;
+pub mod particles;
+pub mod ribbons;
+pub mod skeleton;
+pub mod texture_animation;
+
 use bevy::{
     platform::collections::HashMap,
     prelude::*,
@@ -6,19 +18,42 @@ use bevy::{
         render_resource::{Extent3d, TextureDimension, TextureFormat},
     },
 };
-use bevy_asset::{AssetLoader, AssetPath, LoadContext, RenderAssetUsages, io::Reader};
+use bevy_animation::graph::AnimationGraph;
+use bevy_asset::{
+    AssetLoader, AssetPath, LoadContext, RenderAssetUsages, embedded_asset, io::Reader,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::result::Result as StdResult;
+use std::sync::Arc;
 use wow_blp::{BlpContent, BlpContentTag, BlpImage, CompressionType, parser::load_blp_from_buf};
-use wow_m2::{
-    chunks::material::{M2BlendMode, M2RenderFlags},
-    common::{C2Vector, C3Vector},
-};
+use wow_m2::common::{C2Vector, C3Vector};
 
 use custom_debug::Debug;
 
 use crate::errors::{Error, Result};
+use crate::mpq::format_file_name;
+use anim::ArrayRef;
+use animation::{ColorTracks, M2Animation, M2Sequence, Track, TransformTracks, build_clip};
+use attachments::{M2Attachment, attach_models, spawn_attachment_points};
+use color_animation::{
+    M2Color, M2ColorAnimation, M2TextureWeight, add_color_curves, spawn_colors, update_colors,
+};
+use lights::{M2Light, M2LightState, add_light_curves, spawn_lights, update_lights};
+use material::{
+    BlendMode, M2Material, M2MaterialExtension, MAX_TEXTURES, RenderFlags, ShaderEffect,
+    base_material, sort_bias,
+};
+use particles::{
+    M2ParticleEmitter, M2ParticlePlugin, add_particle_curves, spawn_particle_emitters,
+};
+use ribbons::{M2RibbonEmitter, M2RibbonPlugin, add_ribbon_curves, spawn_ribbon_emitters};
+use skeleton::{M2Skeleton, vertex_joints};
+use texture_animation::{
+    M2TextureAnimation, M2TextureTransform, add_texture_transform_curves, spawn_texture_transforms,
+    update_texture_transforms,
+};
 
 fn c3_to_vec3(vec: C3Vector) -> Vec3 {
     Vec3 {
@@ -83,15 +118,72 @@ fn blp_to_image(blp: &mut BlpImage) -> Result<Image> {
     Ok(image)
 }
 
+/// Builds the material of a texture unit from its `(material index, texture combo index,
+/// texture count, shader ID)`. Replaceable textures the model doesn't name stay unbound.
+fn build_material(
+    model: &wow_m2::M2Model,
+    images: &HashMap<usize, Handle<Image>>,
+    (material_index, texture_combo_index, texture_count, shader_id): (u16, u16, u16, u16),
+    depth_bias: f32,
+) -> M2Material {
+    let textures: Vec<Option<Handle<Image>>> = (0..(texture_count as usize).min(MAX_TEXTURES))
+        .map(|layer| {
+            model
+                .raw_data
+                .texture_lookup_table
+                .get(texture_combo_index as usize + layer)
+                .and_then(|texture| images.get(&(*texture as usize)))
+                .cloned()
+        })
+        .collect();
+
+    let (blend_mode, flags) = material_modes(model, Some(material_index as usize));
+
+    M2Material {
+        base: base_material(blend_mode, flags, depth_bias),
+        extension: M2MaterialExtension::new(
+            ShaderEffect::from_shader_id(shader_id, texture_count),
+            &textures,
+            blend_mode,
+            flags,
+        ),
+    }
+}
+
+/// The blend mode and render flags of one of the model's materials, opaque for missing ones.
+fn material_modes(
+    model: &wow_m2::M2Model,
+    material_index: Option<usize>,
+) -> (BlendMode, RenderFlags) {
+    match material_index.and_then(|index| model.materials.get(index)) {
+        Some(material) => (
+            BlendMode::from_raw(material.blend_mode.bits()),
+            RenderFlags::from_bits_truncate(material.flags.bits()),
+        ),
+        None => (BlendMode::Opaque, RenderFlags::empty()),
+    }
+}
+
+/// Logs and drops parts of the model that couldn't be read, leaving the rest usable.
+fn read_or_warn<T>(what: &str, read: Result<Vec<T>>) -> Vec<T> {
+    read.unwrap_or_else(|err| {
+        warn!("skipping {}: {:?}", what, err);
+        Vec::new()
+    })
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum M2RelatedAsset {
     Skin(u32),
+    /// Keyframes of the sequence with this animation ID and variation.
+    Anim(u16, u16),
 }
 
 impl core::fmt::Display for M2RelatedAsset {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Skin(index) => f.write_str(&format!("{:02}.skin", index)),
+            Self::Anim(id, variation) => f.write_str(&format!("{:04}-{:02}.anim", id, variation)),
         }
     }
 }
@@ -113,7 +205,12 @@ pub enum M2AssetLabel {
     Skin(u32),
     Mesh(u32, u32),
     Texture(u32),
-    Material(u32, (u16, u16)),
+    Material(u32, u32),
+    ParticleMaterial(u32),
+    RibbonMaterial(u32),
+    InverseBindposes,
+    Animation(u16, u16),
+    AnimationGraph,
 }
 
 impl core::fmt::Display for M2AssetLabel {
@@ -124,18 +221,45 @@ impl core::fmt::Display for M2AssetLabel {
                 f.write_str(&format!("skin{}+mesh{}", skin_index, mesh_index))
             }
             Self::Texture(index) => f.write_str(&format!("texture{}", index)),
-            Self::Material(skin_index, (material_index, texture_index)) => f.write_str(&format!(
-                "skin{}+material{:x}_{:x}",
-                skin_index, material_index, texture_index
-            )),
+            Self::Material(skin_index, material_index) => {
+                f.write_str(&format!("skin{}+material{}", skin_index, material_index))
+            }
+            Self::ParticleMaterial(index) => f.write_str(&format!("particle_material{}", index)),
+            Self::RibbonMaterial(index) => f.write_str(&format!("ribbon_material{}", index)),
+            Self::InverseBindposes => f.write_str("inverse_bindposes"),
+            Self::Animation(id, variation) => {
+                f.write_str(&format!("animation{}_{}", id, variation))
+            }
+            Self::AnimationGraph => f.write_str("animation_graph"),
         }
     }
 }
 
+/// One draw of a skin: a submesh with the material of one of its texture units.
 #[derive(Debug)]
 pub struct M2Mesh {
     pub mesh: Handle<Mesh>,
-    pub material: (u16, u16),
+    pub material: Handle<M2Material>,
+    pub submesh: u32,
+    /// Draw order among the texture units of the same submesh, 0 for the base layer.
+    pub layer: u16,
+    pub priority_plane: i8,
+    /// Index in `M2Asset::texture_transforms` of each texture that moves.
+    pub texture_transforms: Vec<Option<usize>>,
+    /// Index in `M2Asset::colors` of the animated tint.
+    pub color: Option<usize>,
+    /// Index in `M2Asset::texture_weights` of the animated opacity.
+    pub texture_weight: Option<usize>,
+}
+
+impl M2Mesh {
+    pub fn has_texture_animation(&self) -> bool {
+        self.texture_transforms.iter().any(Option::is_some)
+    }
+
+    pub fn has_color_animation(&self) -> bool {
+        self.color.is_some() || self.texture_weight.is_some()
+    }
 }
 
 #[derive(Asset, TypePath, Debug)]
@@ -144,11 +268,36 @@ pub struct M2Asset {
     pub skins: Vec<Handle<SkinAsset>>,
     pub meshes: HashMap<u32, Vec<M2Mesh>>,
     pub textures: Vec<(String, Handle<Image>)>,
-    pub materials: Vec<HashMap<(u16, u16), Handle<StandardMaterial>>>,
+    /// Materials of each skin, one per texture unit.
+    pub materials: Vec<Vec<Handle<M2Material>>>,
+    /// Skin profile to show unless the caller picks one, from `M2LoaderSettings::skin_index`.
+    pub default_skin: u32,
+    pub skeleton: M2Skeleton,
+    /// One clip per sequence, in the model's sequence order.
+    pub animations: Vec<M2Animation>,
+    pub animation_graph: Option<Handle<AnimationGraph>>,
+    /// UV animations of the textures, played by the clips in `animations`.
+    pub texture_transforms: Vec<TransformTracks>,
+    /// Tints and opacities of the draws, played by the clips in `animations`.
+    pub colors: Vec<ColorTracks>,
+    pub texture_weights: Vec<Track<f32>>,
+    /// Particle emitters, animated by the clips in `animations`.
+    pub particle_emitters: Vec<Arc<M2ParticleEmitter>>,
+    /// Ribbon emitters, animated by the clips in `animations`.
+    pub ribbon_emitters: Vec<Arc<M2RibbonEmitter>>,
+    /// Point and directional lights, animated by the clips in `animations`.
+    pub lights: Vec<M2Light>,
+    /// Points other models attach to, see `M2Asset::attachment`.
+    pub attachments: Vec<M2Attachment>,
 }
 
 impl M2Asset {
-    pub async fn new(model: wow_m2::M2Model, load_context: &mut LoadContext<'_>) -> Result<Self> {
+    pub async fn new(
+        model: wow_m2::M2Model,
+        bytes: &[u8],
+        settings: &M2LoaderSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self> {
         let num_skins = if let Some(num_skins) = model.header.num_skin_profiles {
             num_skins
         } else {
@@ -159,12 +308,16 @@ impl M2Asset {
         let mut mesh_handles = HashMap::with_capacity(num_skins as usize);
 
         let mut texture_handles = Vec::with_capacity(model.textures.len());
+        let mut images = HashMap::with_capacity(model.textures.len());
         for (i, texture) in model.textures.iter().enumerate() {
-            let orig_path = texture.filename.string.to_string_lossy();
+            let orig_path = match settings.texture_overrides.get(&(i as u32)) {
+                Some(path) => path.clone(),
+                None => texture.filename.string.to_string_lossy(),
+            };
             if orig_path.len() == 0 {
                 continue;
             }
-            let blp_path = AssetPath::parse(&orig_path)
+            let blp_path = AssetPath::parse(&format_file_name(&orig_path))
                 .with_source(load_context.asset_path().source())
                 .clone_owned();
             let bytes = load_context.read_asset_bytes(blp_path).await?;
@@ -178,26 +331,274 @@ impl M2Asset {
                 blp_to_image(&mut blp)?,
             );
 
+            images.insert(i, texture_handle.clone());
             texture_handles.push((orig_path, texture_handle));
         }
 
+        let mut skeleton = M2Skeleton::from_bones(&model.bones);
+        if !skeleton.is_empty() {
+            skeleton.inverse_bindposes = Some(load_context.add_labeled_asset(
+                M2AssetLabel::InverseBindposes.to_string(),
+                skeleton.inverse_bindposes(),
+            ));
+        }
+
+        let mut bone_tracks: Vec<TransformTracks> = model
+            .bones
+            .iter()
+            .map(|bone| TransformTracks::from_bone(bone, settings.coordinates))
+            .collect();
+        let sequences: Vec<M2Sequence> = model.animations.iter().map(M2Sequence::from_m2).collect();
+
+        // Attachments are read from models of any version. External sequence keys, sequence
+        // aliases, texture transforms, colors, texture weights, particle and ribbon emitters and
+        // lights are only read from models laid out like 3.3.5, see `anim::is_supported`.
+        let header = &model.header;
+        let attachments = read_or_warn(
+            "attachments",
+            attachments::read_attachments(
+                bytes,
+                ArrayRef::from_header(&header.attachments),
+                settings.coordinates,
+            ),
+        );
+        let mut texture_transforms = Vec::new();
+        let mut texture_transform_lookup = Vec::new();
+        let mut colors = Vec::new();
+        let mut texture_weights = Vec::new();
+        let mut transparency_lookup = Vec::new();
+        let mut particle_emitters = Vec::new();
+        let mut ribbon_emitters = Vec::new();
+        let mut lights = Vec::new();
+        if anim::is_supported(bytes) {
+            let mut anim_files = vec![None; sequences.len()];
+            if sequences.iter().any(M2Sequence::is_external) {
+                let layouts = anim::read_bone_layouts(bytes)?;
+                for (index, sequence) in sequences.iter().enumerate() {
+                    if !sequence.is_external() {
+                        continue;
+                    }
+                    let anim_path = M2RelatedAsset::Anim(sequence.id, sequence.variation)
+                        .from_asset(load_context.asset_path());
+                    match load_context.read_asset_bytes(anim_path.clone()).await {
+                        Ok(anim_bytes) => {
+                            anim::merge_anim_file(
+                                &mut bone_tracks,
+                                &layouts,
+                                index,
+                                &anim_bytes,
+                                settings.coordinates,
+                            )?;
+                            anim_files[index] = Some(anim_bytes);
+                        }
+                        Err(err) => warn!("skipping animation {}: {:?}", anim_path, err),
+                    }
+                }
+            }
+
+            let sources: Vec<Option<&[u8]>> = sequences
+                .iter()
+                .zip(&anim_files)
+                .map(|(sequence, anim_bytes)| {
+                    if sequence.is_external() {
+                        anim_bytes.as_deref()
+                    } else {
+                        Some(bytes)
+                    }
+                })
+                .collect();
+            texture_transforms = anim::read_texture_transforms(bytes, &sources)?;
+            texture_transform_lookup = anim::read_texture_transform_lookup(bytes)?;
+            colors = anim::read_colors(bytes, &sources)?;
+            texture_weights = anim::read_texture_weights(bytes, &sources)?;
+            transparency_lookup = anim::read_transparency_lookup(bytes)?;
+            particle_emitters = read_or_warn(
+                "particle emitters",
+                particles::read_particle_emitters(
+                    bytes,
+                    ArrayRef::from_header(&header.particle_emitters),
+                    &sources,
+                    settings.coordinates,
+                ),
+            );
+            ribbon_emitters = read_or_warn(
+                "ribbon emitters",
+                ribbons::read_ribbon_emitters(
+                    bytes,
+                    ArrayRef::from_header(&header.ribbon_emitters),
+                    &sources,
+                    settings.coordinates,
+                ),
+            );
+            lights = read_or_warn(
+                "lights",
+                lights::read_lights(
+                    bytes,
+                    ArrayRef::from_header(&header.lights),
+                    &sources,
+                    settings.coordinates,
+                ),
+            );
+
+            let aliases = anim::read_sequence_aliases(bytes)?;
+            anim::resolve_aliases(&mut bone_tracks, &aliases);
+            anim::resolve_aliases(&mut texture_transforms, &aliases);
+            for tracks in &mut colors {
+                anim::resolve_track_aliases(&mut tracks.color, &aliases);
+                anim::resolve_track_aliases(&mut tracks.alpha, &aliases);
+            }
+            for track in &mut texture_weights {
+                anim::resolve_track_aliases(track, &aliases);
+            }
+            for emitter in &mut particle_emitters {
+                for track in emitter.tracks.tracks_mut() {
+                    anim::resolve_track_aliases(track, &aliases);
+                }
+            }
+            for ribbon in &mut ribbon_emitters {
+                let tracks = &mut ribbon.tracks;
+                anim::resolve_track_aliases(&mut tracks.color, &aliases);
+                for track in [
+                    &mut tracks.alpha,
+                    &mut tracks.height_above,
+                    &mut tracks.height_below,
+                    &mut tracks.visible,
+                ] {
+                    anim::resolve_track_aliases(track, &aliases);
+                }
+            }
+            for light in &mut lights {
+                let tracks = &mut light.tracks;
+                for track in [&mut tracks.ambient_color, &mut tracks.diffuse_color] {
+                    anim::resolve_track_aliases(track, &aliases);
+                }
+                for track in [
+                    &mut tracks.ambient_intensity,
+                    &mut tracks.diffuse_intensity,
+                    &mut tracks.attenuation_start,
+                    &mut tracks.attenuation_end,
+                    &mut tracks.visible,
+                ] {
+                    anim::resolve_track_aliases(track, &aliases);
+                }
+            }
+        } else if header.particle_emitters.count > 0
+            || header.ribbon_emitters.count > 0
+            || header.lights.count > 0
+        {
+            warn!(
+                "skipping the emitters and lights of a version {} model",
+                header.version
+            );
+        }
+
+        let particle_emitters: Vec<Arc<M2ParticleEmitter>> = particle_emitters
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut emitter)| {
+                emitter.material = load_context.add_labeled_asset(
+                    M2AssetLabel::ParticleMaterial(i as u32).to_string(),
+                    emitter.build_material(images.get(&emitter.texture).cloned()),
+                );
+                Arc::new(emitter)
+            })
+            .collect();
+        let ribbon_emitters: Vec<Arc<M2RibbonEmitter>> = ribbon_emitters
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut ribbon)| {
+                let (blend_mode, flags) = material_modes(&model, ribbon.material_index);
+                let texture = ribbon
+                    .texture
+                    .and_then(|texture| images.get(&texture))
+                    .cloned();
+                ribbon.material = load_context.add_labeled_asset(
+                    M2AssetLabel::RibbonMaterial(i as u32).to_string(),
+                    ribbon.build_material(texture, blend_mode, flags),
+                );
+                Arc::new(ribbon)
+            })
+            .collect();
+
+        let mut animations = Vec::with_capacity(sequences.len());
+        let mut animation_graph = None;
+        if !skeleton.is_empty()
+            || !texture_transforms.is_empty()
+            || !colors.is_empty()
+            || !texture_weights.is_empty()
+            || !particle_emitters.is_empty()
+            || !ribbon_emitters.is_empty()
+            || !lights.is_empty()
+        {
+            let mut graph = AnimationGraph::new();
+            for (index, sequence) in sequences.into_iter().enumerate() {
+                let mut clip = build_clip(
+                    &skeleton,
+                    &bone_tracks,
+                    index,
+                    sequence.duration,
+                    &model.global_sequences,
+                );
+                add_texture_transform_curves(
+                    &mut clip,
+                    &texture_transforms,
+                    index,
+                    &model.global_sequences,
+                );
+                add_color_curves(
+                    &mut clip,
+                    &colors,
+                    &texture_weights,
+                    index,
+                    &model.global_sequences,
+                );
+                add_particle_curves(
+                    &mut clip,
+                    &particle_emitters,
+                    index,
+                    &model.global_sequences,
+                );
+                add_ribbon_curves(&mut clip, &ribbon_emitters, index, &model.global_sequences);
+                add_light_curves(&mut clip, &lights, index, &model.global_sequences);
+                let clip = load_context.add_labeled_asset(
+                    M2AssetLabel::Animation(sequence.id, sequence.variation).to_string(),
+                    clip,
+                );
+                animations.push(M2Animation {
+                    sequence,
+                    name: sequence.name(),
+                    node: graph.add_clip(clip.clone(), 1.0, graph.root),
+                    clip,
+                });
+            }
+            animation_graph = Some(
+                load_context.add_labeled_asset(M2AssetLabel::AnimationGraph.to_string(), graph),
+            );
+        }
+
         let mut material_handles = Vec::new();
 
         if num_skins > 0 {
             let vertex_count = model.vertices.len();
             let mut vertices = Vec::with_capacity(vertex_count);
             let mut uvs = Vec::with_capacity(vertex_count);
+            let mut uvs2 = Vec::with_capacity(vertex_count);
             let mut normals = Vec::with_capacity(vertex_count);
+            let mut joint_indices = Vec::with_capacity(vertex_count);
+            let mut joint_weights = Vec::with_capacity(vertex_count);
 
             for v in &model.vertices {
                 vertices.push(c3_to_vec3(v.position));
                 uvs.push(c2_to_vec2(v.tex_coords));
+                uvs2.push(c2_to_vec2(v.tex_coords2));
                 normals.push(c3_to_vec3(v.normal));
+
+                let (indices, weights) = vertex_joints(v.bone_indices, v.bone_weights);
+                joint_indices.push(indices);
+                joint_weights.push(weights);
             }
 
             for i in 0..num_skins {
-                let mut material_map = HashMap::new();
-
                 let skin_path = M2RelatedAsset::Skin(i).from_asset(load_context.asset_path());
                 let bytes = load_context.read_asset_bytes(skin_path).await?;
                 let mut reader = Cursor::new(&bytes);
@@ -218,88 +619,100 @@ impl M2Asset {
                         )
                     }
 
-                    let mesh = Mesh::new(
-                        mesh::PrimitiveTopology::TriangleList,
-                        bevy::asset::RenderAssetUsages::default(),
-                    )
-                    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertices.clone())
-                    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs.clone())
-                    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals.clone())
-                    .with_inserted_indices(mesh::Indices::U32(triangles));
-
-                    submeshes.push(M2Mesh {
-                        mesh: load_context
-                            .add_labeled_asset(M2AssetLabel::Mesh(i, mi as u32).to_string(), mesh),
-                        material: (0, 0),
-                    });
-                }
-
-                for (_, texture_unit) in skin_asset.skin.extra_array.iter().enumerate() {
-                    let submesh = submeshes
-                        .get_mut(texture_unit.skin_section_index as usize)
-                        .unwrap();
-                    if texture_unit.material_layer > 0 {
-                        continue;
+                    let mut mesh =
+                        Mesh::new(mesh::PrimitiveTopology::TriangleList, settings.asset_usage)
+                            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertices.clone())
+                            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs.clone())
+                            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_1, uvs2.clone())
+                            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals.clone())
+                            .with_inserted_indices(mesh::Indices::U32(triangles));
+                    if !skeleton.is_empty() {
+                        mesh.insert_attribute(
+                            Mesh::ATTRIBUTE_JOINT_INDEX,
+                            mesh::VertexAttributeValues::Uint16x4(joint_indices.clone()),
+                        );
+                        mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, joint_weights.clone());
                     }
 
-                    if !material_map.contains_key(&(
-                        texture_unit.material_index,
-                        texture_unit.texture_combo_index,
-                    )) {
-                        let material_opts = &model.materials[texture_unit.material_index as usize];
-                        let texture_handle = texture_handles.get(
-                            model.raw_data.texture_lookup_table
-                                [texture_unit.texture_combo_index as usize]
-                                as usize,
-                        );
+                    submeshes.push(
+                        load_context
+                            .add_labeled_asset(M2AssetLabel::Mesh(i, mi as u32).to_string(), mesh),
+                    );
+                }
 
-                        let material = StandardMaterial {
-                            base_color_texture: if let Some(texture_handle) = texture_handle {
-                                Some(texture_handle.1.clone())
-                            } else {
-                                None
-                            },
-                            double_sided: material_opts
-                                .flags
-                                .contains(M2RenderFlags::NO_BACKFACE_CULLING),
-                            cull_mode: if material_opts
-                                .flags
-                                .contains(M2RenderFlags::NO_BACKFACE_CULLING)
-                            {
-                                None
-                            } else {
-                                Some(bevy::render::render_resource::Face::Back)
-                            },
-                            alpha_mode: match material_opts.blend_mode {
-                                M2BlendMode::ALPHA_KEY => AlphaMode::Mask(0.5),
-                                _ => AlphaMode::Opaque,
-                            },
-                            ..default()
-                        };
-                        let key = (
-                            texture_unit.material_index,
-                            texture_unit.texture_combo_index,
+                // Every texture unit is its own draw of a submesh, layered ones included, with its
+                // own material so transparent draws keep the skin's order.
+                let mut materials = Vec::with_capacity(skin_asset.skin.extra_array.len());
+                let mut draws = Vec::with_capacity(skin_asset.skin.extra_array.len());
+                for (ti, texture_unit) in skin_asset.skin.extra_array.iter().enumerate() {
+                    let Some(mesh) = submeshes.get(texture_unit.skin_section_index as usize) else {
+                        warn!(
+                            "texture unit references missing submesh {}",
+                            texture_unit.skin_section_index
                         );
-                        material_map.insert(
-                            key,
-                            load_context.add_labeled_asset(
-                                M2AssetLabel::Material(i, key).to_string(),
-                                material,
+                        continue;
+                    };
+
+                    let priority_plane = texture_unit.priority_plane as i8;
+                    let material = load_context.add_labeled_asset(
+                        M2AssetLabel::Material(i, ti as u32).to_string(),
+                        build_material(
+                            &model,
+                            &images,
+                            (
+                                texture_unit.material_index,
+                                texture_unit.texture_combo_index,
+                                texture_unit.texture_count,
+                                texture_unit.shader_id,
                             ),
-                        );
-                    }
-
-                    submesh.material = (
-                        texture_unit.material_index,
-                        texture_unit.texture_combo_index,
+                            sort_bias(priority_plane, ti),
+                        ),
                     );
+                    materials.push(material.clone());
+
+                    let first_transform = texture_unit.texture_transform_combo_index as usize;
+                    let transforms = (0..texture_unit.texture_count.min(MAX_TEXTURES as u16)
+                        as usize)
+                        .map(|layer| {
+                            texture_transform_lookup
+                                .get(first_transform + layer)
+                                .copied()
+                                .flatten()
+                                .filter(|index| {
+                                    texture_transforms
+                                        .get(*index)
+                                        .is_some_and(TransformTracks::is_animated)
+                                })
+                        })
+                        .collect();
+                    let color = usize::try_from(i32::from(texture_unit.color_index))
+                        .ok()
+                        .filter(|index| colors.get(*index).is_some_and(ColorTracks::is_animated));
+                    let texture_weight = transparency_lookup
+                        .get(texture_unit.texture_weight_combo_index as usize)
+                        .copied()
+                        .flatten()
+                        .filter(|index| {
+                            texture_weights.get(*index).is_some_and(Track::is_animated)
+                        });
+
+                    draws.push(M2Mesh {
+                        mesh: mesh.clone(),
+                        material,
+                        submesh: texture_unit.skin_section_index as u32,
+                        layer: texture_unit.material_layer,
+                        priority_plane,
+                        texture_transforms: transforms,
+                        color,
+                        texture_weight,
+                    });
                 }
 
                 skin_handles.push(
                     load_context.add_labeled_asset(M2AssetLabel::Skin(i).to_string(), skin_asset),
                 );
-                mesh_handles.insert(i, submeshes);
-                material_handles.push(material_map);
+                mesh_handles.insert(i, draws);
+                material_handles.push(materials);
             }
         }
 
@@ -309,14 +722,167 @@ impl M2Asset {
             meshes: mesh_handles,
             textures: texture_handles,
             materials: material_handles,
+            default_skin: settings
+                .skin_index
+                .filter(|index| *index < num_skins)
+                .unwrap_or(0),
+            skeleton,
+            animations,
+            animation_graph,
+            texture_transforms,
+            colors,
+            texture_weights,
+            particle_emitters,
+            ribbon_emitters,
+            lights,
+            attachments,
         })
     }
+
+    /// Spawns the draws of `skin` below `root`, with the joints, texture transforms and colors
+    /// they follow, the particle and ribbon emitters, the lights and the attachment points, and
+    /// returns the draw entities. Animations play once `root` gets an `AnimationPlayer` and the
+    /// model's `AnimationGraphHandle`. Draws with animated materials get their own copy of the
+    /// material, since it follows the animation of this instance. Other models attach to it
+    /// with `M2AttachTo`.
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        materials: &mut Assets<M2Material>,
+        root: Entity,
+        skin: u32,
+    ) -> Vec<Entity> {
+        let Some(draws) = self.meshes.get(&skin) else {
+            return Vec::new();
+        };
+
+        let joints = self.skeleton.spawn(commands, root);
+        let skinned_mesh = self.skeleton.skinned_mesh(&joints);
+        let texture_transforms =
+            spawn_texture_transforms(commands, root, self.texture_transforms.len());
+        let (colors, texture_weights) = spawn_colors(
+            commands,
+            root,
+            self.colors.len(),
+            self.texture_weights.len(),
+        );
+        spawn_particle_emitters(
+            commands,
+            root,
+            &self.skeleton,
+            &joints,
+            &self.particle_emitters,
+        );
+        spawn_ribbon_emitters(
+            commands,
+            root,
+            &self.skeleton,
+            &joints,
+            &self.ribbon_emitters,
+        );
+        spawn_lights(commands, root, &self.skeleton, &joints, &self.lights);
+        spawn_attachment_points(commands, root, &self.skeleton, &joints, &self.attachments);
+
+        draws
+            .iter()
+            .map(|draw| {
+                let material = if draw.has_texture_animation() || draw.has_color_animation() {
+                    materials
+                        .get(&draw.material)
+                        .cloned()
+                        .map_or(draw.material.clone(), |material| materials.add(material))
+                } else {
+                    draw.material.clone()
+                };
+
+                let mut entity = commands.spawn((
+                    Mesh3d(draw.mesh.clone()),
+                    MeshMaterial3d(material),
+                    ChildOf(root),
+                ));
+                if let Some(skinned_mesh) = &skinned_mesh {
+                    entity.insert(skinned_mesh.clone());
+                }
+                if draw.has_texture_animation() {
+                    entity.insert(M2TextureAnimation {
+                        transforms: draw
+                            .texture_transforms
+                            .iter()
+                            .map(|index| index.map(|index| texture_transforms[index]))
+                            .collect(),
+                    });
+                }
+                if draw.has_color_animation() {
+                    entity.insert(M2ColorAnimation {
+                        color: draw.color.map(|index| colors[index]),
+                        texture_weight: draw.texture_weight.map(|index| texture_weights[index]),
+                    });
+                }
+                entity.id()
+            })
+            .collect()
+    }
+
+    /// The attachment point with this ID, such as 1 for the right hand, see
+    /// `attachments::attachment_id` for the IDs by name.
+    pub fn attachment(&self, id: u32) -> Option<&M2Attachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.id == id)
+    }
+
+    /// Finds an animation by its clip name, such as `Stand`, `Walk` or `Stand_1`.
+    pub fn animation(&self, name: &str) -> Option<&M2Animation> {
+        self.animations
+            .iter()
+            .find(|animation| animation.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Axis convention of the loaded vertices.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    /// Converted to Bevy's Y-up, the client's Z-up forward axis becomes -Z.
+    #[default]
+    YUp,
+    /// Kept as stored in the file.
+    ZUp,
 }
 
+impl CoordinateSystem {
+    /// Converts a position or a direction stored in the file.
+    pub fn position(self, v: Vec3) -> Vec3 {
+        match self {
+            Self::YUp => Vec3::new(v.x, v.z, -v.y),
+            Self::ZUp => v,
+        }
+    }
+
+    pub fn rotation(self, q: Quat) -> Quat {
+        match self {
+            Self::YUp => Quat::from_xyzw(q.x, q.z, -q.y, q.w),
+            Self::ZUp => q,
+        }
+    }
+
+    pub fn scale(self, v: Vec3) -> Vec3 {
+        match self {
+            Self::YUp => Vec3::new(v.x, v.z, v.y),
+            Self::ZUp => v,
+        }
+    }
+}
+
+/// Per-model settings, usually pinned in a `.meta` file next to the model or in the reader's
+/// sidecar directory. Missing fields take their default.
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct M2LoaderSettings {
     pub asset_usage: RenderAssetUsages,
-    pub skin_index: usize,
+    pub skin_index: Option<u32>,
+    /// Replaces textures by their index in the model with another file from the same source.
+    pub texture_overrides: BTreeMap<u32, String>,
+    pub coordinates: CoordinateSystem,
 }
 
 #[derive(Clone)]
@@ -324,13 +890,13 @@ pub struct M2Loader {}
 
 impl AssetLoader for M2Loader {
     type Asset = M2Asset;
-    type Settings = ();
+    type Settings = M2LoaderSettings;
     type Error = Error;
 
     async fn load(
         &self,
         reader: &mut dyn Reader,
-        _settings: &Self::Settings,
+        settings: &Self::Settings,
         load_context: &mut LoadContext<'_>,
     ) -> StdResult<Self::Asset, Self::Error> {
         let mut bytes = Vec::new();
@@ -338,17 +904,25 @@ impl AssetLoader for M2Loader {
         let mut reader = Cursor::new(&bytes);
         let mut model = wow_m2::M2Model::parse(&mut reader)?;
 
-        for v in &mut model.vertices {
-            let y = v.position.z;
-            v.position.z = v.position.y * -1.;
-            v.position.y = y;
+        if settings.coordinates == CoordinateSystem::YUp {
+            for v in &mut model.vertices {
+                let y = v.position.z;
+                v.position.z = v.position.y * -1.;
+                v.position.y = y;
+
+                let ny = v.normal.z;
+                v.normal.z = v.normal.y * -1.;
+                v.normal.y = ny;
+            }
 
-            let ny = v.normal.z;
-            v.normal.z = v.normal.y * -1.;
-            v.normal.y = ny;
+            for bone in &mut model.bones {
+                let y = bone.pivot.z;
+                bone.pivot.z = bone.pivot.y * -1.;
+                bone.pivot.y = y;
+            }
         }
 
-        Ok(M2Asset::new(model, load_context).await?)
+        Ok(M2Asset::new(model, &bytes, settings, load_context).await?)
     }
 }
 
@@ -391,10 +965,30 @@ pub struct M2Plugin {}
 
 impl Plugin for M2Plugin {
     fn build(&self, app: &mut App) {
-        app.init_asset::<SkinAsset>()
-            .preregister_asset_loader::<SkinLoader>(&["skin"])
-            .init_asset::<M2Asset>()
-            .preregister_asset_loader::<M2Loader>(&["m2"]);
+        embedded_asset!(app, "m2/material.wgsl");
+
+        app.add_plugins((
+            MaterialPlugin::<M2Material>::default(),
+            M2ParticlePlugin,
+            M2RibbonPlugin,
+        ))
+        .register_type::<M2TextureTransform>()
+        .register_type::<M2Color>()
+        .register_type::<M2TextureWeight>()
+        .register_type::<M2LightState>()
+        .add_systems(
+            PostUpdate,
+            (update_texture_transforms, update_colors, update_lights)
+                .after(bevy_animation::Animation),
+        )
+        .add_systems(
+            PostUpdate,
+            attach_models.before(TransformSystem::TransformPropagate),
+        )
+        .init_asset::<SkinAsset>()
+        .preregister_asset_loader::<SkinLoader>(&["skin"])
+        .init_asset::<M2Asset>()
+        .preregister_asset_loader::<M2Loader>(&["m2"]);
     }
 
     fn finish(&self, app: &mut App) {
@@ -416,26 +1010,35 @@ mod tests {
         Cursor::new(bytes)
     }
 
+    #[test]
+    fn related_asset_paths() {
+        let model = "character/human/male/humanmale.m2";
+        assert_eq!(
+            M2RelatedAsset::Skin(1).from_asset(model).to_string(),
+            "character/human/male/humanmale01.skin"
+        );
+        assert_eq!(
+            M2RelatedAsset::Anim(60, 2).from_asset(model).to_string(),
+            "character/human/male/humanmale0060-02.anim"
+        );
+    }
+
+    #[test]
+    fn converted_rotations_match_converted_positions() {
+        let rotation = Quat::from_euler(EulerRot::XYZ, 0.3, -1.1, 0.7);
+        let v = Vec3::new(1., 2., 3.);
+        let coordinates = CoordinateSystem::YUp;
+
+        let rotated_then_converted = coordinates.position(rotation * v);
+        let converted_then_rotated = coordinates.rotation(rotation) * coordinates.position(v);
+        assert!(rotated_then_converted.abs_diff_eq(converted_then_rotated, 1e-5));
+    }
+
     #[test]
     fn load_m2_with_skins() {
-        let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("..")
-            .join("Data");
-
-        let mut mpq_col = MpqCollection::load(vec![
-            base_path.join("common.MPQ"),
-            base_path.join("common-2.MPQ"),
-            base_path.join("expansion.MPQ"),
-            base_path.join("lichking.MPQ"),
-            base_path.join("patch.MPQ"),
-            base_path.join("patch-2.MPQ"),
-            base_path.join("patch-3.MPQ"),
-            base_path.join("enUS/locale-enUS.MPQ"),
-            base_path.join("enUS/patch-enUS.MPQ"),
-            base_path.join("enUS/patch-enUS-2.MPQ"),
-            base_path.join("enUS/patch-enUS-3.MPQ"),
-        ])
-        .unwrap();
+        let mut mpq_col =
+            MpqCollection::load_client(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(".."))
+                .unwrap();
 
         // let fname = "world/khazmodan/uldaman/passivedoodads/statues/uldamanmountaingiantstatue.m2";
         let fname = "world/lordaeron/plagueland/passivedoodads/forsakenbanner/forsakenbanner01.m2";
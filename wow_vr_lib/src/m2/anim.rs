@@ -0,0 +1,617 @@
+use std::io::{Cursor, Seek, SeekFrom};
+
+use bevy::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+use wow_m2::common::M2Array;
+
+use super::{
+    CoordinateSystem,
+    animation::{ColorTracks, Interpolation, Track, TrackKeys, TransformTracks, comp_quat_to_quat},
+};
+use crate::errors::{Error, Result};
+
+/// Model versions laid out like 3.3.5, the only ones read raw here.
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u32> = 264..=272;
+
+/// Set on sequences whose keyframes are stored in the `.m2` itself, the others have theirs in a
+/// `<model><id>-<variation>.anim` file next to it.
+pub const SEQUENCE_INLINE: u32 = 0x20;
+/// Set on sequences that reuse the keyframes of another variation.
+pub const SEQUENCE_ALIAS: u32 = 0x40;
+
+// Offsets and sizes of the 3.3.5 layout.
+const SEQUENCES_HEADER: u64 = 0x1c;
+const BONES_HEADER: u64 = 0x2c;
+const COLORS_HEADER: u64 = 0x48;
+const TEXTURE_WEIGHTS_HEADER: u64 = 0x58;
+const TEXTURE_TRANSFORMS_HEADER: u64 = 0x60;
+const TRANSPARENCY_LOOKUP_HEADER: u64 = 0x90;
+const TEXTURE_TRANSFORM_LOOKUP_HEADER: u64 = 0x98;
+const SEQUENCE_SIZE: u64 = 64;
+const SEQUENCE_FLAGS: u64 = 12;
+const SEQUENCE_ALIAS_NEXT: u64 = 62;
+const BONE_SIZE: u64 = 88;
+const BONE_TRACKS: u64 = 16;
+pub(super) const TRACK_SIZE: u64 = 20;
+/// Tracks of models older than 3.0 also hold the ranges of their keys.
+const LEGACY_TRACK_SIZE: u64 = 28;
+const TEXTURE_TRANSFORM_SIZE: u64 = TRACK_SIZE * 3;
+const COLOR_SIZE: u64 = TRACK_SIZE * 2;
+
+/// An `M2Array` header, the element count and where they start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArrayRef {
+    pub count: u32,
+    pub offset: u32,
+}
+
+impl ArrayRef {
+    /// An array of the header `wow_m2` parsed.
+    pub fn from_header<T>(array: &M2Array<T>) -> Self {
+        Self {
+            count: array.count,
+            offset: array.offset,
+        }
+    }
+
+    fn read(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        Ok(Self {
+            count: reader.read_u32::<LittleEndian>()?,
+            offset: reader.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    pub(super) fn read_at(reader: &mut Cursor<&[u8]>, position: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(position))?;
+        Self::read(reader)
+    }
+
+    /// Reads `count` elements, refusing counts that can't fit in the data.
+    pub(super) fn read_elements<T>(
+        &self,
+        data: &[u8],
+        count: u32,
+        element_size: usize,
+        read: impl Fn(&mut Cursor<&[u8]>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let end = self.offset as usize + count as usize * element_size;
+        if end > data.len() {
+            return Err(Error::Generic("M2 array out of bounds"));
+        }
+
+        let mut reader = Cursor::new(data);
+        reader.set_position(self.offset as u64);
+        (0..count).map(|_| read(&mut reader)).collect()
+    }
+}
+
+/// Where the keys of a track are for each sequence.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackLayout {
+    pub interpolation: Interpolation,
+    pub global_sequence: Option<usize>,
+    pub timestamps: Vec<ArrayRef>,
+    pub values: Vec<ArrayRef>,
+}
+
+impl TrackLayout {
+    pub(super) fn read(reader: &mut Cursor<&[u8]>, position: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(position))?;
+        let interpolation = Interpolation::from(reader.read_u16::<LittleEndian>()?);
+        let global_sequence = usize::try_from(reader.read_i16::<LittleEndian>()?).ok();
+        let timestamps = ArrayRef::read(reader)?;
+        let values = ArrayRef::read(reader)?;
+
+        let data = *reader.get_ref();
+        Ok(Self {
+            interpolation,
+            global_sequence,
+            timestamps: timestamps.read_elements(data, timestamps.count, 8, ArrayRef::read)?,
+            values: values.read_elements(data, values.count, 8, ArrayRef::read)?,
+        })
+    }
+
+    /// Reads the keys of every sequence. `sources` holds, per sequence, the file its keys are
+    /// in: the model itself for inline sequences, the `.anim` file for external ones, `None`
+    /// when that file couldn't be loaded. Global sequence keys are always in the model.
+    pub(super) fn read_track<T: Clone>(
+        &self,
+        m2: &[u8],
+        sources: &[Option<&[u8]>],
+        value_size: usize,
+        read_value: impl Fn(&mut Cursor<&[u8]>) -> Result<T>,
+    ) -> Result<Track<T>> {
+        let mut sequences = Vec::with_capacity(self.timestamps.len());
+        for sequence in 0..self.timestamps.len() {
+            let source = match self.global_sequence {
+                Some(_) => Some(m2),
+                None => sources.get(sequence).copied().flatten(),
+            };
+            let keys = match source {
+                Some(data) => self.read_keys(sequence, data, value_size, &read_value)?,
+                None => None,
+            };
+            sequences.push(keys.unwrap_or_default());
+        }
+
+        Ok(Track {
+            interpolation: self.interpolation,
+            global_sequence: self.global_sequence,
+            sequences,
+        })
+    }
+
+    /// Reads the keys of `sequence` from the file holding them.
+    fn read_keys<T: Clone>(
+        &self,
+        sequence: usize,
+        anim: &[u8],
+        value_size: usize,
+        read_value: impl Fn(&mut Cursor<&[u8]>) -> Result<T>,
+    ) -> Result<Option<TrackKeys<T>>> {
+        let (Some(timestamps), Some(values)) =
+            (self.timestamps.get(sequence), self.values.get(sequence))
+        else {
+            return Ok(None);
+        };
+
+        // Spline values come with their two tangents, counted as a single element.
+        let value_count = if self.interpolation.is_spline() && values.count == timestamps.count {
+            values.count * 3
+        } else {
+            values.count
+        };
+
+        let timestamps = timestamps.read_elements(anim, timestamps.count, 4, |reader| {
+            Ok(reader.read_u32::<LittleEndian>()?)
+        })?;
+        let values = values.read_elements(anim, value_count, value_size, read_value)?;
+        Ok(Some(TrackKeys::new(self.interpolation, timestamps, values)))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransformLayout {
+    pub translation: TrackLayout,
+    pub rotation: TrackLayout,
+    pub scale: TrackLayout,
+}
+
+fn version(m2: &[u8]) -> Option<u32> {
+    m2.get(4..8)
+        .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+}
+
+/// Whether the raw readers below understand the layout of `m2`.
+pub fn is_supported(m2: &[u8]) -> bool {
+    version(m2).is_some_and(|version| SUPPORTED_VERSIONS.contains(&version))
+}
+
+/// Size of the track headers of `m2`.
+pub(super) fn track_size(m2: &[u8]) -> u64 {
+    if version(m2).is_some_and(|version| version < *SUPPORTED_VERSIONS.start()) {
+        LEGACY_TRACK_SIZE
+    } else {
+        TRACK_SIZE
+    }
+}
+
+/// Reads where each bone keeps its keys, straight from the `.m2` bytes, since the per-sequence
+/// arrays of external sequences point into the `.anim` files rather than the model.
+pub fn read_bone_layouts(m2: &[u8]) -> Result<Vec<TransformLayout>> {
+    let mut reader = Cursor::new(m2);
+    let bones = ArrayRef::read_at(&mut reader, BONES_HEADER)?;
+
+    (0..bones.count as u64)
+        .map(|i| {
+            let tracks = bones.offset as u64 + i * BONE_SIZE + BONE_TRACKS;
+            Ok(TransformLayout {
+                translation: TrackLayout::read(&mut reader, tracks)?,
+                rotation: TrackLayout::read(&mut reader, tracks + TRACK_SIZE)?,
+                scale: TrackLayout::read(&mut reader, tracks + TRACK_SIZE * 2)?,
+            })
+        })
+        .collect()
+}
+
+/// Reads the texture transforms, the UV animations that texture units refer to through the
+/// texture transform lookup. Keys come from `sources` as in `TrackLayout::read_track`.
+pub fn read_texture_transforms(
+    m2: &[u8],
+    sources: &[Option<&[u8]>],
+) -> Result<Vec<TransformTracks>> {
+    let mut reader = Cursor::new(m2);
+    let transforms = ArrayRef::read_at(&mut reader, TEXTURE_TRANSFORMS_HEADER)?;
+
+    (0..transforms.count as u64)
+        .map(|i| {
+            let tracks = transforms.offset as u64 + i * TEXTURE_TRANSFORM_SIZE;
+            let layout = TransformLayout {
+                translation: TrackLayout::read(&mut reader, tracks)?,
+                rotation: TrackLayout::read(&mut reader, tracks + TRACK_SIZE)?,
+                scale: TrackLayout::read(&mut reader, tracks + TRACK_SIZE * 2)?,
+            };
+            Ok(TransformTracks {
+                translation: layout.translation.read_track(m2, sources, 12, read_vec3)?,
+                rotation: layout.rotation.read_track(m2, sources, 16, |reader| {
+                    let mut values = [0.; 4];
+                    reader.read_f32_into::<LittleEndian>(&mut values)?;
+                    Ok(Quat::from_array(values).normalize())
+                })?,
+                scale: layout.scale.read_track(m2, sources, 12, read_vec3)?,
+            })
+        })
+        .collect()
+}
+
+/// Reads the colors texture units tint their draw with, through `color_index`.
+pub fn read_colors(m2: &[u8], sources: &[Option<&[u8]>]) -> Result<Vec<ColorTracks>> {
+    let mut reader = Cursor::new(m2);
+    let colors = ArrayRef::read_at(&mut reader, COLORS_HEADER)?;
+
+    (0..colors.count as u64)
+        .map(|i| {
+            let tracks = colors.offset as u64 + i * COLOR_SIZE;
+            let color = TrackLayout::read(&mut reader, tracks)?;
+            let alpha = TrackLayout::read(&mut reader, tracks + TRACK_SIZE)?;
+            Ok(ColorTracks {
+                color: color.read_track(m2, sources, 12, read_vec3)?,
+                alpha: alpha.read_track(m2, sources, 2, read_fixed16)?,
+            })
+        })
+        .collect()
+}
+
+/// Reads the texture weights, the opacity texture units refer to through the transparency
+/// lookup.
+pub fn read_texture_weights(m2: &[u8], sources: &[Option<&[u8]>]) -> Result<Vec<Track<f32>>> {
+    let mut reader = Cursor::new(m2);
+    let weights = ArrayRef::read_at(&mut reader, TEXTURE_WEIGHTS_HEADER)?;
+
+    (0..weights.count as u64)
+        .map(|i| {
+            TrackLayout::read(&mut reader, weights.offset as u64 + i * TRACK_SIZE)?.read_track(
+                m2,
+                sources,
+                2,
+                read_fixed16,
+            )
+        })
+        .collect()
+}
+
+/// The texture transform lookup, `None` for entries of textures that don't move.
+pub fn read_texture_transform_lookup(m2: &[u8]) -> Result<Vec<Option<usize>>> {
+    read_lookup(m2, TEXTURE_TRANSFORM_LOOKUP_HEADER)
+}
+
+/// The transparency lookup, from texture units to texture weights.
+pub fn read_transparency_lookup(m2: &[u8]) -> Result<Vec<Option<usize>>> {
+    read_lookup(m2, TRANSPARENCY_LOOKUP_HEADER)
+}
+
+fn read_lookup(m2: &[u8], header: u64) -> Result<Vec<Option<usize>>> {
+    let mut reader = Cursor::new(m2);
+    let lookup = ArrayRef::read_at(&mut reader, header)?;
+    lookup.read_elements(m2, lookup.count, 2, |reader| {
+        Ok(usize::try_from(reader.read_i16::<LittleEndian>()?).ok())
+    })
+}
+
+/// The sequence each alias plays instead, `None` for sequences with keyframes of their own.
+pub fn read_sequence_aliases(m2: &[u8]) -> Result<Vec<Option<usize>>> {
+    let mut reader = Cursor::new(m2);
+    let sequences = ArrayRef::read_at(&mut reader, SEQUENCES_HEADER)?;
+
+    (0..sequences.count as u64)
+        .map(|i| {
+            let sequence = sequences.offset as u64 + i * SEQUENCE_SIZE;
+            reader.seek(SeekFrom::Start(sequence + SEQUENCE_FLAGS))?;
+            let flags = reader.read_u32::<LittleEndian>()?;
+            reader.seek(SeekFrom::Start(sequence + SEQUENCE_ALIAS_NEXT))?;
+            let alias_next = reader.read_u16::<LittleEndian>()? as usize;
+            Ok(
+                (flags & SEQUENCE_ALIAS != 0 && alias_next < sequences.count as usize)
+                    .then_some(alias_next),
+            )
+        })
+        .collect()
+}
+
+/// Replaces the keys of `sequence` with the ones stored in its `.anim` file.
+pub fn merge_anim_file(
+    bones: &mut [TransformTracks],
+    layouts: &[TransformLayout],
+    sequence: usize,
+    anim: &[u8],
+    coordinates: CoordinateSystem,
+) -> Result<()> {
+    let read_quat = |reader: &mut Cursor<&[u8]>| -> Result<Quat> {
+        let mut values = [0; 4];
+        reader.read_i16_into::<LittleEndian>(&mut values)?;
+        Ok(comp_quat_to_quat(values))
+    };
+
+    for (tracks, layout) in bones.iter_mut().zip(layouts) {
+        if let Some(keys) = layout
+            .translation
+            .read_keys(sequence, anim, 12, read_vec3)?
+        {
+            set_keys(
+                &mut tracks.translation,
+                sequence,
+                keys.map(|v| coordinates.position(*v)),
+            );
+        }
+        if let Some(keys) = layout.rotation.read_keys(sequence, anim, 8, read_quat)? {
+            set_keys(
+                &mut tracks.rotation,
+                sequence,
+                keys.map(|q| coordinates.rotation(*q)),
+            );
+        }
+        if let Some(keys) = layout.scale.read_keys(sequence, anim, 12, read_vec3)? {
+            set_keys(
+                &mut tracks.scale,
+                sequence,
+                keys.map(|v| coordinates.scale(*v)),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives every alias the keys of the sequence it points to, following chains of aliases.
+pub fn resolve_aliases(transforms: &mut [TransformTracks], aliases: &[Option<usize>]) {
+    for tracks in transforms.iter_mut() {
+        resolve_track_aliases(&mut tracks.translation, aliases);
+        resolve_track_aliases(&mut tracks.rotation, aliases);
+        resolve_track_aliases(&mut tracks.scale, aliases);
+    }
+}
+
+/// `resolve_aliases` for a single track.
+pub fn resolve_track_aliases<T: Clone>(track: &mut Track<T>, aliases: &[Option<usize>]) {
+    for (sequence, alias) in aliases.iter().enumerate() {
+        let mut target = *alias;
+        let mut steps = 0;
+        while let Some(next) = target.and_then(|target| aliases.get(target).copied().flatten()) {
+            if steps > aliases.len() {
+                target = None;
+                break;
+            }
+            target = Some(next);
+            steps += 1;
+        }
+
+        if let Some(target) = target.filter(|target| *target != sequence) {
+            copy_keys(track, target, sequence);
+        }
+    }
+}
+
+pub(super) fn read_vec3(reader: &mut Cursor<&[u8]>) -> Result<Vec3> {
+    Ok(Vec3::new(
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+    ))
+}
+
+/// A signed 16 bit fixed point value, 32767 standing for 1.
+pub(super) fn read_fixed16(reader: &mut Cursor<&[u8]>) -> Result<f32> {
+    Ok(reader.read_i16::<LittleEndian>()? as f32 / 32767.)
+}
+
+fn set_keys<T>(track: &mut Track<T>, sequence: usize, keys: TrackKeys<T>) {
+    if track.sequences.len() <= sequence {
+        track
+            .sequences
+            .resize_with(sequence + 1, TrackKeys::default);
+    }
+    track.sequences[sequence] = keys;
+}
+
+fn copy_keys<T: Clone>(track: &mut Track<T>, from: usize, to: usize) {
+    if let Some(keys) = track.sequences.get(from).cloned() {
+        set_keys(track, to, keys);
+    }
+}
+
+/// Copies `bytes` into `m2` at `position`, to lay out test models.
+#[cfg(test)]
+pub(super) fn write_bytes(m2: &mut [u8], position: usize, bytes: &[u8]) {
+    m2[position..position + bytes.len()].copy_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    fn write_at(m2: &mut [u8], position: usize, values: &[u32]) {
+        for (i, value) in values.iter().enumerate() {
+            write_bytes(m2, position + i * 4, &value.to_le_bytes());
+        }
+    }
+
+    /// A model with one bone whose translation has inline keys for sequence 0 and external
+    /// ones for sequence 1, plus an alias of sequence 1.
+    fn model_bytes() -> Vec<u8> {
+        let mut m2 = vec![0u8; 0x100];
+        m2[..4].copy_from_slice(b"MD20");
+        write_at(&mut m2, 4, &[264]);
+
+        // Sequences at 0x100, the third aliases the second.
+        write_at(&mut m2, SEQUENCES_HEADER as usize, &[3, 0x100]);
+        m2.resize(0x100 + 3 * SEQUENCE_SIZE as usize, 0);
+        for (i, flags) in [SEQUENCE_INLINE, 0, SEQUENCE_ALIAS].iter().enumerate() {
+            let sequence = 0x100 + i * SEQUENCE_SIZE as usize;
+            write_at(&mut m2, sequence + SEQUENCE_FLAGS as usize, &[*flags]);
+        }
+        let alias_next = 0x100 + 2 * SEQUENCE_SIZE as usize + SEQUENCE_ALIAS_NEXT as usize;
+        m2[alias_next..alias_next + 2].copy_from_slice(&1u16.to_le_bytes());
+
+        // One bone at 0x200, per-sequence arrays at 0x300 and 0x340.
+        write_at(&mut m2, BONES_HEADER as usize, &[1, 0x200]);
+        m2.resize(0x400, 0);
+        let translation = 0x200 + BONE_TRACKS as usize;
+        m2[translation..translation + 4].copy_from_slice(&[1, 0, 0xff, 0xff]);
+        write_at(&mut m2, translation + 4, &[2, 0x300, 2, 0x340]);
+        write_at(&mut m2, 0x300, &[1, 0x380, 2, 0x18]);
+        write_at(&mut m2, 0x340, &[1, 0x390, 2, 0x00]);
+
+        m2
+    }
+
+    fn anim_bytes() -> Vec<u8> {
+        let mut anim = Vec::new();
+        for value in [1., 2., 3., 4., 5., 6.] {
+            anim.write_f32::<LittleEndian>(value).unwrap();
+        }
+        for timestamp in [0, 500] {
+            anim.write_u32::<LittleEndian>(timestamp).unwrap();
+        }
+        anim
+    }
+
+    #[test]
+    fn reads_track_layouts() {
+        assert!(is_supported(&model_bytes()));
+        assert!(!is_supported(b"MD20\x00\x01\x00\x00"));
+
+        let layouts = read_bone_layouts(&model_bytes()).unwrap();
+        assert_eq!(layouts.len(), 1);
+
+        let translation = &layouts[0].translation;
+        assert_eq!(translation.interpolation, Interpolation::Linear);
+        assert_eq!(
+            translation.timestamps,
+            vec![
+                ArrayRef {
+                    count: 1,
+                    offset: 0x380
+                },
+                ArrayRef {
+                    count: 2,
+                    offset: 0x18
+                }
+            ]
+        );
+        assert!(layouts[0].rotation.timestamps.is_empty());
+    }
+
+    #[test]
+    fn merges_external_keys_and_aliases() {
+        let m2 = model_bytes();
+        let layouts = read_bone_layouts(&m2).unwrap();
+        let mut bones = vec![TransformTracks::default()];
+
+        merge_anim_file(
+            &mut bones,
+            &layouts,
+            1,
+            &anim_bytes(),
+            CoordinateSystem::ZUp,
+        )
+        .unwrap();
+        let keys = &bones[0].translation.sequences[1];
+        assert_eq!(keys.timestamps, vec![0, 500]);
+        assert_eq!(
+            keys.values,
+            vec![Vec3::new(1., 2., 3.), Vec3::new(4., 5., 6.)]
+        );
+        assert!(bones[0].translation.sequences[0].is_empty());
+
+        let aliases = read_sequence_aliases(&m2).unwrap();
+        assert_eq!(aliases, vec![None, None, Some(1)]);
+        resolve_aliases(&mut bones, &aliases);
+        assert_eq!(
+            bones[0].translation.sequences[2],
+            bones[0].translation.sequences[1]
+        );
+    }
+
+    #[test]
+    fn reads_texture_transforms_from_both_sources() {
+        let mut m2 = model_bytes();
+        m2.resize(0x500, 0);
+
+        // One texture transform at 0x400 whose translation has an inline key for sequence 0 and
+        // an external one for sequence 1.
+        write_at(&mut m2, TEXTURE_TRANSFORMS_HEADER as usize, &[1, 0x400]);
+        m2[0x400..0x404].copy_from_slice(&[1, 0, 0xff, 0xff]);
+        write_at(&mut m2, 0x404, &[2, 0x440, 2, 0x450]);
+        write_at(&mut m2, 0x440, &[1, 0x470, 1, 0x18]);
+        write_at(&mut m2, 0x450, &[1, 0x474, 1, 0x00]);
+        write_at(&mut m2, 0x470, &[0]);
+        for (i, value) in [0.5f32, 0.25, 0.].iter().enumerate() {
+            let start = 0x474 + i * 4;
+            m2[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        // Rotations and scales bound to a global sequence without any keys.
+        let global = 0x400 + TRACK_SIZE as usize;
+        m2[global..global + 4].copy_from_slice(&[0, 0, 0, 0]);
+
+        write_at(
+            &mut m2,
+            TEXTURE_TRANSFORM_LOOKUP_HEADER as usize,
+            &[2, 0x480],
+        );
+        write_at(&mut m2, 0x480, &[0xffff_0000]);
+
+        let anim = anim_bytes();
+        let transforms =
+            read_texture_transforms(&m2, &[Some(m2.as_slice()), Some(anim.as_slice()), None])
+                .unwrap();
+        assert_eq!(transforms.len(), 1);
+        let translation = &transforms[0].translation;
+        assert_eq!(translation.global_sequence, None);
+        assert_eq!(
+            translation.sequences[0].values,
+            vec![Vec3::new(0.5, 0.25, 0.)]
+        );
+        assert_eq!(translation.sequences[1].values, vec![Vec3::new(1., 2., 3.)]);
+        assert!(!transforms[0].rotation.is_animated());
+        assert_eq!(transforms[0].rotation.global_sequence, Some(0));
+
+        let missing = read_texture_transforms(&m2, &[Some(m2.as_slice()), None, None]).unwrap();
+        assert!(missing[0].translation.sequences[1].is_empty());
+
+        assert_eq!(
+            read_texture_transform_lookup(&m2).unwrap(),
+            vec![Some(0), None]
+        );
+    }
+
+    #[test]
+    fn reads_fixed_point_weights() {
+        let mut m2 = model_bytes();
+        m2.resize(0x500, 0);
+
+        // One texture weight with a single inline key for sequence 0.
+        write_at(&mut m2, TEXTURE_WEIGHTS_HEADER as usize, &[1, 0x400]);
+        m2[0x400..0x404].copy_from_slice(&[0, 0, 0xff, 0xff]);
+        write_at(&mut m2, 0x404, &[1, 0x440, 1, 0x450]);
+        write_at(&mut m2, 0x440, &[1, 0x460, 0, 0]);
+        write_at(&mut m2, 0x450, &[1, 0x464, 0, 0]);
+        m2[0x464..0x466].copy_from_slice(&16384i16.to_le_bytes());
+        write_at(&mut m2, TRANSPARENCY_LOOKUP_HEADER as usize, &[1, 0x480]);
+
+        let weights = read_texture_weights(&m2, &[Some(m2.as_slice())]).unwrap();
+        assert_eq!(weights[0].interpolation, Interpolation::None);
+        assert_eq!(weights[0].sequences[0].timestamps, vec![0]);
+        assert!((weights[0].sequences[0].values[0] - 0.5).abs() < 1e-4);
+        assert_eq!(read_transparency_lookup(&m2).unwrap(), vec![Some(0)]);
+        assert!(read_colors(&m2, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_keys_outside_the_file() {
+        let layouts = read_bone_layouts(&model_bytes()).unwrap();
+        let mut bones = vec![TransformTracks::default()];
+        assert!(merge_anim_file(&mut bones, &layouts, 1, &[0; 8], CoordinateSystem::ZUp).is_err());
+    }
+}
@@ -0,0 +1,992 @@
+use bevy::{
+    math::{
+        VectorSpace,
+        curve::{ConstantCurve, Curve, ForeverCurve, Interval},
+    },
+    prelude::*,
+};
+use bevy_animation::{
+    AnimationClip, AnimationTargetId,
+    animatable::Animatable,
+    animated_field,
+    animation_curves::{
+        AnimatableCurve, AnimatableKeyframeCurve, AnimatableProperty, AnimatedField,
+        AnimationCompatibleCurve,
+    },
+    gltf_curves::{CubicKeyframeCurve, CubicRotationCurve, SteppedKeyframeCurve},
+    graph::AnimationNodeIndex,
+};
+use wow_m2::chunks::{
+    animation::M2Animation as M2SequenceEntry,
+    bone::{M2Bone, M2BoneRotation},
+    m2_track::{M2Track, TrackVec},
+};
+
+use super::{
+    CoordinateSystem,
+    anim::{SEQUENCE_ALIAS, SEQUENCE_INLINE},
+    c3_to_vec3,
+    skeleton::M2Skeleton,
+};
+
+/// Names of the client's `AnimationData.dbc` entries, indexed by animation ID.
+const ANIMATION_NAMES: &[&str] = &[
+    "Stand",
+    "Death",
+    "Spell",
+    "Stop",
+    "Walk",
+    "Run",
+    "Dead",
+    "Rise",
+    "StandWound",
+    "CombatWound",
+    "CombatCritical",
+    "ShuffleLeft",
+    "ShuffleRight",
+    "Walkbackwards",
+    "Stun",
+    "HandsClosed",
+    "AttackUnarmed",
+    "Attack1H",
+    "Attack2H",
+    "Attack2HL",
+    "ParryUnarmed",
+    "Parry1H",
+    "Parry2H",
+    "Parry2HL",
+    "ShieldBlock",
+    "ReadyUnarmed",
+    "Ready1H",
+    "Ready2H",
+    "Ready2HL",
+    "ReadyBow",
+    "Dodge",
+    "SpellPrecast",
+    "SpellCast",
+    "SpellCastArea",
+    "NPCWelcome",
+    "NPCGoodbye",
+    "Block",
+    "JumpStart",
+    "Jump",
+    "JumpEnd",
+    "Fall",
+    "SwimIdle",
+    "Swim",
+    "SwimLeft",
+    "SwimRight",
+    "SwimBackwards",
+    "AttackBow",
+    "FireBow",
+    "ReadyRifle",
+    "AttackRifle",
+    "Loot",
+    "ReadySpellDirected",
+    "ReadySpellOmni",
+    "SpellCastDirected",
+    "SpellCastOmni",
+    "BattleRoar",
+    "ReadyAbility",
+    "Special1H",
+    "Special2H",
+    "ShieldBash",
+    "EmoteTalk",
+    "EmoteEat",
+    "EmoteWork",
+    "EmoteUseStanding",
+    "EmoteTalkExclamation",
+    "EmoteTalkQuestion",
+    "EmoteBow",
+    "EmoteWave",
+    "EmoteCheer",
+    "EmoteDance",
+    "EmoteLaugh",
+    "EmoteSleep",
+    "EmoteSitGround",
+    "EmoteRude",
+    "EmoteRoar",
+    "EmoteKneel",
+    "EmoteKiss",
+    "EmoteCry",
+    "EmoteChicken",
+    "EmoteBeg",
+    "EmoteApplaud",
+    "EmoteShout",
+    "EmoteFlex",
+    "EmoteShy",
+    "EmotePoint",
+    "Attack1HPierce",
+    "Attack2HLoosePierce",
+    "AttackOff",
+    "AttackOffPierce",
+    "Sheath",
+    "HipSheath",
+    "Mount",
+    "RunRight",
+    "RunLeft",
+    "MountSpecial",
+    "Kick",
+    "SitGroundDown",
+    "SitGround",
+    "SitGroundUp",
+    "SleepDown",
+    "Sleep",
+    "SleepUp",
+    "SitChairLow",
+    "SitChairMed",
+    "SitChairHigh",
+    "LoadBow",
+    "LoadRifle",
+    "AttackThrown",
+    "ReadyThrown",
+    "HoldBow",
+    "HoldRifle",
+    "HoldThrown",
+    "LoadThrown",
+    "EmoteSalute",
+    "KneelStart",
+    "KneelLoop",
+    "KneelEnd",
+    "AttackUnarmedOff",
+    "SpecialUnarmed",
+    "StealthWalk",
+    "StealthStand",
+    "Knockdown",
+    "EatingLoop",
+    "UseStandingLoop",
+    "ChannelCastDirected",
+    "ChannelCastOmni",
+    "Whirlwind",
+    "Birth",
+    "UseStandingStart",
+    "UseStandingEnd",
+    "CreatureSpecial",
+    "Drown",
+    "Drowned",
+    "FishingCast",
+    "FishingLoop",
+    "Fly",
+    "EmoteWorkNoSheathe",
+    "EmoteStunNoSheathe",
+    "EmoteUseStandingNoSheathe",
+    "SpellSleepDown",
+    "SpellKneelStart",
+    "SpellKneelLoop",
+    "SpellKneelEnd",
+    "Sprint",
+    "InFlight",
+    "Spawn",
+    "Close",
+    "Closed",
+    "Open",
+    "Opened",
+    "Destroy",
+    "Destroyed",
+    "Rebuild",
+    "Custom0",
+    "Custom1",
+    "Custom2",
+    "Custom3",
+    "Despawn",
+];
+
+/// Display name of an animation ID, `Animation<id>` for IDs missing from the table.
+pub fn animation_name(id: u16) -> String {
+    match ANIMATION_NAMES.get(id as usize) {
+        Some(name) => name.to_string(),
+        None => format!("Animation{}", id),
+    }
+}
+
+/// The animation ID a name refers to, the reverse of `animation_name`.
+pub fn animation_id(name: &str) -> Option<u16> {
+    ANIMATION_NAMES
+        .iter()
+        .position(|known| known.eq_ignore_ascii_case(name))
+        .map(|id| id as u16)
+        .or_else(|| name.strip_prefix("Animation")?.parse().ok())
+}
+
+/// One entry of the model's sequence table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct M2Sequence {
+    pub id: u16,
+    pub variation: u16,
+    /// Length in milliseconds.
+    pub duration: u32,
+    pub flags: u32,
+}
+
+impl M2Sequence {
+    pub fn from_m2(entry: &M2SequenceEntry) -> Self {
+        Self {
+            id: entry.animation_id,
+            variation: entry.sub_animation_id,
+            // Since WotLK the start timestamp holds the duration and there is no end timestamp.
+            duration: match entry.end_timestamp {
+                Some(end) => end.saturating_sub(entry.start_timestamp),
+                None => entry.start_timestamp,
+            },
+            flags: entry.flags,
+        }
+    }
+
+    /// Whether the keyframes live in a separate `.anim` file.
+    pub fn is_external(&self) -> bool {
+        self.flags & (SEQUENCE_INLINE | SEQUENCE_ALIAS) == 0
+    }
+
+    /// `Stand` for the main variation of the stand animation, `Stand_1` for the next one.
+    pub fn name(&self) -> String {
+        match self.variation {
+            0 => animation_name(self.id),
+            variation => format!("{}_{}", animation_name(self.id), variation),
+        }
+    }
+}
+
+/// A sequence of the model as a playable clip.
+#[derive(Debug, Clone)]
+pub struct M2Animation {
+    pub sequence: M2Sequence,
+    pub name: String,
+    pub clip: Handle<AnimationClip>,
+    /// Node playing the clip in the model's `AnimationGraph`.
+    pub node: AnimationNodeIndex,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    None,
+    #[default]
+    Linear,
+    Hermite,
+    Bezier,
+}
+
+impl From<u16> for Interpolation {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::None,
+            2 => Self::Hermite,
+            3 => Self::Bezier,
+            _ => Self::Linear,
+        }
+    }
+}
+
+impl Interpolation {
+    pub fn is_spline(self) -> bool {
+        matches!(self, Self::Hermite | Self::Bezier)
+    }
+}
+
+/// Keys of a track for one sequence, timestamps in milliseconds from the sequence start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackKeys<T> {
+    pub timestamps: Vec<u32>,
+    pub values: Vec<T>,
+    /// The two tangents stored after each value of hermite and bezier tracks, empty otherwise.
+    pub tangents: Vec<(T, T)>,
+}
+
+impl<T> Default for TrackKeys<T> {
+    fn default() -> Self {
+        Self {
+            timestamps: Vec::new(),
+            values: Vec::new(),
+            tangents: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> TrackKeys<T> {
+    /// Splits raw track values into keys. Spline tracks store three values per timestamp.
+    pub fn new(interpolation: Interpolation, timestamps: Vec<u32>, values: Vec<T>) -> Self {
+        if interpolation.is_spline() && values.len() == timestamps.len() * 3 {
+            let mut keys = Self {
+                timestamps,
+                ..default()
+            };
+            for chunk in values.chunks_exact(3) {
+                keys.values.push(chunk[0].clone());
+                keys.tangents.push((chunk[1].clone(), chunk[2].clone()));
+            }
+            return keys;
+        }
+
+        let len = timestamps.len().min(values.len());
+        let mut keys = Self {
+            timestamps,
+            values,
+            tangents: Vec::new(),
+        };
+        keys.timestamps.truncate(len);
+        keys.values.truncate(len);
+        keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> TrackKeys<U> {
+        TrackKeys {
+            timestamps: self.timestamps.clone(),
+            values: self.values.iter().map(&f).collect(),
+            tangents: self.tangents.iter().map(|(a, b)| (f(a), f(b))).collect(),
+        }
+    }
+}
+
+impl<V: VectorSpace> TrackKeys<V> {
+    /// The keys moved by `offset`. Bezier tangents are control points and move with the values,
+    /// hermite ones are slopes and stay as they are.
+    pub fn translated(&self, offset: V, interpolation: Interpolation) -> Self {
+        let mut keys = self.map(|v| *v + offset);
+        if interpolation != Interpolation::Bezier {
+            keys.tangents = self.tangents.clone();
+        }
+        keys
+    }
+}
+
+/// An animated value of the model, with its keys split per sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    /// Tracks bound to a global sequence loop on their own and ignore the playing sequence.
+    pub global_sequence: Option<usize>,
+    pub sequences: Vec<TrackKeys<T>>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Self {
+            interpolation: Interpolation::default(),
+            global_sequence: None,
+            sequences: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> Track<T> {
+    pub fn from_m2<S: Clone>(track: &M2Track<S>, convert: impl Fn(&S) -> T) -> Self {
+        let interpolation = Interpolation::from(track.interpolation_type as u16);
+        let timestamps = track_vec(&track.timestamps);
+        let values = track_vec(&track.values);
+
+        Self {
+            interpolation,
+            global_sequence: usize::try_from(track.global_sequence).ok(),
+            sequences: timestamps
+                .into_iter()
+                .zip(values)
+                .map(|(timestamps, values)| {
+                    TrackKeys::new(interpolation, timestamps, values).map(&convert)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.sequences.iter().any(|keys| !keys.is_empty())
+    }
+
+    /// Keys to play during `sequence`, and the loop period in milliseconds for global sequence
+    /// tracks.
+    pub fn keys(
+        &self,
+        sequence: usize,
+        global_sequences: &[u32],
+    ) -> Option<(&TrackKeys<T>, Option<u32>)> {
+        let period = self
+            .global_sequence
+            .and_then(|index| global_sequences.get(index))
+            .copied();
+        let keys = match period {
+            Some(_) => self.sequences.first()?,
+            None => self.sequences.get(sequence)?,
+        };
+        (!keys.is_empty()).then_some((keys, period))
+    }
+
+    /// The first key of the first sequence that has any, for values that need a sensible
+    /// default outside of their keyed sequences.
+    pub fn first_value(&self) -> Option<&T> {
+        self.sequences.iter().find_map(|keys| keys.values.first())
+    }
+
+    /// The value outside of the keyed sequences, `default` for tracks without keys.
+    pub fn rest_value(&self, default: T) -> T {
+        self.first_value().cloned().unwrap_or(default)
+    }
+}
+
+fn track_vec<T: Clone>(values: &TrackVec<T>) -> Vec<Vec<T>> {
+    match values {
+        TrackVec::Multiple(values) => values.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// Decompresses a quaternion stored as four signed 16 bit integers.
+pub fn comp_quat_to_quat(values: [i16; 4]) -> Quat {
+    let [x, y, z, w] = values.map(|value| {
+        let value = value as i32;
+        let value = if value < 0 {
+            value + 32768
+        } else {
+            value - 32767
+        };
+        value as f32 / 32767.
+    });
+    Quat::from_xyzw(x, y, z, w).normalize()
+}
+
+/// Translation, rotation and scale tracks of a bone, in the loader's coordinate system, or of a
+/// texture transform, in UV space.
+#[derive(Debug, Clone, Default)]
+pub struct TransformTracks {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Quat>,
+    pub scale: Track<Vec3>,
+}
+
+impl TransformTracks {
+    pub fn is_animated(&self) -> bool {
+        self.translation.is_animated() || self.rotation.is_animated() || self.scale.is_animated()
+    }
+
+    pub fn from_bone(bone: &M2Bone, coordinates: CoordinateSystem) -> Self {
+        Self {
+            translation: Track::from_m2(&bone.translation, |v| {
+                coordinates.position(c3_to_vec3(*v))
+            }),
+            rotation: match &bone.rotation {
+                M2BoneRotation::Others(track) => Track::from_m2(track, |q| {
+                    coordinates.rotation(comp_quat_to_quat([q.x, q.y, q.z, q.w]))
+                }),
+                _ => Track::default(),
+            },
+            scale: Track::from_m2(&bone.scale, |v| coordinates.scale(c3_to_vec3(*v))),
+        }
+    }
+}
+
+/// Color and opacity of an M2 color, both in 0..1.
+#[derive(Debug, Clone, Default)]
+pub struct ColorTracks {
+    pub color: Track<Vec3>,
+    pub alpha: Track<f32>,
+}
+
+impl ColorTracks {
+    pub fn is_animated(&self) -> bool {
+        self.color.is_animated() || self.alpha.is_animated()
+    }
+}
+
+/// Builds the clip of one sequence. Channels animated in any sequence get a curve in every clip,
+/// resting ones hold their rest value, so switching clips never leaves a bone posed.
+pub fn build_clip(
+    skeleton: &M2Skeleton,
+    bones: &[TransformTracks],
+    sequence: usize,
+    duration_ms: u32,
+    global_sequences: &[u32],
+) -> AnimationClip {
+    let mut clip = AnimationClip::default();
+
+    for (index, (joint, tracks)) in skeleton.joints.iter().zip(bones).enumerate() {
+        let target = skeleton.target_id(index);
+        let rest_translation = joint.rest_translation;
+
+        if tracks.translation.is_animated() {
+            let interpolation = tracks.translation.interpolation;
+            let keys = tracks
+                .translation
+                .keys(sequence, global_sequences)
+                .map(|(keys, period)| (keys.translated(rest_translation, interpolation), period));
+            add_vector_channel(
+                &mut clip,
+                target,
+                animated_field!(Transform::translation),
+                interpolation,
+                keys.as_ref().map(|(keys, period)| (keys, *period)),
+                rest_translation,
+            );
+        }
+
+        if tracks.rotation.is_animated() {
+            add_rotation_channel(
+                &mut clip,
+                target,
+                animated_field!(Transform::rotation),
+                tracks.rotation.interpolation,
+                tracks.rotation.keys(sequence, global_sequences),
+            );
+        }
+
+        if tracks.scale.is_animated() {
+            add_vector_channel(
+                &mut clip,
+                target,
+                animated_field!(Transform::scale),
+                tracks.scale.interpolation,
+                tracks.scale.keys(sequence, global_sequences),
+                Vec3::ONE,
+            );
+        }
+    }
+
+    clip.set_duration(duration_ms as f32 / 1000.);
+    clip
+}
+
+/// Keys with strictly increasing times in seconds. Global sequence keys get a closing key at the
+/// end of the period so the repeated curve wraps around smoothly.
+struct Keyframes<T> {
+    times: Vec<f32>,
+    values: Vec<T>,
+    tangents: Vec<(T, T)>,
+}
+
+impl<T: Clone> Keyframes<T> {
+    fn new(keys: &TrackKeys<T>, period: Option<u32>) -> Self {
+        let mut keyframes = Self {
+            times: Vec::with_capacity(keys.timestamps.len() + 1),
+            values: Vec::with_capacity(keys.values.len() + 1),
+            tangents: Vec::with_capacity(keys.tangents.len() + 1),
+        };
+        let mut last = None;
+        for (i, timestamp) in keys.timestamps.iter().enumerate() {
+            if last.is_some_and(|last| *timestamp <= last) {
+                continue;
+            }
+            last = Some(*timestamp);
+            keyframes.push(*timestamp, keys, i);
+        }
+
+        if let Some(period) = period.filter(|period| last.is_some_and(|last| *period > last)) {
+            keyframes.push(period, keys, 0);
+        }
+
+        keyframes
+    }
+
+    fn push(&mut self, timestamp: u32, keys: &TrackKeys<T>, index: usize) {
+        self.times.push(timestamp as f32 / 1000.);
+        self.values.push(keys.values[index].clone());
+        if let Some(tangents) = keys.tangents.get(index) {
+            self.tangents.push(tangents.clone());
+        }
+    }
+
+    fn samples(&self) -> impl Iterator<Item = (f32, T)> + '_ {
+        self.times.iter().copied().zip(self.values.iter().cloned())
+    }
+
+    fn has_tangents(&self) -> bool {
+        self.tangents.len() == self.values.len()
+    }
+}
+
+impl<V: VectorSpace> Keyframes<V> {
+    /// In-tangent, value and out-tangent per key, the layout of the glTF cubic curves. As in the
+    /// client, both control values of a segment come from its first key: hermite tangents span
+    /// the whole segment and bezier ones are the inner control points.
+    fn cubic_samples(&self, interpolation: Interpolation) -> Vec<V> {
+        let mut samples = Vec::with_capacity(self.values.len() * 3);
+        for i in 0..self.values.len() {
+            let in_tangent = match i.checked_sub(1) {
+                Some(prev) => self.segment_tangents(prev, interpolation).1,
+                None => V::ZERO,
+            };
+            let out_tangent = if i + 1 < self.values.len() {
+                self.segment_tangents(i, interpolation).0
+            } else {
+                V::ZERO
+            };
+            samples.extend([in_tangent, self.values[i], out_tangent]);
+        }
+        samples
+    }
+
+    /// Start and end derivatives of the segment after key `i`, per second.
+    fn segment_tangents(&self, i: usize, interpolation: Interpolation) -> (V, V) {
+        let duration = self.times[i + 1] - self.times[i];
+        let (first, second) = self.tangents[i];
+        let (start, end) = match interpolation {
+            Interpolation::Bezier => (
+                (first - self.values[i]) * 3.,
+                (self.values[i + 1] - second) * 3.,
+            ),
+            _ => (first, second),
+        };
+        (start / duration, end / duration)
+    }
+}
+
+fn add_curve<P, C>(
+    clip: &mut AnimationClip,
+    target: AnimationTargetId,
+    property: P,
+    curve: C,
+    looping: bool,
+) where
+    P: AnimatableProperty + Clone,
+    C: AnimationCompatibleCurve<P::Property>,
+    ForeverCurve<P::Property, C>: AnimationCompatibleCurve<P::Property>,
+{
+    match looping.then(|| curve.clone().forever().ok()).flatten() {
+        Some(curve) => clip.add_curve_to_target(target, AnimatableCurve::new(property, curve)),
+        None => clip.add_curve_to_target(target, AnimatableCurve::new(property, curve)),
+    }
+}
+
+fn add_constant<P>(
+    clip: &mut AnimationClip,
+    target: AnimationTargetId,
+    property: P,
+    value: P::Property,
+) where
+    P: AnimatableProperty + Clone,
+    ConstantCurve<P::Property>: AnimationCompatibleCurve<P::Property>,
+{
+    clip.add_curve_to_target(
+        target,
+        AnimatableCurve::new(property, ConstantCurve::new(Interval::EVERYWHERE, value)),
+    );
+}
+
+/// Adds a curve for each animated `$field` of `$tracks`, driving the field of the same name of
+/// the `$state` component and holding `$rest.$field` in sequences without keys.
+macro_rules! add_track_channels {
+    (
+        $clip:expr, $target:expr, $state:ident, $tracks:expr, $rest:expr,
+        $sequence:expr, $global_sequences:expr; $($field:ident),* $(,)?
+    ) => {$(
+        if $tracks.$field.is_animated() {
+            $crate::m2::animation::add_vector_channel(
+                $clip,
+                $target,
+                bevy_animation::animated_field!($state::$field),
+                $tracks.$field.interpolation,
+                $tracks.$field.keys($sequence, $global_sequences),
+                $rest.$field,
+            );
+        }
+    )*};
+}
+pub(super) use add_track_channels;
+
+/// Adds the curve of a vector or scalar property, holding `rest` in sequences without keys.
+pub(super) fn add_vector_channel<V, P>(
+    clip: &mut AnimationClip,
+    target: AnimationTargetId,
+    property: P,
+    interpolation: Interpolation,
+    keys: Option<(&TrackKeys<V>, Option<u32>)>,
+    rest: V,
+) where
+    V: VectorSpace + Animatable,
+    P: AnimatableProperty<Property = V> + Clone,
+    ConstantCurve<V>: AnimationCompatibleCurve<V>,
+    SteppedKeyframeCurve<V>: AnimationCompatibleCurve<V>,
+    CubicKeyframeCurve<V>: AnimationCompatibleCurve<V>,
+    AnimatableKeyframeCurve<V>: AnimationCompatibleCurve<V>,
+    ForeverCurve<V, SteppedKeyframeCurve<V>>: AnimationCompatibleCurve<V>,
+    ForeverCurve<V, CubicKeyframeCurve<V>>: AnimationCompatibleCurve<V>,
+    ForeverCurve<V, AnimatableKeyframeCurve<V>>: AnimationCompatibleCurve<V>,
+{
+    let Some((keys, period)) = keys else {
+        add_constant(clip, target, property, rest);
+        return;
+    };
+
+    let keyframes = Keyframes::new(keys, period);
+    let looping = period.is_some();
+    if keyframes.values.len() < 2 {
+        add_constant(clip, target, property, keyframes.values[0]);
+        return;
+    }
+
+    match interpolation {
+        Interpolation::None => {
+            if let Ok(curve) = SteppedKeyframeCurve::new(keyframes.samples()) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+        Interpolation::Hermite | Interpolation::Bezier if keyframes.has_tangents() => {
+            if let Ok(curve) = CubicKeyframeCurve::new(
+                keyframes.times.iter().copied(),
+                keyframes.cubic_samples(interpolation),
+            ) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+        _ => {
+            if let Ok(curve) = AnimatableKeyframeCurve::new(keyframes.samples()) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+    }
+}
+
+pub(super) fn add_rotation_channel<P: AnimatableProperty<Property = Quat> + Clone>(
+    clip: &mut AnimationClip,
+    target: AnimationTargetId,
+    property: P,
+    interpolation: Interpolation,
+    keys: Option<(&TrackKeys<Quat>, Option<u32>)>,
+) {
+    let Some((keys, period)) = keys else {
+        add_constant(clip, target, property, Quat::IDENTITY);
+        return;
+    };
+
+    let keyframes = Keyframes::new(keys, period);
+    let looping = period.is_some();
+    if keyframes.values.len() < 2 {
+        add_constant(clip, target, property, keyframes.values[0]);
+        return;
+    }
+
+    match interpolation {
+        Interpolation::None => {
+            if let Ok(curve) = SteppedKeyframeCurve::new(keyframes.samples()) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+        Interpolation::Hermite | Interpolation::Bezier if keyframes.has_tangents() => {
+            let keyframes = Keyframes {
+                times: keyframes.times,
+                values: keyframes.values.iter().map(|q| Vec4::from(*q)).collect(),
+                tangents: keyframes
+                    .tangents
+                    .iter()
+                    .map(|(a, b)| (Vec4::from(*a), Vec4::from(*b)))
+                    .collect(),
+            };
+            if let Ok(curve) = CubicRotationCurve::new(
+                keyframes.times.iter().copied(),
+                keyframes.cubic_samples(interpolation),
+            ) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+        _ => {
+            if let Ok(curve) = AnimatableKeyframeCurve::new(keyframes.samples()) {
+                add_curve(clip, target, property, curve, looping);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys<T: Clone>(timestamps: &[u32], values: &[T]) -> TrackKeys<T> {
+        TrackKeys::new(Interpolation::Linear, timestamps.to_vec(), values.to_vec())
+    }
+
+    #[test]
+    fn compressed_quaternions_decompress() {
+        let identity = comp_quat_to_quat([32767, 32767, 32767, -1]);
+        assert!(identity.abs_diff_eq(Quat::IDENTITY, 1e-6));
+
+        let half_turn = comp_quat_to_quat([32767, 32767, -1, 32767]);
+        assert!(half_turn.abs_diff_eq(Quat::from_rotation_z(std::f32::consts::PI), 1e-6));
+    }
+
+    #[test]
+    fn animation_names_round_trip() {
+        assert_eq!(animation_name(0), "Stand");
+        assert_eq!(animation_name(4), "Walk");
+        assert_eq!(animation_name(5), "Run");
+        assert_eq!(animation_name(4000), "Animation4000");
+        assert_eq!(animation_id("walk"), Some(4));
+        assert_eq!(animation_id("Animation4000"), Some(4000));
+        assert_eq!(animation_id("Moonwalk"), None);
+
+        let sequence = M2Sequence {
+            id: 0,
+            variation: 2,
+            duration: 1000,
+            flags: 0,
+        };
+        assert_eq!(sequence.name(), "Stand_2");
+    }
+
+    #[test]
+    fn spline_values_carry_tangents() {
+        let keys = TrackKeys::new(
+            Interpolation::Hermite,
+            vec![0, 100],
+            vec![1., 2., 3., 4., 5., 6.],
+        );
+        assert_eq!(keys.values, vec![1., 4.]);
+        assert_eq!(keys.tangents, vec![(2., 3.), (5., 6.)]);
+
+        let keys = TrackKeys::new(Interpolation::Linear, vec![0, 100, 200], vec![1., 2.]);
+        assert_eq!(keys.timestamps, vec![0, 100]);
+    }
+
+    #[test]
+    fn hermite_curves_match_the_client() {
+        let keys = TrackKeys::new(
+            Interpolation::Hermite,
+            vec![0, 500],
+            vec![
+                Vec3::ZERO,
+                Vec3::new(2., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::ONE,
+                Vec3::ZERO,
+                Vec3::ZERO,
+            ],
+        );
+        let keyframes = Keyframes::new(&keys, None);
+        let curve = CubicKeyframeCurve::new(
+            keyframes.times.iter().copied(),
+            keyframes.cubic_samples(Interpolation::Hermite),
+        )
+        .unwrap();
+
+        // h1 * v1 + h2 * v2 + h3 * in + h4 * out at the segment midpoint.
+        let (h1, h2, h3, h4) = (0.5, 0.5, 0.125, -0.125);
+        let expected = Vec3::ZERO * h1 + Vec3::ONE * h2 + Vec3::new(2., 0., 0.) * h3 + Vec3::X * h4;
+        assert!(curve.sample_clamped(0.25).abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn bezier_curves_use_control_points() {
+        let keys = TrackKeys::new(
+            Interpolation::Bezier,
+            vec![0, 1000],
+            vec![
+                Vec3::ZERO,
+                Vec3::Y,
+                Vec3::new(1., 1., 0.),
+                Vec3::X,
+                Vec3::ZERO,
+                Vec3::ZERO,
+            ],
+        );
+        let keyframes = Keyframes::new(&keys, None);
+        let curve = CubicKeyframeCurve::new(
+            keyframes.times.iter().copied(),
+            keyframes.cubic_samples(Interpolation::Bezier),
+        )
+        .unwrap();
+
+        let expected = Vec3::Y * 0.375 + Vec3::new(1., 1., 0.) * 0.375 + Vec3::X * 0.125;
+        assert!(curve.sample_clamped(0.5).abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn translated_keys_keep_hermite_slopes() {
+        let skeleton = M2Skeleton::from_links([(-1, Vec3::Y), (0, Vec3::new(0., 3., 0.))]);
+        let rest = skeleton.joints[1].rest_translation;
+        assert_eq!(rest, Vec3::new(0., 2., 0.));
+
+        let values = vec![
+            Vec3::ZERO,
+            Vec3::new(2., 0., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::ONE,
+            Vec3::ZERO,
+            Vec3::ZERO,
+        ];
+        for interpolation in [Interpolation::Hermite, Interpolation::Bezier] {
+            let keys = TrackKeys::new(interpolation, vec![0, 500], values.clone());
+            let curve = |keys: &TrackKeys<Vec3>| {
+                let keyframes = Keyframes::new(keys, None);
+                CubicKeyframeCurve::new(
+                    keyframes.times.iter().copied(),
+                    keyframes.cubic_samples(interpolation),
+                )
+                .unwrap()
+            };
+
+            // Offsetting the keys has to move the whole curve, without bending it.
+            let (original, moved) = (curve(&keys), curve(&keys.translated(rest, interpolation)));
+            for t in [0., 0.1, 0.25, 0.4, 0.5] {
+                let expected = original.sample_clamped(t) + rest;
+                assert!(moved.sample_clamped(t).abs_diff_eq(expected, 1e-5));
+            }
+        }
+
+        let bones = [
+            TransformTracks::default(),
+            TransformTracks {
+                translation: Track {
+                    interpolation: Interpolation::Hermite,
+                    global_sequence: None,
+                    sequences: vec![TrackKeys::new(Interpolation::Hermite, vec![0, 500], values)],
+                },
+                ..default()
+            },
+        ];
+        let clip = build_clip(&skeleton, &bones, 0, 500, &[]);
+        assert!(clip.curves_for_target(skeleton.target_id(1)).is_some());
+    }
+
+    #[test]
+    fn global_sequence_keys_close_the_loop() {
+        let keys = keys(&[0, 250, 250, 500], &[0., 1., 5., 2.]);
+        let keyframes = Keyframes::new(&keys, Some(1000));
+        assert_eq!(keyframes.times, vec![0., 0.25, 0.5, 1.]);
+        assert_eq!(keyframes.values, vec![0., 1., 2., 0.]);
+
+        let track = Track {
+            interpolation: Interpolation::Linear,
+            global_sequence: Some(0),
+            sequences: vec![keys.clone()],
+        };
+        let (_, period) = track.keys(3, &[1000]).unwrap();
+        assert_eq!(period, Some(1000));
+    }
+
+    #[test]
+    fn clips_cover_every_animated_channel() {
+        let skeleton = M2Skeleton::from_links([(-1, Vec3::ZERO), (0, Vec3::Y)]);
+        let moving = Track {
+            interpolation: Interpolation::Linear,
+            global_sequence: None,
+            sequences: vec![
+                keys(&[0, 1000], &[Vec3::ZERO, Vec3::X]),
+                TrackKeys::default(),
+            ],
+        };
+        let bones = [
+            TransformTracks {
+                translation: moving,
+                ..default()
+            },
+            TransformTracks::default(),
+        ];
+
+        let walk = build_clip(&skeleton, &bones, 0, 1200, &[]);
+        assert_eq!(walk.duration(), 1.2);
+        assert_eq!(
+            walk.curves_for_target(skeleton.target_id(0)).unwrap().len(),
+            1
+        );
+        assert!(walk.curves_for_target(skeleton.target_id(1)).is_none());
+
+        // The second sequence doesn't move the bone, it still gets pinned to its rest pose.
+        let stand = build_clip(&skeleton, &bones, 1, 3000, &[]);
+        assert_eq!(stand.duration(), 3.);
+        assert_eq!(
+            stand
+                .curves_for_target(skeleton.target_id(0))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::io::{Cursor, Seek, SeekFrom};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{
+    CoordinateSystem,
+    anim::{ArrayRef, read_vec3, track_size},
+    skeleton::M2Skeleton,
+};
+use crate::errors::Result;
+
+const ATTACHMENT_POSITION: u64 = 0x08;
+const ATTACHMENT_ANIMATED: u64 = 0x14;
+
+/// Names of the client's attachment IDs, indexed by ID.
+const ATTACHMENT_NAMES: &[&str] = &[
+    "Shield",
+    "HandRight",
+    "HandLeft",
+    "ElbowRight",
+    "ElbowLeft",
+    "ShoulderRight",
+    "ShoulderLeft",
+    "KneeRight",
+    "KneeLeft",
+    "HipRight",
+    "HipLeft",
+    "Helm",
+    "Back",
+    "ShoulderFlapRight",
+    "ShoulderFlapLeft",
+    "ChestBloodFront",
+    "ChestBloodBack",
+    "Breath",
+    "PlayerName",
+    "Base",
+    "Head",
+    "SpellLeftHand",
+    "SpellRightHand",
+    "Special1",
+    "Special2",
+    "Special3",
+    "SheathMainHand",
+    "SheathOffHand",
+    "SheathShield",
+    "PlayerNameMounted",
+    "LargeWeaponLeft",
+    "LargeWeaponRight",
+    "HipWeaponLeft",
+    "HipWeaponRight",
+    "Chest",
+    "HandArrow",
+    "Bullet",
+    "SpellHandOmni",
+    "SpellHandDirected",
+    "VehicleSeat1",
+    "VehicleSeat2",
+    "VehicleSeat3",
+    "VehicleSeat4",
+    "VehicleSeat5",
+    "VehicleSeat6",
+    "VehicleSeat7",
+    "VehicleSeat8",
+    "LeftFoot",
+    "RightFoot",
+    "ShieldNoGlove",
+    "SpineLow",
+    "AlteredShoulderRight",
+    "AlteredShoulderLeft",
+    "BeltBuckle",
+    "SheathCrossbow",
+    "HeadTop",
+];
+
+/// Display name of an attachment ID, `Attachment<id>` for IDs missing from the table.
+pub fn attachment_name(id: u32) -> String {
+    match ATTACHMENT_NAMES.get(id as usize) {
+        Some(name) => name.to_string(),
+        None => format!("Attachment{}", id),
+    }
+}
+
+/// The attachment ID a name refers to, the reverse of `attachment_name`.
+pub fn attachment_id(name: &str) -> Option<u32> {
+    ATTACHMENT_NAMES
+        .iter()
+        .position(|known| known.eq_ignore_ascii_case(name))
+        .map(|id| id as u32)
+        .or_else(|| name.strip_prefix("Attachment")?.parse().ok())
+}
+
+/// A point other models attach to, such as a hand holding a weapon or the head wearing a helm.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct M2Attachment {
+    pub id: u32,
+    pub bone: Option<usize>,
+    /// Position in model space, converted like the vertices.
+    pub position: Vec3,
+}
+
+impl M2Attachment {
+    pub fn name(&self) -> Name {
+        Name::new(format!("attachment_{}", attachment_name(self.id)))
+    }
+}
+
+/// Reads the `attachments`, of any version: only the track ending them changes size.
+pub fn read_attachments(
+    m2: &[u8],
+    attachments: ArrayRef,
+    coordinates: CoordinateSystem,
+) -> Result<Vec<M2Attachment>> {
+    let mut reader = Cursor::new(m2);
+    let size = ATTACHMENT_ANIMATED + track_size(m2);
+
+    (0..attachments.count as u64)
+        .map(|i| {
+            let start = attachments.offset as u64 + i * size;
+            reader.seek(SeekFrom::Start(start))?;
+            let id = reader.read_u32::<LittleEndian>()?;
+            let bone = usize::try_from(reader.read_i16::<LittleEndian>()?).ok();
+            reader.seek(SeekFrom::Start(start + ATTACHMENT_POSITION))?;
+            let position = coordinates.position(read_vec3(&mut reader)?);
+
+            Ok(M2Attachment { id, bone, position })
+        })
+        .collect()
+}
+
+/// The attachment point entities of a spawned model, by attachment ID, inserted on its root.
+#[derive(Component, Debug, Clone, Default)]
+pub struct M2AttachmentPoints(pub HashMap<u32, Entity>);
+
+impl M2AttachmentPoints {
+    pub fn get(&self, id: u32) -> Option<Entity> {
+        self.0.get(&id).copied()
+    }
+}
+
+/// Spawns the attachment points and records them on `root`.
+pub fn spawn_attachment_points(
+    commands: &mut Commands,
+    root: Entity,
+    skeleton: &M2Skeleton,
+    joints: &[Entity],
+    attachments: &[M2Attachment],
+) -> M2AttachmentPoints {
+    let points = M2AttachmentPoints(
+        attachments
+            .iter()
+            .map(|attachment| {
+                let (parent, translation) =
+                    skeleton.parent_of(joints, root, attachment.bone, attachment.position);
+                let point = commands
+                    .spawn((
+                        attachment.name(),
+                        Transform::from_translation(translation),
+                        Visibility::default(),
+                        ChildOf(parent),
+                    ))
+                    .id();
+                (attachment.id, point)
+            })
+            .collect(),
+    );
+    commands.entity(root).insert(points.clone());
+    points
+}
+
+/// Attaches the root of a model to an attachment point of another spawned model, so it follows
+/// that point's bone. The attached model's `Transform` is then relative to the point. Models
+/// without that attachment hold it at their origin.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct M2AttachTo {
+    /// Root of the model to attach to, as passed to `M2Asset::spawn`.
+    pub model: Entity,
+    pub attachment: u32,
+}
+
+impl M2AttachTo {
+    /// Attaches to the attachment named as by `attachment_name`, `None` for unknown names.
+    pub fn named(model: Entity, attachment: &str) -> Option<Self> {
+        Some(Self {
+            model,
+            attachment: attachment_id(attachment)?,
+        })
+    }
+}
+
+/// Parents every `M2AttachTo` model to its attachment point, once the model it attaches to has
+/// been spawned, and again whenever the attachment changes.
+pub fn attach_models(
+    mut commands: Commands,
+    attached: Query<(Entity, &M2AttachTo, Option<&ChildOf>)>,
+    points: Query<&M2AttachmentPoints>,
+) {
+    for (entity, attach_to, child_of) in &attached {
+        let Ok(model_points) = points.get(attach_to.model) else {
+            continue;
+        };
+        let parent = model_points
+            .get(attach_to.attachment)
+            .unwrap_or(attach_to.model);
+        if child_of.map(ChildOf::parent) != Some(parent) {
+            commands.entity(entity).insert(ChildOf(parent));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::m2::anim::write_bytes;
+
+    #[test]
+    fn reads_attachment_points() {
+        let start = 0x140;
+        let array = ArrayRef {
+            count: 2,
+            offset: start as u32,
+        };
+
+        // Older models end their attachments with a longer track.
+        for (version, size) in [(264u32, 0x28), (256, 0x30)] {
+            let mut m2 = vec![0u8; 0x200];
+            write_bytes(&mut m2, 4, &version.to_le_bytes());
+            write_bytes(&mut m2, start, &[1, 0, 0, 0, 4, 0]);
+            write_bytes(
+                &mut m2,
+                start + ATTACHMENT_POSITION as usize + 4,
+                &2f32.to_le_bytes(),
+            );
+            write_bytes(&mut m2, start + size, &[11, 0, 0, 0, 0xff, 0xff]);
+
+            let attachments = read_attachments(&m2, array, CoordinateSystem::YUp).unwrap();
+            assert_eq!(
+                attachments,
+                vec![
+                    M2Attachment {
+                        id: 1,
+                        bone: Some(4),
+                        position: Vec3::new(0., 0., -2.),
+                    },
+                    M2Attachment {
+                        id: 11,
+                        bone: None,
+                        position: Vec3::ZERO,
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn attachment_names_round_trip() {
+        assert_eq!(attachment_name(1), "HandRight");
+        assert_eq!(attachment_id("sheathmainhand"), Some(26));
+        assert_eq!(attachment_name(200), "Attachment200");
+        assert_eq!(attachment_id("Attachment200"), Some(200));
+        assert_eq!(attachment_id("Tail"), None);
+    }
+
+    #[test]
+    fn attached_models_follow_their_attachment_point() {
+        let mut world = World::new();
+        let model = world.spawn_empty().id();
+        let hand = world.spawn(ChildOf(model)).id();
+        world
+            .entity_mut(model)
+            .insert(M2AttachmentPoints([(1, hand)].into_iter().collect()));
+        let sword = world
+            .spawn(M2AttachTo::named(model, "HandRight").unwrap())
+            .id();
+        let helm = world
+            .spawn(M2AttachTo {
+                model,
+                attachment: 11,
+            })
+            .id();
+        let waiting = world
+            .spawn(M2AttachTo {
+                model: helm,
+                attachment: 1,
+            })
+            .id();
+
+        world.run_system_once(attach_models).unwrap();
+
+        assert_eq!(world.get::<ChildOf>(sword), Some(&ChildOf(hand)));
+        assert_eq!(world.get::<ChildOf>(helm), Some(&ChildOf(model)));
+        assert_eq!(world.get::<ChildOf>(waiting), None);
+    }
+}
@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy_animation::{
+    AnimationClip, AnimationTarget, AnimationTargetId, animated_field,
+    animation_curves::AnimatedField,
+};
+
+use super::{
+    animation::{ColorTracks, Track, add_vector_channel},
+    material::{BlendMode, M2Material},
+};
+
+/// The current value of one of the model's colors, animated by the model's clips. Draws pick it
+/// up through `M2ColorAnimation`.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct M2Color {
+    pub color: Vec3,
+    pub alpha: f32,
+}
+
+impl Default for M2Color {
+    fn default() -> Self {
+        Self {
+            color: Vec3::ONE,
+            alpha: 1.,
+        }
+    }
+}
+
+impl M2Color {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("color{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+}
+
+/// The current value of one of the model's texture weights, the opacity of the draws using it.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct M2TextureWeight {
+    pub weight: f32,
+}
+
+impl Default for M2TextureWeight {
+    fn default() -> Self {
+        Self { weight: 1. }
+    }
+}
+
+impl M2TextureWeight {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("texture_weight{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+}
+
+/// On a draw with an animated tint or opacity, the entities holding them.
+#[derive(Component, Debug, Clone, Default)]
+pub struct M2ColorAnimation {
+    pub color: Option<Entity>,
+    pub texture_weight: Option<Entity>,
+}
+
+impl M2ColorAnimation {
+    /// The diffuse color of the draw, white when nothing is bound.
+    pub fn base_color(
+        &self,
+        colors: &Query<&M2Color>,
+        weights: &Query<&M2TextureWeight>,
+    ) -> LinearRgba {
+        let color = self
+            .color
+            .and_then(|entity| colors.get(entity).ok())
+            .copied()
+            .unwrap_or_default();
+        let weight = self
+            .texture_weight
+            .and_then(|entity| weights.get(entity).ok())
+            .copied()
+            .unwrap_or_default();
+        mix_color(color, weight)
+    }
+}
+
+fn mix_color(color: M2Color, weight: M2TextureWeight) -> LinearRgba {
+    LinearRgba::new(
+        color.color.x,
+        color.color.y,
+        color.color.z,
+        (color.alpha * weight.weight).clamp(0., 1.),
+    )
+}
+
+/// Spawns the `M2Color` and `M2TextureWeight` entities below `root`, animated by the
+/// `AnimationPlayer` on `root`.
+pub fn spawn_colors(
+    commands: &mut Commands,
+    root: Entity,
+    colors: usize,
+    texture_weights: usize,
+) -> (Vec<Entity>, Vec<Entity>) {
+    let colors = (0..colors)
+        .map(|i| {
+            commands
+                .spawn((
+                    M2Color::name(i),
+                    M2Color::default(),
+                    AnimationTarget {
+                        id: M2Color::target_id(i),
+                        player: root,
+                    },
+                    ChildOf(root),
+                ))
+                .id()
+        })
+        .collect();
+    let texture_weights = (0..texture_weights)
+        .map(|i| {
+            commands
+                .spawn((
+                    M2TextureWeight::name(i),
+                    M2TextureWeight::default(),
+                    AnimationTarget {
+                        id: M2TextureWeight::target_id(i),
+                        player: root,
+                    },
+                    ChildOf(root),
+                ))
+                .id()
+        })
+        .collect();
+    (colors, texture_weights)
+}
+
+/// The alpha mode of a draw whose diffuse alpha is `alpha`. Opaque and alpha keyed draws would
+/// ignore a fading alpha, so they blend until it is back to 1.
+fn alpha_mode(blend_mode: BlendMode, alpha: f32) -> AlphaMode {
+    if alpha < 1. && !blend_mode.is_transparent() {
+        AlphaMode::Blend
+    } else {
+        blend_mode.alpha_mode()
+    }
+}
+
+/// Adds the curves of the colors and texture weights to the clip of `sequence`. Sequences
+/// without keys show the draws untinted and opaque, like the client.
+pub fn add_color_curves(
+    clip: &mut AnimationClip,
+    colors: &[ColorTracks],
+    texture_weights: &[Track<f32>],
+    sequence: usize,
+    global_sequences: &[u32],
+) {
+    for (index, tracks) in colors.iter().enumerate() {
+        let target = M2Color::target_id(index);
+
+        if tracks.color.is_animated() {
+            add_vector_channel(
+                clip,
+                target,
+                animated_field!(M2Color::color),
+                tracks.color.interpolation,
+                tracks.color.keys(sequence, global_sequences),
+                Vec3::ONE,
+            );
+        }
+
+        if tracks.alpha.is_animated() {
+            add_vector_channel(
+                clip,
+                target,
+                animated_field!(M2Color::alpha),
+                tracks.alpha.interpolation,
+                tracks.alpha.keys(sequence, global_sequences),
+                1.,
+            );
+        }
+    }
+
+    for (index, track) in texture_weights.iter().enumerate() {
+        if track.is_animated() {
+            add_vector_channel(
+                clip,
+                M2TextureWeight::target_id(index),
+                animated_field!(M2TextureWeight::weight),
+                track.interpolation,
+                track.keys(sequence, global_sequences),
+                1.,
+            );
+        }
+    }
+}
+
+/// Copies the animated colors and texture weights into the materials of the draws using them,
+/// along with the alpha mode that opacity needs. Those materials belong to a single spawned
+/// model, see `M2Asset::spawn`.
+pub fn update_colors(
+    draws: Query<(&M2ColorAnimation, &MeshMaterial3d<M2Material>)>,
+    colors: Query<&M2Color>,
+    weights: Query<&M2TextureWeight>,
+    mut materials: ResMut<Assets<M2Material>>,
+) {
+    for (animation, material) in &draws {
+        let Some(current) = materials.get(&material.0) else {
+            continue;
+        };
+
+        let base_color = animation.base_color(&colors, &weights);
+        let alpha_mode = alpha_mode(current.extension.blend_mode, base_color.alpha);
+        let base_color = Color::from(base_color);
+        if current.base.base_color == base_color && current.base.alpha_mode == alpha_mode {
+            continue;
+        }
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.base.base_color = base_color;
+            material.base.alpha_mode = alpha_mode;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_scale_color_alpha() {
+        let color = M2Color {
+            color: Vec3::new(1., 0.5, 0.),
+            alpha: 0.5,
+        };
+        assert_eq!(
+            mix_color(color, M2TextureWeight { weight: 0.5 }),
+            LinearRgba::new(1., 0.5, 0., 0.25)
+        );
+        assert_eq!(
+            mix_color(M2Color::default(), M2TextureWeight::default()),
+            LinearRgba::WHITE
+        );
+    }
+
+    #[test]
+    fn fading_opaque_draws_blend() {
+        assert_eq!(alpha_mode(BlendMode::Opaque, 1.), AlphaMode::Opaque);
+        assert_eq!(alpha_mode(BlendMode::Opaque, 0.5), AlphaMode::Blend);
+        assert_eq!(alpha_mode(BlendMode::AlphaKey, 0.), AlphaMode::Blend);
+        assert_eq!(
+            alpha_mode(BlendMode::AlphaKey, 1.),
+            BlendMode::AlphaKey.alpha_mode()
+        );
+        assert_eq!(alpha_mode(BlendMode::Add, 0.5), AlphaMode::Add);
+    }
+}
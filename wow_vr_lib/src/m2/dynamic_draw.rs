@@ -0,0 +1,45 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::PrimitiveTopology, view::NoFrustumCulling},
+};
+use bevy_asset::RenderAssetUsages;
+
+use super::material::M2Material;
+
+/// Spawns the entity drawing the geometry of an emitter below `root`, hidden until there is
+/// something to draw. Its mesh is rebuilt in the space of `root` every frame.
+pub fn spawn_dynamic_draw(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    name: &'static str,
+    material: Handle<M2Material>,
+    root: Entity,
+) -> (Entity, Handle<Mesh>) {
+    let mesh = meshes.add(Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    ));
+    let draw = commands
+        .spawn((
+            Name::new(name),
+            Mesh3d(mesh.clone()),
+            MeshMaterial3d(material),
+            Transform::default(),
+            Visibility::Hidden,
+            NoFrustumCulling,
+            ChildOf(root),
+        ))
+        .id();
+    (draw, mesh)
+}
+
+/// Shows the draw of an emitter only while it has something to draw.
+pub fn show_dynamic_draw(visibilities: &mut Query<&mut Visibility>, draw: Entity, shown: bool) {
+    if let Ok(mut visibility) = visibilities.get_mut(draw) {
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
@@ -0,0 +1,374 @@
+use std::io::{Cursor, Seek, SeekFrom};
+
+use bevy::prelude::*;
+use bevy_animation::{
+    AnimationClip, AnimationTarget, AnimationTargetId, animation_curves::AnimatedField,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{
+    CoordinateSystem,
+    anim::{ArrayRef, TRACK_SIZE, TrackLayout, read_vec3},
+    animation::{Track, add_track_channels},
+    skeleton::M2Skeleton,
+};
+use crate::errors::Result;
+
+const LIGHT_SIZE: u64 = 0x9c;
+const LIGHT_POSITION: u64 = 0x04;
+const LIGHT_TRACKS: u64 = 0x10;
+
+/// Illuminance, in lux, of an M2 light of intensity 1 on the surfaces it fully lights: those
+/// closer than its attenuation start for point lights, everything for directional ones.
+pub const LIGHT_ILLUMINANCE: f32 = light_consts::lux::OVERCAST_DAY;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LightType {
+    /// Shines along the light's down axis on the whole scene.
+    Directional,
+    #[default]
+    Point,
+}
+
+impl LightType {
+    pub fn from_raw(light_type: u16) -> Self {
+        match light_type {
+            0 => Self::Directional,
+            _ => Self::Point,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightTracks {
+    pub ambient_color: Track<Vec3>,
+    pub ambient_intensity: Track<f32>,
+    pub diffuse_color: Track<Vec3>,
+    pub diffuse_intensity: Track<f32>,
+    /// Distance up to which the light is at full strength.
+    pub attenuation_start: Track<f32>,
+    /// Distance past which the light has faded out.
+    pub attenuation_end: Track<f32>,
+    pub visible: Track<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct M2Light {
+    pub light_type: LightType,
+    pub bone: Option<usize>,
+    pub position: Vec3,
+    pub tracks: LightTracks,
+    pub coordinates: CoordinateSystem,
+}
+
+impl M2Light {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("light{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+
+    /// Turns Bevy's light forward axis into the light's down axis, the way directional lights
+    /// shine.
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_arc(Vec3::NEG_Z, self.coordinates.position(Vec3::NEG_Z))
+    }
+}
+
+/// Reads the `lights`, with their tracks' keys from `sources` as in `TrackLayout::read_track`.
+pub fn read_lights(
+    m2: &[u8],
+    lights: ArrayRef,
+    sources: &[Option<&[u8]>],
+    coordinates: CoordinateSystem,
+) -> Result<Vec<M2Light>> {
+    let mut reader = Cursor::new(m2);
+
+    (0..lights.count as u64)
+        .map(|i| {
+            let start = lights.offset as u64 + i * LIGHT_SIZE;
+            reader.seek(SeekFrom::Start(start))?;
+            let light_type = LightType::from_raw(reader.read_u16::<LittleEndian>()?);
+            let bone = usize::try_from(reader.read_i16::<LittleEndian>()?).ok();
+            reader.seek(SeekFrom::Start(start + LIGHT_POSITION))?;
+            let position = coordinates.position(read_vec3(&mut reader)?);
+
+            let track = |index: u64| start + LIGHT_TRACKS + TRACK_SIZE * index;
+            let read_color = |reader: &mut Cursor<&[u8]>, index: u64| {
+                TrackLayout::read(reader, track(index))?.read_track(m2, sources, 12, read_vec3)
+            };
+            let read_f32 = |reader: &mut Cursor<&[u8]>, index: u64| {
+                TrackLayout::read(reader, track(index))?.read_track(m2, sources, 4, |reader| {
+                    Ok(reader.read_f32::<LittleEndian>()?)
+                })
+            };
+            let tracks = LightTracks {
+                ambient_color: read_color(&mut reader, 0)?,
+                ambient_intensity: read_f32(&mut reader, 1)?,
+                diffuse_color: read_color(&mut reader, 2)?,
+                diffuse_intensity: read_f32(&mut reader, 3)?,
+                attenuation_start: read_f32(&mut reader, 4)?,
+                attenuation_end: read_f32(&mut reader, 5)?,
+                visible: TrackLayout::read(&mut reader, track(6))?.read_track(
+                    m2,
+                    sources,
+                    1,
+                    |reader| Ok(reader.read_u8()? as f32),
+                )?,
+            };
+
+            Ok(M2Light {
+                light_type,
+                bone,
+                position,
+                tracks,
+                coordinates,
+            })
+        })
+        .collect()
+}
+
+/// Copied into the Bevy light by `update_lights`.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct M2LightState {
+    /// Kept for reference, Bevy lights have no ambient term of their own.
+    pub ambient_color: Vec3,
+    pub ambient_intensity: f32,
+    pub diffuse_color: Vec3,
+    pub diffuse_intensity: f32,
+    pub attenuation_start: f32,
+    pub attenuation_end: f32,
+    pub visible: f32,
+}
+
+impl Default for M2LightState {
+    fn default() -> Self {
+        Self {
+            ambient_color: Vec3::ZERO,
+            ambient_intensity: 0.,
+            diffuse_color: Vec3::ONE,
+            diffuse_intensity: 1.,
+            attenuation_start: 0.,
+            attenuation_end: 0.,
+            visible: 1.,
+        }
+    }
+}
+
+impl M2LightState {
+    pub fn at_rest(tracks: &LightTracks) -> Self {
+        let rest = Self::default();
+        Self {
+            ambient_color: tracks.ambient_color.rest_value(rest.ambient_color),
+            ambient_intensity: tracks.ambient_intensity.rest_value(rest.ambient_intensity),
+            diffuse_color: tracks.diffuse_color.rest_value(rest.diffuse_color),
+            diffuse_intensity: tracks.diffuse_intensity.rest_value(rest.diffuse_intensity),
+            attenuation_start: tracks.attenuation_start.rest_value(rest.attenuation_start),
+            attenuation_end: tracks.attenuation_end.rest_value(rest.attenuation_end),
+            visible: tracks.visible.rest_value(rest.visible),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        Color::linear_rgb(
+            self.diffuse_color.x,
+            self.diffuse_color.y,
+            self.diffuse_color.z,
+        )
+    }
+
+    fn intensity(&self) -> f32 {
+        if self.visible > 0.5 {
+            self.diffuse_intensity.max(0.)
+        } else {
+            0.
+        }
+    }
+
+    /// Luminous power, in lumens, of a point light giving `LIGHT_ILLUMINANCE` times its
+    /// intensity at the attenuation start, with Bevy's inverse square falloff. Lights without
+    /// an attenuation start use half their range instead.
+    pub fn luminous_power(&self) -> f32 {
+        let start = if self.attenuation_start > 0. {
+            self.attenuation_start
+        } else {
+            self.attenuation_end * 0.5
+        };
+        self.intensity() * LIGHT_ILLUMINANCE * 4. * std::f32::consts::PI * start * start
+    }
+
+    pub fn illuminance(&self) -> f32 {
+        self.intensity() * LIGHT_ILLUMINANCE
+    }
+
+    fn apply_to_point(&self, light: &mut PointLight) {
+        light.color = self.color();
+        light.intensity = self.luminous_power();
+        light.range = self.attenuation_end.max(0.);
+    }
+
+    fn apply_to_directional(&self, light: &mut DirectionalLight) {
+        light.color = self.color();
+        light.illuminance = self.illuminance();
+    }
+}
+
+/// Spawns a Bevy point or directional light per model light.
+pub fn spawn_lights(
+    commands: &mut Commands,
+    root: Entity,
+    skeleton: &M2Skeleton,
+    joints: &[Entity],
+    lights: &[M2Light],
+) -> Vec<Entity> {
+    lights
+        .iter()
+        .enumerate()
+        .map(|(i, light)| {
+            let (parent, translation) =
+                skeleton.parent_of(joints, root, light.bone, light.position);
+            let state = M2LightState::at_rest(&light.tracks);
+            let mut entity = commands.spawn((
+                M2Light::name(i),
+                state,
+                AnimationTarget {
+                    id: M2Light::target_id(i),
+                    player: root,
+                },
+                ChildOf(parent),
+            ));
+            match light.light_type {
+                LightType::Point => {
+                    let mut point = PointLight::default();
+                    state.apply_to_point(&mut point);
+                    entity.insert((point, Transform::from_translation(translation)));
+                }
+                LightType::Directional => {
+                    let mut directional = DirectionalLight::default();
+                    state.apply_to_directional(&mut directional);
+                    entity.insert((
+                        directional,
+                        Transform::from_translation(translation).with_rotation(light.rotation()),
+                    ));
+                }
+            }
+            entity.id()
+        })
+        .collect()
+}
+
+pub fn add_light_curves(
+    clip: &mut AnimationClip,
+    lights: &[M2Light],
+    sequence: usize,
+    global_sequences: &[u32],
+) {
+    for (index, light) in lights.iter().enumerate() {
+        let target = M2Light::target_id(index);
+        let tracks = &light.tracks;
+        let rest = M2LightState::at_rest(tracks);
+
+        add_track_channels!(
+            clip, target, M2LightState, tracks, rest, sequence, global_sequences;
+            ambient_color,
+            ambient_intensity,
+            diffuse_color,
+            diffuse_intensity,
+            attenuation_start,
+            attenuation_end,
+            visible
+        );
+    }
+}
+
+/// Copies the animated light parameters into the Bevy lights.
+pub fn update_lights(
+    mut lights: Query<
+        (
+            &M2LightState,
+            Option<&mut PointLight>,
+            Option<&mut DirectionalLight>,
+        ),
+        Changed<M2LightState>,
+    >,
+) {
+    for (state, point, directional) in &mut lights {
+        if let Some(mut point) = point {
+            state.apply_to_point(&mut point);
+        }
+        if let Some(mut directional) = directional {
+            state.apply_to_directional(&mut directional);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::m2::anim::write_bytes;
+
+    #[test]
+    fn reads_light_definitions() {
+        let mut m2 = vec![0u8; 0x300];
+        let start = 0x140;
+        let array = ArrayRef {
+            count: 2,
+            offset: start as u32,
+        };
+        write_bytes(&mut m2, start, &[1, 0, 2, 0]);
+        write_bytes(
+            &mut m2,
+            start + LIGHT_POSITION as usize + 4,
+            &3f32.to_le_bytes(),
+        );
+        write_bytes(&mut m2, start + LIGHT_SIZE as usize, &[0, 0, 0xff, 0xff]);
+
+        let lights = read_lights(&m2, array, &[], CoordinateSystem::YUp).unwrap();
+        assert_eq!(lights.len(), 2);
+        assert_eq!(lights[0].light_type, LightType::Point);
+        assert_eq!(lights[0].bone, Some(2));
+        assert_eq!(lights[0].position, Vec3::new(0., 0., -3.));
+        assert_eq!(lights[1].light_type, LightType::Directional);
+        assert_eq!(lights[1].bone, None);
+        assert_eq!(
+            M2LightState::at_rest(&lights[1].tracks),
+            M2LightState::default()
+        );
+    }
+
+    #[test]
+    fn point_lights_reach_full_strength_at_the_attenuation_start() {
+        let state = M2LightState {
+            diffuse_intensity: 0.5,
+            attenuation_start: 2.,
+            attenuation_end: 6.,
+            ..default()
+        };
+        let mut light = PointLight::default();
+        state.apply_to_point(&mut light);
+
+        let candela = light.intensity / (4. * std::f32::consts::PI);
+        let illuminance = candela / (state.attenuation_start * state.attenuation_start);
+        assert!((illuminance - 0.5 * LIGHT_ILLUMINANCE).abs() < 1e-2);
+        assert_eq!(light.range, 6.);
+
+        let hidden = M2LightState {
+            visible: 0.,
+            ..state
+        };
+        assert_eq!(hidden.luminous_power(), 0.);
+        assert_eq!(hidden.illuminance(), 0.);
+    }
+
+    #[test]
+    fn directional_lights_shine_down() {
+        let light = M2Light {
+            light_type: LightType::Directional,
+            coordinates: CoordinateSystem::YUp,
+            ..default()
+        };
+        assert!((light.rotation() * Vec3::NEG_Z).abs_diff_eq(Vec3::NEG_Y, 1e-6));
+    }
+}
@@ -0,0 +1,453 @@
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    prelude::*,
+    render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_resource::{
+            AsBindGroup, BlendComponent, BlendFactor, BlendOperation, BlendState, CompareFunction,
+            Face, RenderPipelineDescriptor, ShaderRef, ShaderType, SpecializedMeshPipelineError,
+        },
+    },
+};
+use bitflags::bitflags;
+use std::result::Result as StdResult;
+
+/// Registered by `M2Plugin` as an embedded asset.
+pub const SHADER_PATH: &str = "embedded://wow_vr_lib/m2/material.wgsl";
+
+/// Most textures a single draw can combine, the 3.3.5 combiners blend at most two.
+pub const MAX_TEXTURES: usize = 2;
+
+/// Alpha the client tests against for `BlendMode::AlphaKey`.
+pub const ALPHA_KEY_CUTOFF: f32 = 224. / 255.;
+
+/// Sort offsets of transparent draws, small enough to only matter between the draws of one model.
+const PRIORITY_PLANE_BIAS: f32 = 1e-2;
+const DRAW_ORDER_BIAS: f32 = 1e-5;
+
+/// How a draw is blended into the frame, `blend_mode` of an M2 material. Values are shared with
+/// `material.wgsl`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+#[repr(u32)]
+pub enum BlendMode {
+    #[default]
+    Opaque = 0,
+    AlphaKey = 1,
+    Alpha = 2,
+    /// Additive, ignoring the source alpha.
+    NoAlphaAdd = 3,
+    Add = 4,
+    Mod = 5,
+    Mod2x = 6,
+    /// Premultiplied alpha.
+    BlendAdd = 7,
+}
+
+impl BlendMode {
+    /// Unknown modes draw opaque.
+    pub fn from_raw(blend_mode: u16) -> Self {
+        match blend_mode {
+            1 => Self::AlphaKey,
+            2 => Self::Alpha,
+            3 => Self::NoAlphaAdd,
+            4 => Self::Add,
+            5 => Self::Mod,
+            6 => Self::Mod2x,
+            7 => Self::BlendAdd,
+            _ => Self::Opaque,
+        }
+    }
+
+    /// The closest Bevy mode. It also decides the render phase, so `Mod2x` is drawn as
+    /// `Multiply` with its blend state replaced in `M2MaterialExtension::specialize`.
+    pub fn alpha_mode(self) -> AlphaMode {
+        match self {
+            Self::Opaque => AlphaMode::Opaque,
+            Self::AlphaKey => AlphaMode::Mask(ALPHA_KEY_CUTOFF),
+            Self::Alpha => AlphaMode::Blend,
+            Self::NoAlphaAdd | Self::Add => AlphaMode::Add,
+            Self::Mod | Self::Mod2x => AlphaMode::Multiply,
+            Self::BlendAdd => AlphaMode::Premultiplied,
+        }
+    }
+
+    pub fn is_transparent(self) -> bool {
+        !matches!(self, Self::Opaque | Self::AlphaKey)
+    }
+}
+
+bitflags! {
+    /// `flags` of an M2 material.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct RenderFlags: u16 {
+        const UNLIT = 0x01;
+        const UNFOGGED = 0x02;
+        const TWO_SIDED = 0x04;
+        const NO_DEPTH_TEST = 0x08;
+        const NO_DEPTH_WRITE = 0x10;
+    }
+}
+
+/// Offsets a draw's sort distance so the transparent draws of a skin keep the client's order:
+/// by priority plane first, then by texture unit order.
+pub fn sort_bias(priority_plane: i8, order: usize) -> f32 {
+    priority_plane as f32 * PRIORITY_PLANE_BIAS + order as f32 * DRAW_ORDER_BIAS
+}
+
+/// The base material of an M2 draw, everything the combiners leave to `StandardMaterial`.
+pub fn base_material(
+    blend_mode: BlendMode,
+    flags: RenderFlags,
+    depth_bias: f32,
+) -> StandardMaterial {
+    let two_sided = flags.contains(RenderFlags::TWO_SIDED);
+    StandardMaterial {
+        double_sided: two_sided,
+        cull_mode: if two_sided { None } else { Some(Face::Back) },
+        unlit: flags.contains(RenderFlags::UNLIT),
+        fog: !flags.contains(RenderFlags::UNFOGGED),
+        alpha_mode: blend_mode.alpha_mode(),
+        depth_bias,
+        ..default()
+    }
+}
+
+/// Pixel shader combos of the 3.3.5 client, how the textures of a draw are blended with each
+/// other and with the diffuse color. Values are shared with `material.wgsl`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+#[repr(u32)]
+pub enum PixelShader {
+    Opaque = 0,
+    #[default]
+    Mod = 1,
+    OpaqueOpaque = 2,
+    OpaqueMod = 3,
+    OpaqueAddAlpha = 4,
+    OpaqueMod2x = 5,
+    OpaqueMod2xNA = 6,
+    ModOpaque = 7,
+    ModMod = 8,
+    ModAdd = 9,
+    ModMod2x = 10,
+    ModMod2xNA = 11,
+    ModAddNA = 12,
+    OpaqueMod2xNAAlpha = 13,
+    OpaqueAddAlphaAlpha = 14,
+}
+
+/// Where a texture takes its coordinates from, picked by the vertex shader combo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+#[repr(u32)]
+pub enum TexCoord {
+    #[default]
+    T1 = 0,
+    T2 = 1,
+    /// Sphere mapped from the view space normal.
+    Env = 2,
+}
+
+/// The shader combo a texture unit's `shader_id` stands for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShaderEffect {
+    pub pixel: PixelShader,
+    pub coords: [TexCoord; 2],
+}
+
+impl ShaderEffect {
+    /// Decodes the shader ID the way the client does. The low byte packs one nibble per texture,
+    /// bit 3 of a nibble selects environment mapping and the lower bits the blend op, and IDs
+    /// with the high bit set index a table of special effects.
+    pub fn from_shader_id(shader_id: u16, texture_count: u16) -> Self {
+        if shader_id & 0x8000 != 0 {
+            let pixel = match shader_id & 0x7fff {
+                0 => PixelShader::OpaqueMod2xNAAlpha,
+                1 => PixelShader::OpaqueAddAlpha,
+                2 => PixelShader::OpaqueAddAlphaAlpha,
+                _ => PixelShader::Mod,
+            };
+            return Self {
+                pixel,
+                coords: [TexCoord::T1, TexCoord::Env],
+            };
+        }
+
+        let first_mod = shader_id & 0x70 != 0;
+        let first_env = shader_id & 0x80 != 0;
+        let second_env = shader_id & 0x08 != 0;
+        let second_t2 = shader_id & 0x4000 != 0;
+
+        if texture_count <= 1 {
+            return Self {
+                pixel: if first_mod {
+                    PixelShader::Mod
+                } else {
+                    PixelShader::Opaque
+                },
+                coords: [
+                    if first_env {
+                        TexCoord::Env
+                    } else if second_t2 {
+                        TexCoord::T2
+                    } else {
+                        TexCoord::T1
+                    },
+                    TexCoord::T1,
+                ],
+            };
+        }
+
+        let pixel = match (first_mod, shader_id & 0x07) {
+            (false, 0) => PixelShader::OpaqueOpaque,
+            (false, 3 | 7) => PixelShader::OpaqueAddAlpha,
+            (false, 4) => PixelShader::OpaqueMod2x,
+            (false, 6) => PixelShader::OpaqueMod2xNA,
+            (false, _) => PixelShader::OpaqueMod,
+            (true, 0) => PixelShader::ModOpaque,
+            (true, 3) => PixelShader::ModAdd,
+            (true, 4) => PixelShader::ModMod2x,
+            (true, 6) => PixelShader::ModMod2xNA,
+            (true, 7) => PixelShader::ModAddNA,
+            (true, _) => PixelShader::ModMod,
+        };
+
+        let first = if first_env {
+            TexCoord::Env
+        } else {
+            TexCoord::T1
+        };
+        let second = if second_env {
+            TexCoord::Env
+        } else if second_t2 && !first_env {
+            TexCoord::T2
+        } else {
+            TexCoord::T1
+        };
+
+        Self {
+            pixel,
+            coords: [first, second],
+        }
+    }
+}
+
+#[derive(ShaderType, Debug, Clone, Copy, Reflect)]
+pub struct M2Combiner {
+    pub pixel_shader: u32,
+    pub texture_count: u32,
+    pub blend_mode: u32,
+    /// A `TexCoord` per texture.
+    pub coords: UVec4,
+    /// UV matrix of the first two textures, animated by `M2TextureAnimation`.
+    pub transforms: [Mat3; 2],
+}
+
+impl M2Combiner {
+    pub fn new(effect: ShaderEffect, texture_count: usize, blend_mode: BlendMode) -> Self {
+        Self {
+            pixel_shader: effect.pixel as u32,
+            texture_count: texture_count.min(MAX_TEXTURES) as u32,
+            blend_mode: blend_mode as u32,
+            coords: UVec4::new(effect.coords[0] as u32, effect.coords[1] as u32, 0, 0),
+            transforms: [Mat3::IDENTITY; 2],
+        }
+    }
+}
+
+impl Default for M2Combiner {
+    fn default() -> Self {
+        Self::new(ShaderEffect::default(), 0, BlendMode::default())
+    }
+}
+
+/// The parts of an M2 material that need their own pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct M2MaterialKey {
+    blend_mode: BlendMode,
+    depth_test: bool,
+    depth_write: bool,
+}
+
+impl From<&M2MaterialExtension> for M2MaterialKey {
+    fn from(extension: &M2MaterialExtension) -> Self {
+        Self {
+            blend_mode: extension.blend_mode,
+            depth_test: !extension.render_flags.contains(RenderFlags::NO_DEPTH_TEST),
+            depth_write: !extension.render_flags.contains(RenderFlags::NO_DEPTH_WRITE),
+        }
+    }
+}
+
+/// Combines up to two textures on top of a `StandardMaterial`, which keeps handling lighting,
+/// culling and blending. The base material's color is the diffuse color the combos modulate.
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone, Default)]
+#[bind_group_data(M2MaterialKey)]
+pub struct M2MaterialExtension {
+    #[uniform(100)]
+    pub combiner: M2Combiner,
+    pub blend_mode: BlendMode,
+    #[reflect(ignore)]
+    pub render_flags: RenderFlags,
+    #[texture(101)]
+    #[sampler(102)]
+    pub texture_0: Option<Handle<Image>>,
+    #[texture(103)]
+    #[sampler(104)]
+    pub texture_1: Option<Handle<Image>>,
+}
+
+impl M2MaterialExtension {
+    pub fn new(
+        effect: ShaderEffect,
+        textures: &[Option<Handle<Image>>],
+        blend_mode: BlendMode,
+        render_flags: RenderFlags,
+    ) -> Self {
+        let combiner = M2Combiner::new(effect, textures.len(), blend_mode);
+        let mut textures = textures.iter().cloned().chain(std::iter::repeat(None));
+        Self {
+            combiner,
+            blend_mode,
+            render_flags,
+            texture_0: textures.next().flatten(),
+            texture_1: textures.next().flatten(),
+        }
+    }
+}
+
+impl MaterialExtension for M2MaterialExtension {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        key: MaterialExtensionKey<Self>,
+    ) -> StdResult<(), SpecializedMeshPipelineError> {
+        let key = key.bind_group_data;
+
+        if let Some(depth_stencil) = &mut descriptor.depth_stencil {
+            if !key.depth_test {
+                depth_stencil.depth_compare = CompareFunction::Always;
+            }
+            if !key.depth_write {
+                depth_stencil.depth_write_enabled = false;
+            }
+        }
+
+        // Bevy has no 2x modulation, the shader leaves grey where the draw should be invisible.
+        if key.blend_mode == BlendMode::Mod2x {
+            let blend = BlendComponent {
+                src_factor: BlendFactor::Dst,
+                dst_factor: BlendFactor::Src,
+                operation: BlendOperation::Add,
+            };
+            let targets = descriptor
+                .fragment
+                .iter_mut()
+                .flat_map(|fragment| &mut fragment.targets);
+            for target in targets.flatten() {
+                target.blend = Some(BlendState {
+                    color: blend,
+                    alpha: blend,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub type M2Material = ExtendedMaterial<StandardMaterial, M2MaterialExtension>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_single_texture_shaders() {
+        let opaque = ShaderEffect::from_shader_id(0x0000, 1);
+        assert_eq!(opaque.pixel, PixelShader::Opaque);
+        assert_eq!(opaque.coords[0], TexCoord::T1);
+
+        let env = ShaderEffect::from_shader_id(0x0090, 1);
+        assert_eq!(env.pixel, PixelShader::Mod);
+        assert_eq!(env.coords[0], TexCoord::Env);
+
+        assert_eq!(
+            ShaderEffect::from_shader_id(0x4000, 1).coords[0],
+            TexCoord::T2
+        );
+    }
+
+    #[test]
+    fn decodes_two_texture_shaders() {
+        let glint = ShaderEffect::from_shader_id(0x0019, 2);
+        assert_eq!(glint.pixel, PixelShader::ModMod);
+        assert_eq!(glint.coords, [TexCoord::T1, TexCoord::Env]);
+
+        let glow = ShaderEffect::from_shader_id(0x0003, 2);
+        assert_eq!(glow.pixel, PixelShader::OpaqueAddAlpha);
+
+        let runes = ShaderEffect::from_shader_id(0x4014, 2);
+        assert_eq!(runes.pixel, PixelShader::ModMod2x);
+        assert_eq!(runes.coords, [TexCoord::T1, TexCoord::T2]);
+
+        let special = ShaderEffect::from_shader_id(0x8002, 2);
+        assert_eq!(special.pixel, PixelShader::OpaqueAddAlphaAlpha);
+        assert_eq!(special.coords, [TexCoord::T1, TexCoord::Env]);
+    }
+
+    #[test]
+    fn missing_textures_are_left_unbound() {
+        let extension = M2MaterialExtension::new(
+            ShaderEffect::from_shader_id(0x0011, 2),
+            &[None, Some(Handle::default())],
+            BlendMode::Alpha,
+            RenderFlags::empty(),
+        );
+        assert_eq!(extension.combiner.texture_count, 2);
+        assert_eq!(extension.combiner.pixel_shader, PixelShader::ModMod as u32);
+        assert!(extension.texture_0.is_none());
+        assert!(extension.texture_1.is_some());
+
+        let layered = M2Combiner::new(
+            ShaderEffect::from_shader_id(0x4014, 4),
+            4,
+            BlendMode::Opaque,
+        );
+        assert_eq!(layered.texture_count, MAX_TEXTURES as u32);
+    }
+
+    #[test]
+    fn blend_modes_pick_a_render_phase() {
+        assert_eq!(
+            BlendMode::from_raw(1).alpha_mode(),
+            AlphaMode::Mask(ALPHA_KEY_CUTOFF)
+        );
+        assert_eq!(
+            BlendMode::from_raw(7).alpha_mode(),
+            AlphaMode::Premultiplied
+        );
+        assert_eq!(BlendMode::from_raw(42), BlendMode::Opaque);
+        assert!(!BlendMode::AlphaKey.is_transparent());
+        assert!(BlendMode::Mod2x.is_transparent());
+
+        let key = M2MaterialKey::from(&M2MaterialExtension::new(
+            ShaderEffect::default(),
+            &[],
+            BlendMode::Add,
+            RenderFlags::UNLIT | RenderFlags::NO_DEPTH_WRITE,
+        ));
+        assert!(key.depth_test);
+        assert!(!key.depth_write);
+    }
+
+    #[test]
+    fn priority_planes_sort_before_draw_order() {
+        assert!(sort_bias(0, 500) < sort_bias(1, 0));
+        assert!(sort_bias(-1, 0) < sort_bias(0, 0));
+        assert!(sort_bias(2, 3) < sort_bias(2, 4));
+    }
+}
@@ -0,0 +1,128 @@
+// Texture combiners of the 3.3.5 M2 shaders, on top of the standard PBR fragment. Pixel shader,
+// texture coordinate and blend mode values match `PixelShader`, `TexCoord` and `BlendMode` in
+// `material.rs`.
+
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_view_bindings::view,
+}
+
+struct M2Combiner {
+    pixel_shader: u32,
+    texture_count: u32,
+    blend_mode: u32,
+    coords: vec4<u32>,
+    transforms: array<mat3x3<f32>, 2>,
+}
+
+@group(2) @binding(100) var<uniform> combiner: M2Combiner;
+@group(2) @binding(101) var texture_0: texture_2d<f32>;
+@group(2) @binding(102) var sampler_0: sampler;
+@group(2) @binding(103) var texture_1: texture_2d<f32>;
+@group(2) @binding(104) var sampler_1: sampler;
+
+const COORD_T2: u32 = 1u;
+const COORD_ENV: u32 = 2u;
+
+const BLEND_NO_ALPHA_ADD: u32 = 3u;
+const BLEND_MOD2X: u32 = 6u;
+
+// Sphere map lookup from the reflection vector in view space, like the fixed function pipeline.
+fn env_coords(in: VertexOutput) -> vec2<f32> {
+    let position = (view.view_from_world * in.world_position).xyz;
+    let normal = normalize((view.view_from_world * vec4(in.world_normal, 0.0)).xyz);
+    let r = reflect(normalize(position), normal);
+    let m = 2.0 * sqrt(r.x * r.x + r.y * r.y + (r.z + 1.0) * (r.z + 1.0));
+    return vec2(r.x / m + 0.5, 0.5 - r.y / m);
+}
+
+fn tex_coords(in: VertexOutput, source: u32) -> vec2<f32> {
+    if source == COORD_ENV {
+        return env_coords(in);
+    }
+#ifdef VERTEX_UVS_B
+    if source == COORD_T2 {
+        return in.uv_b;
+    }
+#endif
+#ifdef VERTEX_UVS_A
+    return in.uv;
+#else
+    return vec2(0.0);
+#endif
+}
+
+fn combine(diffuse: vec4<f32>, t0: vec4<f32>, t1: vec4<f32>) -> vec4<f32> {
+    let v = diffuse.rgb;
+    let a = diffuse.a;
+    switch combiner.pixel_shader {
+        // Opaque
+        case 0u: { return vec4(v * t0.rgb, a); }
+        // Mod
+        case 1u: { return vec4(v * t0.rgb, a * t0.a); }
+        // Opaque_Opaque
+        case 2u: { return vec4(v * t0.rgb * t1.rgb, a); }
+        // Opaque_Mod
+        case 3u: { return vec4(v * t0.rgb * t1.rgb, a * t1.a); }
+        // Opaque_AddAlpha
+        case 4u: { return vec4(v * t0.rgb + t1.rgb * t1.a, a); }
+        // Opaque_Mod2x
+        case 5u: { return vec4(v * t0.rgb * t1.rgb * 2.0, a * t1.a * 2.0); }
+        // Opaque_Mod2xNA
+        case 6u: { return vec4(v * t0.rgb * t1.rgb * 2.0, a); }
+        // Mod_Opaque
+        case 7u: { return vec4(v * t0.rgb * t1.rgb, a * t0.a); }
+        // Mod_Mod
+        case 8u: { return vec4(v * t0.rgb * t1.rgb, a * t0.a * t1.a); }
+        // Mod_Add
+        case 9u: { return vec4(v * t0.rgb + t1.rgb, a * (t0.a + t1.a)); }
+        // Mod_Mod2x
+        case 10u: { return vec4(v * t0.rgb * t1.rgb * 2.0, a * t0.a * t1.a * 2.0); }
+        // Mod_Mod2xNA
+        case 11u: { return vec4(v * t0.rgb * t1.rgb * 2.0, a * t0.a); }
+        // Mod_AddNA
+        case 12u: { return vec4(v * t0.rgb + t1.rgb, a * t0.a); }
+        // Opaque_Mod2xNA_Alpha
+        case 13u: { return vec4(v * mix(t0.rgb * t1.rgb * 2.0, t0.rgb, t0.a), a); }
+        // Opaque_AddAlpha_Alpha
+        case 14u: { return vec4(v * t0.rgb + t1.rgb * t1.a * (1.0 - t0.a), a); }
+        default: { return vec4(v * t0.rgb, a * t0.a); }
+    }
+}
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    let uv_0 = combiner.transforms[0] * vec3(tex_coords(in, combiner.coords.x), 1.0);
+    let t0 = textureSample(texture_0, sampler_0, uv_0.xy);
+    var t1 = vec4(0.0);
+    if combiner.texture_count > 1u {
+        let uv_1 = combiner.transforms[1] * vec3(tex_coords(in, combiner.coords.y), 1.0);
+        t1 = textureSample(texture_1, sampler_1, uv_1.xy);
+    }
+    pbr_input.material.base_color = combine(pbr_input.material.base_color, t0, t1);
+    if combiner.blend_mode == BLEND_NO_ALPHA_ADD {
+        pbr_input.material.base_color.a = 1.0;
+    }
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    // Mod2x blends as dst * src * 2, where grey leaves the frame unchanged.
+    if combiner.blend_mode == BLEND_MOD2X {
+        out.color = vec4(mix(vec3(0.5), out.color.rgb, out.color.a), 1.0);
+    }
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,953 @@
+use std::io::{Cursor, Seek, SeekFrom};
+use std::sync::Arc;
+
+use bevy::{
+    math::VectorSpace,
+    prelude::*,
+    render::{mesh::Indices, view::VisibilitySystems},
+    transform::TransformSystem,
+};
+use bevy_animation::{
+    AnimationClip, AnimationTarget, AnimationTargetId, animation_curves::AnimatedField,
+};
+use bitflags::bitflags;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{
+    CoordinateSystem,
+    anim::{ArrayRef, TRACK_SIZE, TrackLayout, read_fixed16, read_vec3},
+    animation::{Track, add_track_channels},
+    dynamic_draw::{show_dynamic_draw, spawn_dynamic_draw},
+    material::{
+        BlendMode, M2Material, M2MaterialExtension, PixelShader, RenderFlags, ShaderEffect,
+        TexCoord, base_material,
+    },
+    skeleton::M2Skeleton,
+};
+use crate::errors::Result;
+
+const PARTICLE_SIZE: u64 = 0x1dc;
+const PARTICLE_FLAGS: u64 = 0x04;
+const PARTICLE_POSITION: u64 = 0x08;
+const PARTICLE_BONE: u64 = 0x14;
+const PARTICLE_BLENDING: u64 = 0x28;
+const PARTICLE_ROWS: u64 = 0x30;
+const PARTICLE_SPEED: u64 = 0x34;
+const PARTICLE_LIFESPAN: u64 = 0x98;
+const PARTICLE_LIFESPAN_VARIATION: u64 = 0xac;
+const PARTICLE_RATE: u64 = 0xb0;
+const PARTICLE_RATE_VARIATION: u64 = 0xc4;
+const PARTICLE_AREA: u64 = 0xc8;
+const PARTICLE_COLOR: u64 = 0x104;
+const PARTICLE_SCALE_VARIATION: u64 = 0x134;
+const PARTICLE_HEAD_CELL: u64 = 0x13c;
+const PARTICLE_DRAG: u64 = 0x174;
+const PARTICLE_ENABLED: u64 = 0x1c8;
+const LIFE_CURVE_SIZE: u64 = 16;
+
+/// Particles alive at once per emitter, past which emission waits for old ones to die.
+pub const MAX_PARTICLES: usize = 1000;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ParticleFlags: u32 {
+        /// Lit by the scene, the others are fullbright.
+        const LIT = 0x1;
+        /// Particles move along with the emitter instead of trailing behind it.
+        const FOLLOW_EMITTER = 0x10;
+        /// Quads lie in the emitter's XY plane instead of facing the camera.
+        const XY_QUAD = 0x1000;
+        /// Every particle keeps a random texture tile instead of following the head cell curve.
+        const RANDOM_TILE = 0x10000;
+    }
+}
+
+/// Where an emitter spawns its particles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmitterType {
+    /// On a rectangle of the emitter's XY plane, heading up.
+    #[default]
+    Plane,
+    /// On a sphere shell around the emitter, heading outwards.
+    Sphere,
+    /// Along a spline, emitted from the emitter itself here.
+    Spline,
+    /// At the emitter's bone.
+    Bone,
+}
+
+impl EmitterType {
+    pub fn from_raw(emitter_type: u8) -> Self {
+        match emitter_type {
+            2 => Self::Sphere,
+            3 => Self::Spline,
+            4 => Self::Bone,
+            _ => Self::Plane,
+        }
+    }
+}
+
+/// A value over the life of a particle, keyed by the fraction of its lifespan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LifeCurve<T> {
+    pub times: Vec<f32>,
+    pub values: Vec<T>,
+}
+
+impl<T: VectorSpace> LifeCurve<T> {
+    /// The value at `life`, between 0 and 1, or `default` for empty curves.
+    pub fn sample(&self, life: f32, default: T) -> T {
+        let Some(last) = self.times.len().checked_sub(1) else {
+            return default;
+        };
+        let next = self.times.partition_point(|time| *time <= life);
+        if next == 0 {
+            return self.values[0];
+        }
+        if next > last {
+            return self.values[last];
+        }
+
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let t = if end > start {
+            (life - start) / (end - start)
+        } else {
+            0.
+        };
+        self.values[next - 1].lerp(self.values[next], t)
+    }
+}
+
+/// The animated emission parameters of an emitter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EmitterTracks {
+    pub speed: Track<f32>,
+    pub speed_variation: Track<f32>,
+    pub vertical_range: Track<f32>,
+    pub horizontal_range: Track<f32>,
+    pub gravity: Track<f32>,
+    pub lifespan: Track<f32>,
+    pub rate: Track<f32>,
+    pub area_length: Track<f32>,
+    pub area_width: Track<f32>,
+    pub z_source: Track<f32>,
+    pub enabled: Track<f32>,
+}
+
+impl EmitterTracks {
+    pub fn tracks_mut(&mut self) -> [&mut Track<f32>; 11] {
+        [
+            &mut self.speed,
+            &mut self.speed_variation,
+            &mut self.vertical_range,
+            &mut self.horizontal_range,
+            &mut self.gravity,
+            &mut self.lifespan,
+            &mut self.rate,
+            &mut self.area_length,
+            &mut self.area_width,
+            &mut self.z_source,
+            &mut self.enabled,
+        ]
+    }
+}
+
+/// One particle emitter of the model.
+#[derive(Debug, Clone, Default)]
+pub struct M2ParticleEmitter {
+    pub flags: ParticleFlags,
+    pub position: Vec3,
+    pub bone: Option<usize>,
+    pub texture: usize,
+    pub blend_mode: BlendMode,
+    pub emitter_type: EmitterType,
+    /// Tiles of the texture, as columns and rows.
+    pub tiles: UVec2,
+    pub lifespan_variation: f32,
+    pub rate_variation: f32,
+    /// Color over life, from 0 to 1.
+    pub color: LifeCurve<Vec3>,
+    pub alpha: LifeCurve<f32>,
+    /// Half extents of the quad over life.
+    pub scale: LifeCurve<Vec2>,
+    pub scale_variation: Vec2,
+    pub head_cell: LifeCurve<f32>,
+    pub drag: f32,
+    pub base_spin: f32,
+    pub base_spin_variation: f32,
+    pub spin: f32,
+    pub spin_variation: f32,
+    pub tracks: EmitterTracks,
+    /// Axis convention the emission directions are converted to.
+    pub coordinates: CoordinateSystem,
+    /// Set by the loader once the texture is known.
+    pub material: Handle<M2Material>,
+}
+
+impl M2ParticleEmitter {
+    /// Names the emitter for its `AnimationTarget`.
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("particle_emitter{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+
+    pub fn tile_count(&self) -> u32 {
+        self.tiles.x.max(1) * self.tiles.y.max(1)
+    }
+
+    /// Texture coordinates of the top left and bottom right corners of `tile`.
+    pub fn tile_uvs(&self, tile: u32) -> (Vec2, Vec2) {
+        let tiles = self.tiles.max(UVec2::ONE);
+        let tile = tile % self.tile_count();
+        let size = Vec2::ONE / tiles.as_vec2();
+        let start = UVec2::new(tile % tiles.x, tile / tiles.x).as_vec2() * size;
+        (start, start + size)
+    }
+
+    /// The material of the particles, two-sided and tinted by their vertex colors, fullbright
+    /// unless the emitter is lit.
+    pub fn build_material(&self, texture: Option<Handle<Image>>) -> M2Material {
+        let mut flags = RenderFlags::TWO_SIDED;
+        if !self.flags.contains(ParticleFlags::LIT) {
+            flags |= RenderFlags::UNLIT;
+        }
+        let effect = ShaderEffect {
+            pixel: PixelShader::Mod,
+            coords: [TexCoord::T1, TexCoord::T1],
+        };
+
+        M2Material {
+            base: base_material(self.blend_mode, flags, 0.),
+            extension: M2MaterialExtension::new(effect, &[texture], self.blend_mode, flags),
+        }
+    }
+}
+
+/// Reads the particle `emitters`, with their tracks' keys from `sources` as in
+/// `TrackLayout::read_track`. Materials are left to the caller.
+pub fn read_particle_emitters(
+    m2: &[u8],
+    emitters: ArrayRef,
+    sources: &[Option<&[u8]>],
+    coordinates: CoordinateSystem,
+) -> Result<Vec<M2ParticleEmitter>> {
+    let mut reader = Cursor::new(m2);
+
+    (0..emitters.count as u64)
+        .map(|i| {
+            let start = emitters.offset as u64 + i * PARTICLE_SIZE;
+            let read_f32_track = |reader: &mut Cursor<&[u8]>, position: u64| {
+                TrackLayout::read(reader, position)?.read_track(m2, sources, 4, |reader| {
+                    Ok(reader.read_f32::<LittleEndian>()?)
+                })
+            };
+
+            reader.seek(SeekFrom::Start(start + PARTICLE_FLAGS))?;
+            let flags = ParticleFlags::from_bits_truncate(reader.read_u32::<LittleEndian>()?);
+            reader.seek(SeekFrom::Start(start + PARTICLE_POSITION))?;
+            let position = coordinates.position(read_vec3(&mut reader)?);
+            reader.seek(SeekFrom::Start(start + PARTICLE_BONE))?;
+            let bone = reader.read_u16::<LittleEndian>()? as usize;
+            let texture = reader.read_u16::<LittleEndian>()? as usize;
+            reader.seek(SeekFrom::Start(start + PARTICLE_BLENDING))?;
+            let blend_mode = BlendMode::from_raw(reader.read_u8()? as u16);
+            let emitter_type = EmitterType::from_raw(reader.read_u8()?);
+            reader.seek(SeekFrom::Start(start + PARTICLE_ROWS))?;
+            let rows = reader.read_u16::<LittleEndian>()? as u32;
+            let columns = reader.read_u16::<LittleEndian>()? as u32;
+
+            let speed = start + PARTICLE_SPEED;
+            let area = start + PARTICLE_AREA;
+            let tracks = EmitterTracks {
+                speed: read_f32_track(&mut reader, speed)?,
+                speed_variation: read_f32_track(&mut reader, speed + TRACK_SIZE)?,
+                vertical_range: read_f32_track(&mut reader, speed + TRACK_SIZE * 2)?,
+                horizontal_range: read_f32_track(&mut reader, speed + TRACK_SIZE * 3)?,
+                gravity: read_f32_track(&mut reader, speed + TRACK_SIZE * 4)?,
+                lifespan: read_f32_track(&mut reader, start + PARTICLE_LIFESPAN)?,
+                rate: read_f32_track(&mut reader, start + PARTICLE_RATE)?,
+                area_length: read_f32_track(&mut reader, area)?,
+                area_width: read_f32_track(&mut reader, area + TRACK_SIZE)?,
+                z_source: read_f32_track(&mut reader, area + TRACK_SIZE * 2)?,
+                enabled: TrackLayout::read(&mut reader, start + PARTICLE_ENABLED)?.read_track(
+                    m2,
+                    sources,
+                    1,
+                    |reader| Ok(reader.read_u8()? as f32),
+                )?,
+            };
+
+            reader.seek(SeekFrom::Start(start + PARTICLE_LIFESPAN_VARIATION))?;
+            let lifespan_variation = reader.read_f32::<LittleEndian>()?;
+            reader.seek(SeekFrom::Start(start + PARTICLE_RATE_VARIATION))?;
+            let rate_variation = reader.read_f32::<LittleEndian>()?;
+
+            let color = start + PARTICLE_COLOR;
+            let color_curve =
+                read_life_curve(m2, color, 12, |reader| Ok(read_vec3(reader)? / 255.))?;
+            let alpha = read_life_curve(m2, color + LIFE_CURVE_SIZE, 2, read_fixed16)?;
+            let scale = read_life_curve(m2, color + LIFE_CURVE_SIZE * 2, 8, read_vec2)?;
+            reader.seek(SeekFrom::Start(start + PARTICLE_SCALE_VARIATION))?;
+            let scale_variation = read_vec2(&mut reader)?;
+            let head_cell = read_life_curve(m2, start + PARTICLE_HEAD_CELL, 2, |reader| {
+                Ok(reader.read_u16::<LittleEndian>()? as f32)
+            })?;
+
+            reader.seek(SeekFrom::Start(start + PARTICLE_DRAG))?;
+            let mut spin = [0.; 5];
+            reader.read_f32_into::<LittleEndian>(&mut spin)?;
+            let [drag, base_spin, base_spin_variation, spin, spin_variation] = spin;
+
+            Ok(M2ParticleEmitter {
+                flags,
+                position,
+                bone: Some(bone),
+                texture,
+                blend_mode,
+                emitter_type,
+                tiles: UVec2::new(columns, rows),
+                lifespan_variation,
+                rate_variation,
+                color: color_curve,
+                alpha,
+                scale,
+                scale_variation,
+                head_cell,
+                drag,
+                base_spin,
+                base_spin_variation,
+                spin,
+                spin_variation,
+                tracks,
+                coordinates,
+                material: Handle::default(),
+            })
+        })
+        .collect()
+}
+
+/// Reads a curve over the particles' life, keyed by fixed point fractions of it.
+fn read_life_curve<T>(
+    m2: &[u8],
+    position: u64,
+    value_size: usize,
+    read_value: impl Fn(&mut Cursor<&[u8]>) -> Result<T>,
+) -> Result<LifeCurve<T>> {
+    let mut reader = Cursor::new(m2);
+    let times = ArrayRef::read_at(&mut reader, position)?;
+    let values = ArrayRef::read_at(&mut reader, position + 8)?;
+    let count = times.count.min(values.count);
+
+    Ok(LifeCurve {
+        times: times.read_elements(m2, count, 2, |reader| {
+            Ok(reader.read_u16::<LittleEndian>()? as f32 / 32767.)
+        })?,
+        values: values.read_elements(m2, count, value_size, read_value)?,
+    })
+}
+
+fn read_vec2(reader: &mut Cursor<&[u8]>) -> Result<Vec2> {
+    Ok(Vec2::new(
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+    ))
+}
+
+/// The current emission parameters of a spawned emitter, animated by the model's clips.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq)]
+pub struct M2EmitterState {
+    pub speed: f32,
+    pub speed_variation: f32,
+    pub vertical_range: f32,
+    pub horizontal_range: f32,
+    pub gravity: f32,
+    pub lifespan: f32,
+    pub rate: f32,
+    pub area_length: f32,
+    pub area_width: f32,
+    pub z_source: f32,
+    /// Emits while above 0.5.
+    pub enabled: f32,
+}
+
+impl M2EmitterState {
+    /// The parameters outside of the keyed sequences, the first keys of each track.
+    pub fn at_rest(tracks: &EmitterTracks) -> Self {
+        Self {
+            speed: tracks.speed.rest_value(0.),
+            speed_variation: tracks.speed_variation.rest_value(0.),
+            vertical_range: tracks.vertical_range.rest_value(0.),
+            horizontal_range: tracks.horizontal_range.rest_value(0.),
+            gravity: tracks.gravity.rest_value(0.),
+            lifespan: tracks.lifespan.rest_value(1.),
+            rate: tracks.rate.rest_value(0.),
+            area_length: tracks.area_length.rest_value(0.),
+            area_width: tracks.area_width.rest_value(0.),
+            z_source: tracks.z_source.rest_value(0.),
+            enabled: tracks.enabled.rest_value(1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    /// In world space, or in emitter space for `ParticleFlags::FOLLOW_EMITTER`.
+    position: Vec3,
+    velocity: Vec3,
+    age: f32,
+    lifespan: f32,
+    scale: Vec2,
+    angle: f32,
+    angular_velocity: f32,
+    tile: Option<u32>,
+}
+
+/// Small xorshift generator, plenty to scatter particles.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self((seed ^ 0x9e37_79b9_7f4a_7c15).max(1))
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Uniform in `[-1, 1)`.
+    fn signed(&mut self) -> f32 {
+        self.unit() * 2. - 1.
+    }
+}
+
+/// The live particles of a spawned emitter. They are drawn by an entity below `root`, created
+/// on the first update.
+#[derive(Component, Debug)]
+pub struct M2ParticleSystem {
+    pub emitter: Arc<M2ParticleEmitter>,
+    pub root: Entity,
+    particles: Vec<Particle>,
+    /// Fraction of a particle carried over to the next frame.
+    pending: f32,
+    rng: Rng,
+    draw: Option<(Entity, Handle<Mesh>)>,
+}
+
+impl M2ParticleSystem {
+    pub fn new(emitter: Arc<M2ParticleEmitter>, root: Entity, seed: u64) -> Self {
+        Self {
+            emitter,
+            root,
+            particles: Vec::new(),
+            pending: 0.,
+            rng: Rng::new(seed),
+            draw: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Position and velocity of a new particle in the emitter's space, before conversion.
+    fn emit(&mut self, state: &M2EmitterState) -> (Vec3, Vec3) {
+        let rng = &mut self.rng;
+        let polar = rng.signed() * state.vertical_range;
+        let azimuth = rng.signed() * state.horizontal_range;
+        let (position, direction) = match self.emitter.emitter_type {
+            EmitterType::Sphere => {
+                let direction = Vec3::new(
+                    polar.sin() * azimuth.cos(),
+                    polar.sin() * azimuth.sin(),
+                    polar.cos(),
+                );
+                let radius =
+                    state.area_length + (state.area_width - state.area_length) * rng.unit();
+                (direction * radius, direction)
+            }
+            EmitterType::Plane => (
+                Vec3::new(
+                    rng.signed() * state.area_length * 0.5,
+                    rng.signed() * state.area_width * 0.5,
+                    0.,
+                ),
+                Quat::from_rotation_z(azimuth) * Quat::from_rotation_x(polar) * Vec3::Z,
+            ),
+            EmitterType::Spline | EmitterType::Bone => (
+                Vec3::ZERO,
+                Quat::from_rotation_z(azimuth) * Quat::from_rotation_x(polar) * Vec3::Z,
+            ),
+        };
+
+        // A Z source makes particles fly away from that point instead.
+        let direction = if state.z_source > 0. {
+            (position - Vec3::Z * state.z_source).normalize_or(direction)
+        } else {
+            direction
+        };
+        let speed = state.speed * (1. + rng.signed() * state.speed_variation);
+        (position, direction * speed)
+    }
+
+    fn spawn_particle(&mut self, state: &M2EmitterState, emitter_transform: &GlobalTransform) {
+        let (position, velocity) = self.emit(state);
+        let coordinates = self.emitter.coordinates;
+        let (position, velocity) = (
+            coordinates.position(position),
+            coordinates.position(velocity),
+        );
+        let (position, velocity) = if self.emitter.flags.contains(ParticleFlags::FOLLOW_EMITTER) {
+            (position, velocity)
+        } else {
+            (
+                emitter_transform.transform_point(position),
+                emitter_transform.affine().transform_vector3(velocity),
+            )
+        };
+
+        let emitter = &self.emitter;
+        let rng = &mut self.rng;
+        let scale = 1. + rng.signed() * emitter.scale_variation.x;
+        let particle = Particle {
+            position,
+            velocity,
+            age: 0.,
+            lifespan: (state.lifespan + rng.signed() * emitter.lifespan_variation).max(0.01),
+            scale: Vec2::new(
+                scale,
+                scale * (1. + rng.signed() * emitter.scale_variation.y),
+            ),
+            angle: emitter.base_spin + rng.signed() * emitter.base_spin_variation,
+            angular_velocity: emitter.spin + rng.signed() * emitter.spin_variation,
+            tile: emitter
+                .flags
+                .contains(ParticleFlags::RANDOM_TILE)
+                .then(|| rng.next_u32() % emitter.tile_count()),
+        };
+        self.particles.push(particle);
+    }
+
+    /// Ages, moves and emits particles over `delta` seconds.
+    pub fn update(
+        &mut self,
+        state: &M2EmitterState,
+        emitter_transform: &GlobalTransform,
+        delta: f32,
+    ) {
+        let follow = self.emitter.flags.contains(ParticleFlags::FOLLOW_EMITTER);
+        let gravity = self.emitter.coordinates.position(Vec3::NEG_Z) * state.gravity;
+        let gravity = if follow {
+            gravity
+        } else {
+            emitter_transform.affine().transform_vector3(gravity)
+        };
+        let drag = (-self.emitter.drag * delta).exp();
+
+        self.particles.retain_mut(|particle| {
+            particle.age += delta;
+            particle.velocity = (particle.velocity + gravity * delta) * drag;
+            particle.position += particle.velocity * delta;
+            particle.angle += particle.angular_velocity * delta;
+            particle.age < particle.lifespan
+        });
+
+        if state.enabled <= 0.5 {
+            self.pending = 0.;
+            return;
+        }
+
+        let rate = state.rate + self.rng.signed() * self.emitter.rate_variation;
+        let room = (MAX_PARTICLES - self.particles.len()) as f32;
+        self.pending = (self.pending + rate.max(0.) * delta).min(room);
+        while self.pending >= 1. {
+            self.pending -= 1.;
+            self.spawn_particle(state, emitter_transform);
+        }
+    }
+
+    /// Builds the quads of the live particles in the space of `root_transform`. Quads face the
+    /// camera along `right` and `up`, world space axes, unless the emitter lays them flat.
+    fn quads(
+        &self,
+        emitter_transform: &GlobalTransform,
+        root_transform: &GlobalTransform,
+        (right, up): (Vec3, Vec3),
+    ) -> ParticleQuads {
+        let emitter = &self.emitter;
+        let follow = emitter.flags.contains(ParticleFlags::FOLLOW_EMITTER);
+        let emitter_affine = emitter_transform.affine();
+        let (right, up) = if emitter.flags.contains(ParticleFlags::XY_QUAD) {
+            (
+                emitter_affine
+                    .transform_vector3(emitter.coordinates.position(Vec3::X))
+                    .normalize_or_zero(),
+                emitter_affine
+                    .transform_vector3(emitter.coordinates.position(Vec3::Y))
+                    .normalize_or_zero(),
+            )
+        } else {
+            (right, up)
+        };
+        let to_root = root_transform.affine().inverse();
+        let normal = to_root
+            .transform_vector3(right.cross(up))
+            .normalize_or_zero();
+        let size = emitter_transform.scale().x;
+
+        let mut quads = ParticleQuads::with_capacity(self.particles.len());
+        for particle in &self.particles {
+            let life = (particle.age / particle.lifespan).clamp(0., 1.);
+            let center = if follow {
+                emitter_transform.transform_point(particle.position)
+            } else {
+                particle.position
+            };
+            let scale = emitter.scale.sample(life, Vec2::ONE) * particle.scale * size;
+            let (sin, cos) = particle.angle.sin_cos();
+            let (right, up) = (
+                (right * cos + up * sin) * scale.x,
+                (up * cos - right * sin) * scale.y,
+            );
+
+            let tile = particle
+                .tile
+                .unwrap_or_else(|| emitter.head_cell.sample(life, 0.) as u32);
+            let color = emitter.color.sample(life, Vec3::ONE);
+            let alpha = emitter.alpha.sample(life, 1.);
+            quads.push(
+                [
+                    center - right + up,
+                    center + right + up,
+                    center + right - up,
+                    center - right - up,
+                ]
+                .map(|corner| to_root.transform_point3(corner)),
+                emitter.tile_uvs(tile),
+                [color.x, color.y, color.z, alpha],
+                normal,
+            );
+        }
+        quads
+    }
+}
+
+/// Vertex data of the particle quads of one emitter.
+#[derive(Debug, Default)]
+struct ParticleQuads {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl ParticleQuads {
+    fn with_capacity(quads: usize) -> Self {
+        Self {
+            positions: Vec::with_capacity(quads * 4),
+            normals: Vec::with_capacity(quads * 4),
+            uvs: Vec::with_capacity(quads * 4),
+            colors: Vec::with_capacity(quads * 4),
+            indices: Vec::with_capacity(quads * 6),
+        }
+    }
+
+    /// Adds a quad from its top left, top right, bottom right and bottom left corners.
+    fn push(
+        &mut self,
+        corners: [Vec3; 4],
+        (start, end): (Vec2, Vec2),
+        color: [f32; 4],
+        normal: Vec3,
+    ) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal; 4]);
+        self.uvs.extend([
+            start,
+            Vec2::new(end.x, start.y),
+            end,
+            Vec2::new(start.x, end.y),
+        ]);
+        self.colors.extend([color; 4]);
+        self.indices
+            .extend([0, 3, 2, 0, 2, 1].map(|index| first + index));
+    }
+
+    fn write_to(self, mesh: &mut Mesh) {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_indices(Indices::U32(self.indices));
+    }
+}
+
+/// Spawns the emitters below their joints, see `M2Skeleton::parent_of`.
+pub fn spawn_particle_emitters(
+    commands: &mut Commands,
+    root: Entity,
+    skeleton: &M2Skeleton,
+    joints: &[Entity],
+    emitters: &[Arc<M2ParticleEmitter>],
+) -> Vec<Entity> {
+    emitters
+        .iter()
+        .enumerate()
+        .map(|(i, emitter)| {
+            let (parent, translation) =
+                skeleton.parent_of(joints, root, emitter.bone, emitter.position);
+
+            let entity = commands
+                .spawn((
+                    M2ParticleEmitter::name(i),
+                    Transform::from_translation(translation),
+                    Visibility::default(),
+                    M2EmitterState::at_rest(&emitter.tracks),
+                    AnimationTarget {
+                        id: M2ParticleEmitter::target_id(i),
+                        player: root,
+                    },
+                    ChildOf(parent),
+                ))
+                .id();
+            commands.entity(entity).insert(M2ParticleSystem::new(
+                emitter.clone(),
+                root,
+                entity.to_bits(),
+            ));
+            entity
+        })
+        .collect()
+}
+
+/// Adds the curves of the animated emission parameters to the clip of `sequence`.
+pub fn add_particle_curves(
+    clip: &mut AnimationClip,
+    emitters: &[Arc<M2ParticleEmitter>],
+    sequence: usize,
+    global_sequences: &[u32],
+) {
+    for (index, emitter) in emitters.iter().enumerate() {
+        let target = M2ParticleEmitter::target_id(index);
+        let tracks = &emitter.tracks;
+        let rest = M2EmitterState::at_rest(tracks);
+
+        add_track_channels!(
+            clip, target, M2EmitterState, tracks, rest, sequence, global_sequences;
+            speed,
+            speed_variation,
+            vertical_range,
+            horizontal_range,
+            gravity,
+            lifespan,
+            rate,
+            area_length,
+            area_width,
+            z_source,
+            enabled
+        );
+    }
+}
+
+/// Moves the particles of every emitter and emits new ones, once the emitters have followed
+/// their joints.
+pub fn simulate_particles(
+    time: Res<Time>,
+    mut emitters: Query<(&mut M2ParticleSystem, &M2EmitterState, &GlobalTransform)>,
+) {
+    let delta = time.delta_secs();
+    for (mut system, state, transform) in &mut emitters {
+        system.update(state, transform, delta);
+    }
+}
+
+/// Rebuilds the mesh of every emitter's particles, facing the first 3D camera.
+pub fn draw_particles(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut emitters: Query<(&mut M2ParticleSystem, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    mut visibilities: Query<&mut Visibility>,
+) {
+    let facing = cameras.iter().next().map_or((Vec3::X, Vec3::Y), |camera| {
+        (camera.right().as_vec3(), camera.up().as_vec3())
+    });
+
+    for (mut system, emitter_transform) in &mut emitters {
+        let Some((draw, mesh)) = system.draw.clone() else {
+            system.draw = Some(spawn_dynamic_draw(
+                &mut commands,
+                &mut meshes,
+                "particles",
+                system.emitter.material.clone(),
+                system.root,
+            ));
+            continue;
+        };
+        let Ok(root_transform) = transforms.get(system.root) else {
+            continue;
+        };
+
+        show_dynamic_draw(&mut visibilities, draw, !system.is_empty());
+        if system.is_empty() {
+            continue;
+        }
+        if let Some(mesh) = meshes.get_mut(&mesh) {
+            system
+                .quads(emitter_transform, root_transform, facing)
+                .write_to(mesh);
+        }
+    }
+}
+
+/// Simulates and draws the particle emitters spawned by `M2Asset::spawn`.
+#[derive(Default)]
+pub struct M2ParticlePlugin;
+
+impl Plugin for M2ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<M2EmitterState>().add_systems(
+            PostUpdate,
+            (simulate_particles, draw_particles)
+                .chain()
+                .after(TransformSystem::TransformPropagate)
+                .before(VisibilitySystems::VisibilityPropagate),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::m2::anim::write_bytes;
+
+    #[test]
+    fn life_curves_interpolate_and_clamp() {
+        let curve = LifeCurve {
+            times: vec![0., 0.5, 1.],
+            values: vec![0., 1., 0.5],
+        };
+        assert_eq!(curve.sample(-1., 9.), 0.);
+        assert_eq!(curve.sample(0.25, 9.), 0.5);
+        assert_eq!(curve.sample(0.75, 9.), 0.75);
+        assert_eq!(curve.sample(2., 9.), 0.5);
+        assert_eq!(LifeCurve::default().sample(0.5, 9.), 9.);
+    }
+
+    #[test]
+    fn tiles_split_the_texture_by_rows() {
+        let emitter = M2ParticleEmitter {
+            tiles: UVec2::new(4, 2),
+            ..default()
+        };
+        assert_eq!(emitter.tile_count(), 8);
+        assert_eq!(
+            emitter.tile_uvs(5),
+            (Vec2::new(0.25, 0.5), Vec2::new(0.5, 1.))
+        );
+        assert_eq!(emitter.tile_uvs(8), emitter.tile_uvs(0));
+    }
+
+    #[test]
+    fn reads_emitter_definitions() {
+        let mut m2 = vec![0u8; 0x400];
+        let start = 0x140;
+        let array = ArrayRef {
+            count: 1,
+            offset: start as u32,
+        };
+        write_bytes(
+            &mut m2,
+            start + PARTICLE_FLAGS as usize,
+            &0x1010u32.to_le_bytes(),
+        );
+        write_bytes(
+            &mut m2,
+            start + PARTICLE_POSITION as usize,
+            &2f32.to_le_bytes(),
+        );
+        write_bytes(&mut m2, start + PARTICLE_BONE as usize, &[3, 0, 1, 0]);
+        write_bytes(&mut m2, start + PARTICLE_BLENDING as usize, &[4, 2]);
+        write_bytes(&mut m2, start + PARTICLE_ROWS as usize, &[2, 0, 4, 0]);
+
+        // Alpha fades out over the particles' life.
+        let alpha = start + (PARTICLE_COLOR + LIFE_CURVE_SIZE) as usize;
+        write_bytes(&mut m2, alpha, &[2, 0, 0, 0, 0x40, 0x03, 0, 0]);
+        write_bytes(&mut m2, alpha + 8, &[2, 0, 0, 0, 0x50, 0x03, 0, 0]);
+        write_bytes(&mut m2, 0x340, &[0, 0, 0xff, 0x7f]);
+        write_bytes(&mut m2, 0x350, &[0xff, 0x7f, 0, 0]);
+
+        let emitters = read_particle_emitters(&m2, array, &[], CoordinateSystem::ZUp).unwrap();
+        assert_eq!(emitters.len(), 1);
+        let emitter = &emitters[0];
+        assert_eq!(
+            emitter.flags,
+            ParticleFlags::FOLLOW_EMITTER | ParticleFlags::XY_QUAD
+        );
+        assert_eq!(emitter.position, Vec3::new(2., 0., 0.));
+        assert_eq!(emitter.bone, Some(3));
+        assert_eq!(emitter.texture, 1);
+        assert_eq!(emitter.blend_mode, BlendMode::Add);
+        assert_eq!(emitter.emitter_type, EmitterType::Sphere);
+        assert_eq!(emitter.tiles, UVec2::new(4, 2));
+        assert_eq!(emitter.alpha.times, vec![0., 1.]);
+        assert_eq!(emitter.alpha.sample(0.5, 0.), 0.5);
+        assert!(!emitter.tracks.rate.is_animated());
+    }
+
+    #[test]
+    fn emits_at_the_animated_rate() {
+        let emitter = M2ParticleEmitter {
+            coordinates: CoordinateSystem::ZUp,
+            ..default()
+        };
+        let mut system = M2ParticleSystem::new(Arc::new(emitter), Entity::PLACEHOLDER, 1);
+        let mut state = M2EmitterState {
+            rate: 10.,
+            lifespan: 1.,
+            speed: 1.,
+            gravity: 4.,
+            enabled: 1.,
+            ..default()
+        };
+
+        system.update(&state, &GlobalTransform::IDENTITY, 0.55);
+        assert_eq!(system.len(), 5);
+        assert!(
+            system
+                .particles
+                .iter()
+                .all(|particle| particle.velocity.z == 1.)
+        );
+
+        system.update(&state, &GlobalTransform::IDENTITY, 0.5);
+        assert_eq!(system.len(), 10);
+        assert!(system.particles[0].velocity.z < 0.);
+
+        state.rate = 1e30;
+        system.update(&state, &GlobalTransform::IDENTITY, 1.);
+        assert_eq!(system.len(), MAX_PARTICLES);
+
+        state.enabled = 0.;
+        system.update(&state, &GlobalTransform::IDENTITY, 1.);
+        assert!(system.is_empty());
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::VecDeque;
+use std::io::{Cursor, Seek, SeekFrom};
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, view::VisibilitySystems},
+    transform::TransformSystem,
+};
+use bevy_animation::{
+    AnimationClip, AnimationTarget, AnimationTargetId, animation_curves::AnimatedField,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{
+    CoordinateSystem,
+    anim::{ArrayRef, TRACK_SIZE, TrackLayout, read_fixed16, read_vec3},
+    animation::{Track, add_track_channels},
+    dynamic_draw::{show_dynamic_draw, spawn_dynamic_draw},
+    material::{
+        BlendMode, M2Material, M2MaterialExtension, PixelShader, RenderFlags, ShaderEffect,
+        TexCoord, base_material, sort_bias,
+    },
+    skeleton::M2Skeleton,
+};
+use crate::errors::Result;
+
+const RIBBON_SIZE: u64 = 0xb0;
+const RIBBON_BONE: u64 = 0x04;
+const RIBBON_POSITION: u64 = 0x08;
+const RIBBON_TEXTURES: u64 = 0x14;
+const RIBBON_MATERIALS: u64 = 0x1c;
+const RIBBON_COLOR: u64 = 0x24;
+const RIBBON_EDGES_PER_SECOND: u64 = 0x74;
+const RIBBON_VISIBILITY: u64 = 0x98;
+const RIBBON_PRIORITY_PLANE: u64 = 0xac;
+
+/// Edges alive at once per ribbon, past which the oldest ones are dropped early.
+pub const MAX_RIBBON_EDGES: usize = 256;
+
+/// The animated parameters of a ribbon.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RibbonTracks {
+    pub color: Track<Vec3>,
+    pub alpha: Track<f32>,
+    pub height_above: Track<f32>,
+    pub height_below: Track<f32>,
+    pub visible: Track<f32>,
+}
+
+/// A strip trailing behind its bone.
+#[derive(Debug, Clone, Default)]
+pub struct M2RibbonEmitter {
+    pub position: Vec3,
+    pub bone: Option<usize>,
+    pub texture: Option<usize>,
+    pub material_index: Option<usize>,
+    pub edges_per_second: f32,
+    /// Seconds an edge lives, fading out along the way.
+    pub edge_lifetime: f32,
+    pub gravity: f32,
+    pub priority_plane: i8,
+    pub tracks: RibbonTracks,
+    pub coordinates: CoordinateSystem,
+    /// Set by the loader once the texture and material are known.
+    pub material: Handle<M2Material>,
+}
+
+impl M2RibbonEmitter {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("ribbon_emitter{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+
+    /// The material of the strip, two-sided and tinted by its vertex colors.
+    pub fn build_material(
+        &self,
+        texture: Option<Handle<Image>>,
+        blend_mode: BlendMode,
+        flags: RenderFlags,
+    ) -> M2Material {
+        let flags = flags | RenderFlags::TWO_SIDED;
+        let effect = ShaderEffect {
+            pixel: PixelShader::Mod,
+            coords: [TexCoord::T1, TexCoord::T1],
+        };
+
+        M2Material {
+            base: base_material(blend_mode, flags, sort_bias(self.priority_plane, 0)),
+            extension: M2MaterialExtension::new(effect, &[texture], blend_mode, flags),
+        }
+    }
+}
+
+/// Reads the ribbon `emitters` like `read_particle_emitters`.
+pub fn read_ribbon_emitters(
+    m2: &[u8],
+    emitters: ArrayRef,
+    sources: &[Option<&[u8]>],
+    coordinates: CoordinateSystem,
+) -> Result<Vec<M2RibbonEmitter>> {
+    let mut reader = Cursor::new(m2);
+
+    (0..emitters.count as u64)
+        .map(|i| {
+            let start = emitters.offset as u64 + i * RIBBON_SIZE;
+            let read_f32 = |reader: &mut Cursor<&[u8]>| Ok(reader.read_f32::<LittleEndian>()?);
+            let read_first_index = |reader: &mut Cursor<&[u8]>, position: u64| -> Result<_> {
+                let indices = ArrayRef::read_at(reader, position)?;
+                let indices = indices.read_elements(m2, indices.count.min(1), 2, |reader| {
+                    Ok(reader.read_u16::<LittleEndian>()? as usize)
+                })?;
+                Ok(indices.first().copied())
+            };
+
+            reader.seek(SeekFrom::Start(start + RIBBON_BONE))?;
+            let bone = reader.read_u32::<LittleEndian>()? as usize;
+            reader.seek(SeekFrom::Start(start + RIBBON_POSITION))?;
+            let position = coordinates.position(read_vec3(&mut reader)?);
+            let texture = read_first_index(&mut reader, start + RIBBON_TEXTURES)?;
+            let material_index = read_first_index(&mut reader, start + RIBBON_MATERIALS)?;
+
+            let color = start + RIBBON_COLOR;
+            let tracks =
+                RibbonTracks {
+                    color: TrackLayout::read(&mut reader, color)?
+                        .read_track(m2, sources, 12, read_vec3)?,
+                    alpha: TrackLayout::read(&mut reader, color + TRACK_SIZE)?.read_track(
+                        m2,
+                        sources,
+                        2,
+                        read_fixed16,
+                    )?,
+                    height_above: TrackLayout::read(&mut reader, color + TRACK_SIZE * 2)?
+                        .read_track(m2, sources, 4, read_f32)?,
+                    height_below: TrackLayout::read(&mut reader, color + TRACK_SIZE * 3)?
+                        .read_track(m2, sources, 4, read_f32)?,
+                    visible: TrackLayout::read(&mut reader, start + RIBBON_VISIBILITY)?
+                        .read_track(m2, sources, 1, |reader| Ok(reader.read_u8()? as f32))?,
+                };
+
+            reader.seek(SeekFrom::Start(start + RIBBON_EDGES_PER_SECOND))?;
+            let edges_per_second = reader.read_f32::<LittleEndian>()?;
+            let edge_lifetime = reader.read_f32::<LittleEndian>()?;
+            let gravity = reader.read_f32::<LittleEndian>()?;
+            reader.seek(SeekFrom::Start(start + RIBBON_PRIORITY_PLANE))?;
+            let priority_plane = reader.read_i16::<LittleEndian>()? as i8;
+
+            Ok(M2RibbonEmitter {
+                position,
+                bone: Some(bone),
+                texture,
+                material_index,
+                edges_per_second,
+                edge_lifetime,
+                gravity,
+                priority_plane,
+                tracks,
+                coordinates,
+                material: Handle::default(),
+            })
+        })
+        .collect()
+}
+
+/// The current parameters of a spawned ribbon, animated by the model's clips.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct M2RibbonState {
+    pub color: Vec3,
+    pub alpha: f32,
+    pub height_above: f32,
+    pub height_below: f32,
+    pub visible: f32,
+}
+
+impl Default for M2RibbonState {
+    fn default() -> Self {
+        Self {
+            color: Vec3::ONE,
+            alpha: 1.,
+            height_above: 0.,
+            height_below: 0.,
+            visible: 1.,
+        }
+    }
+}
+
+impl M2RibbonState {
+    pub fn at_rest(tracks: &RibbonTracks) -> Self {
+        let rest = Self::default();
+        Self {
+            color: tracks.color.rest_value(rest.color),
+            alpha: tracks.alpha.rest_value(rest.alpha),
+            height_above: tracks.height_above.rest_value(rest.height_above),
+            height_below: tracks.height_below.rest_value(rest.height_below),
+            visible: tracks.visible.rest_value(rest.visible),
+        }
+    }
+}
+
+/// A cross section of the strip, left behind by the emitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edge {
+    /// In world space, like `up`.
+    position: Vec3,
+    velocity: Vec3,
+    /// The emitter's up axis when the edge was left, scaled like the emitter.
+    up: Vec3,
+    above: f32,
+    below: f32,
+    age: f32,
+}
+
+/// The live edges of a spawned ribbon, newest first. They are drawn by an entity below `root`,
+/// created on the first update.
+#[derive(Component, Debug)]
+pub struct M2RibbonStrip {
+    pub emitter: Arc<M2RibbonEmitter>,
+    pub root: Entity,
+    edges: VecDeque<Edge>,
+    pending: f32,
+    draw: Option<(Entity, Handle<Mesh>)>,
+}
+
+impl M2RibbonStrip {
+    pub fn new(emitter: Arc<M2RibbonEmitter>, root: Entity) -> Self {
+        Self {
+            emitter,
+            root,
+            edges: VecDeque::new(),
+            pending: 0.,
+            draw: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// The edge at the emitter right now.
+    fn edge(&self, state: &M2RibbonState, emitter_transform: &GlobalTransform) -> Edge {
+        Edge {
+            position: emitter_transform.translation(),
+            velocity: Vec3::ZERO,
+            up: emitter_transform
+                .affine()
+                .transform_vector3(self.emitter.coordinates.position(Vec3::Z)),
+            above: state.height_above,
+            below: state.height_below,
+            age: 0.,
+        }
+    }
+
+    /// Ages and drops the edges, and leaves new ones behind over `delta` seconds.
+    pub fn update(
+        &mut self,
+        state: &M2RibbonState,
+        emitter_transform: &GlobalTransform,
+        delta: f32,
+    ) {
+        let gravity = emitter_transform
+            .affine()
+            .transform_vector3(self.emitter.coordinates.position(Vec3::NEG_Z))
+            * self.emitter.gravity;
+        let lifetime = self.emitter.edge_lifetime;
+        for edge in &mut self.edges {
+            edge.age += delta;
+            edge.velocity += gravity * delta;
+            edge.position += edge.velocity * delta;
+        }
+        self.edges.retain(|edge| edge.age < lifetime);
+
+        if state.visible <= 0.5 {
+            self.pending = 0.;
+            return;
+        }
+
+        self.pending = (self.pending + self.emitter.edges_per_second.max(0.) * delta)
+            .min(MAX_RIBBON_EDGES as f32);
+        while self.pending >= 1. {
+            self.pending -= 1.;
+            self.edges.push_front(self.edge(state, emitter_transform));
+        }
+        self.edges.truncate(MAX_RIBBON_EDGES);
+    }
+
+    /// Builds the strip from the emitter through every edge, in the space of `root_transform`.
+    /// Edges fade out over their lifetime and the whole strip takes the current color.
+    fn strip(
+        &self,
+        state: &M2RibbonState,
+        emitter_transform: &GlobalTransform,
+        root_transform: &GlobalTransform,
+    ) -> RibbonStrip {
+        let head = (state.visible > 0.5).then(|| self.edge(state, emitter_transform));
+        let edges: Vec<Edge> = head.into_iter().chain(self.edges.iter().copied()).collect();
+        let to_root = root_transform.affine().inverse();
+        let lifetime = self.emitter.edge_lifetime.max(f32::EPSILON);
+
+        let mut strip = RibbonStrip::with_capacity(edges.len());
+        if edges.len() < 2 {
+            return strip;
+        }
+        for (i, edge) in edges.iter().enumerate() {
+            let life = (edge.age / lifetime).clamp(0., 1.);
+            let previous = edges[i.saturating_sub(1)].position;
+            let next = edges[(i + 1).min(edges.len() - 1)].position;
+            let normal = (next - previous).cross(edge.up);
+
+            strip.push(
+                [
+                    to_root.transform_point3(edge.position + edge.up * edge.above),
+                    to_root.transform_point3(edge.position - edge.up * edge.below),
+                ],
+                life,
+                [
+                    state.color.x,
+                    state.color.y,
+                    state.color.z,
+                    state.alpha * (1. - life),
+                ],
+                to_root.transform_vector3(normal).normalize_or(Vec3::Y),
+            );
+        }
+        strip
+    }
+}
+
+/// Vertex data of a ribbon, a top and a bottom vertex per edge.
+#[derive(Debug, Default)]
+struct RibbonStrip {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl RibbonStrip {
+    fn with_capacity(edges: usize) -> Self {
+        Self {
+            positions: Vec::with_capacity(edges * 2),
+            normals: Vec::with_capacity(edges * 2),
+            uvs: Vec::with_capacity(edges * 2),
+            colors: Vec::with_capacity(edges * 2),
+            indices: Vec::with_capacity(edges.saturating_sub(1) * 6),
+        }
+    }
+
+    /// Adds an edge from its top and bottom vertices, joined to the previous edge. The texture
+    /// runs along the strip with the age of its edges.
+    fn push(&mut self, [top, bottom]: [Vec3; 2], u: f32, color: [f32; 4], normal: Vec3) {
+        let first = self.positions.len() as u32;
+        if first > 0 {
+            self.indices
+                .extend([0, 1, 3, 0, 3, 2].map(|index| first - 2 + index));
+        }
+        self.positions.extend([top, bottom]);
+        self.normals.extend([normal; 2]);
+        self.uvs.extend([Vec2::new(u, 0.), Vec2::new(u, 1.)]);
+        self.colors.extend([color; 2]);
+    }
+
+    fn write_to(self, mesh: &mut Mesh) {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_indices(Indices::U32(self.indices));
+    }
+}
+
+/// Spawns the ribbons like `spawn_particle_emitters`.
+pub fn spawn_ribbon_emitters(
+    commands: &mut Commands,
+    root: Entity,
+    skeleton: &M2Skeleton,
+    joints: &[Entity],
+    emitters: &[Arc<M2RibbonEmitter>],
+) -> Vec<Entity> {
+    emitters
+        .iter()
+        .enumerate()
+        .map(|(i, emitter)| {
+            let (parent, translation) =
+                skeleton.parent_of(joints, root, emitter.bone, emitter.position);
+            commands
+                .spawn((
+                    M2RibbonEmitter::name(i),
+                    Transform::from_translation(translation),
+                    Visibility::default(),
+                    M2RibbonState::at_rest(&emitter.tracks),
+                    M2RibbonStrip::new(emitter.clone(), root),
+                    AnimationTarget {
+                        id: M2RibbonEmitter::target_id(i),
+                        player: root,
+                    },
+                    ChildOf(parent),
+                ))
+                .id()
+        })
+        .collect()
+}
+
+pub fn add_ribbon_curves(
+    clip: &mut AnimationClip,
+    emitters: &[Arc<M2RibbonEmitter>],
+    sequence: usize,
+    global_sequences: &[u32],
+) {
+    for (index, emitter) in emitters.iter().enumerate() {
+        let target = M2RibbonEmitter::target_id(index);
+        let tracks = &emitter.tracks;
+        let rest = M2RibbonState::at_rest(tracks);
+
+        add_track_channels!(
+            clip, target, M2RibbonState, tracks, rest, sequence, global_sequences;
+            color, alpha, height_above, height_below, visible
+        );
+    }
+}
+
+/// Moves the edges of every ribbon and leaves new ones behind, once the ribbons have followed
+/// their joints.
+pub fn update_ribbons(
+    time: Res<Time>,
+    mut ribbons: Query<(&mut M2RibbonStrip, &M2RibbonState, &GlobalTransform)>,
+) {
+    let delta = time.delta_secs();
+    for (mut strip, state, transform) in &mut ribbons {
+        strip.update(state, transform, delta);
+    }
+}
+
+/// Rebuilds the mesh of every ribbon.
+pub fn draw_ribbons(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut ribbons: Query<(&mut M2RibbonStrip, &M2RibbonState, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    mut visibilities: Query<&mut Visibility>,
+) {
+    for (mut ribbon, state, emitter_transform) in &mut ribbons {
+        let Some((draw, mesh)) = ribbon.draw.clone() else {
+            ribbon.draw = Some(spawn_dynamic_draw(
+                &mut commands,
+                &mut meshes,
+                "ribbon",
+                ribbon.emitter.material.clone(),
+                ribbon.root,
+            ));
+            continue;
+        };
+        let Ok(root_transform) = transforms.get(ribbon.root) else {
+            continue;
+        };
+
+        let strip = ribbon.strip(state, emitter_transform, root_transform);
+        show_dynamic_draw(&mut visibilities, draw, !strip.indices.is_empty());
+        if strip.indices.is_empty() {
+            continue;
+        }
+        if let Some(mesh) = meshes.get_mut(&mesh) {
+            strip.write_to(mesh);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct M2RibbonPlugin;
+
+impl Plugin for M2RibbonPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<M2RibbonState>().add_systems(
+            PostUpdate,
+            (update_ribbons, draw_ribbons)
+                .chain()
+                .after(TransformSystem::TransformPropagate)
+                .before(VisibilitySystems::VisibilityPropagate),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::m2::anim::write_bytes;
+
+    #[test]
+    fn reads_ribbon_definitions() {
+        let mut m2 = vec![0u8; 0x300];
+        let start = 0x140;
+        let array = ArrayRef {
+            count: 1,
+            offset: start as u32,
+        };
+        write_bytes(&mut m2, start + RIBBON_BONE as usize, &[5, 0, 0, 0]);
+        write_bytes(
+            &mut m2,
+            start + RIBBON_POSITION as usize + 8,
+            &1f32.to_le_bytes(),
+        );
+        write_bytes(
+            &mut m2,
+            start + RIBBON_TEXTURES as usize,
+            &[1, 0, 0, 0, 0, 2, 0, 0],
+        );
+        write_bytes(&mut m2, 0x200, &[2, 0]);
+        write_bytes(
+            &mut m2,
+            start + RIBBON_EDGES_PER_SECOND as usize,
+            &30f32.to_le_bytes(),
+        );
+        write_bytes(
+            &mut m2,
+            start + RIBBON_EDGES_PER_SECOND as usize + 4,
+            &0.5f32.to_le_bytes(),
+        );
+        write_bytes(
+            &mut m2,
+            start + RIBBON_PRIORITY_PLANE as usize,
+            &[0xff, 0xff],
+        );
+
+        let ribbons = read_ribbon_emitters(&m2, array, &[], CoordinateSystem::YUp).unwrap();
+        assert_eq!(ribbons.len(), 1);
+        let ribbon = &ribbons[0];
+        assert_eq!(ribbon.bone, Some(5));
+        assert_eq!(ribbon.position, Vec3::new(0., 1., 0.));
+        assert_eq!(ribbon.texture, Some(2));
+        assert_eq!(ribbon.material_index, None);
+        assert_eq!(ribbon.edges_per_second, 30.);
+        assert_eq!(ribbon.edge_lifetime, 0.5);
+        assert_eq!(ribbon.priority_plane, -1);
+        assert_eq!(
+            M2RibbonState::at_rest(&ribbon.tracks),
+            M2RibbonState::default()
+        );
+    }
+
+    #[test]
+    fn edges_trail_and_fade() {
+        let emitter = M2RibbonEmitter {
+            edges_per_second: 10.,
+            edge_lifetime: 1.,
+            coordinates: CoordinateSystem::ZUp,
+            ..default()
+        };
+        let mut ribbon = M2RibbonStrip::new(Arc::new(emitter), Entity::PLACEHOLDER);
+        let state = M2RibbonState {
+            height_above: 1.,
+            height_below: 0.5,
+            ..default()
+        };
+
+        ribbon.update(&state, &GlobalTransform::IDENTITY, 0.25);
+        assert_eq!(ribbon.len(), 2);
+        let moved = GlobalTransform::from_translation(Vec3::X);
+        ribbon.update(&state, &moved, 0.5);
+        assert_eq!(ribbon.len(), 7);
+
+        let strip = ribbon.strip(&state, &moved, &GlobalTransform::IDENTITY);
+        assert_eq!(strip.positions.len(), 16);
+        assert_eq!(strip.indices.len(), 7 * 6);
+        assert_eq!(strip.positions[0], Vec3::new(1., 0., 1.));
+        assert_eq!(strip.positions[1], Vec3::new(1., 0., -0.5));
+        assert_eq!(strip.positions[15], Vec3::new(0., 0., -0.5));
+        assert_eq!(strip.colors[0][3], 1.);
+        assert_eq!(strip.colors[15][3], 0.5);
+
+        ribbon.update(
+            &M2RibbonState {
+                visible: 0.,
+                ..state
+            },
+            &moved,
+            1.,
+        );
+        assert!(ribbon.is_empty());
+
+        let emitter = M2RibbonEmitter {
+            edges_per_second: 1e30,
+            edge_lifetime: 1.,
+            ..default()
+        };
+        let mut ribbon = M2RibbonStrip::new(Arc::new(emitter), Entity::PLACEHOLDER);
+        ribbon.update(&state, &GlobalTransform::IDENTITY, 1.);
+        assert_eq!(ribbon.len(), MAX_RIBBON_EDGES);
+    }
+}
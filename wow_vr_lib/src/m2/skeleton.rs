@@ -0,0 +1,234 @@
+use bevy::{
+    prelude::*,
+    render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+};
+use bevy_animation::{AnimationTarget, AnimationTargetId};
+use wow_m2::chunks::bone::M2Bone;
+
+use super::c3_to_vec3;
+
+/// One joint of the model skeleton. M2 bones have no rest rotation, only a pivot in model
+/// space, so a joint at rest is a pure translation from its parent's pivot to its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct M2Joint {
+    pub parent: Option<usize>,
+    pub pivot: Vec3,
+    /// Offset from the parent pivot, or from the model origin for root joints.
+    pub rest_translation: Vec3,
+}
+
+impl M2Joint {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("bone{}", index))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct M2Skeleton {
+    pub joints: Vec<M2Joint>,
+    /// Moves vertices from model space into each joint's space, `None` for models without bones.
+    pub inverse_bindposes: Option<Handle<SkinnedMeshInverseBindposes>>,
+}
+
+impl M2Skeleton {
+    pub fn from_bones(bones: &[M2Bone]) -> Self {
+        Self::from_links(
+            bones
+                .iter()
+                .map(|bone| (bone.parent_bone, c3_to_vec3(bone.pivot))),
+        )
+    }
+
+    /// Builds the joints from `(parent index, pivot)` pairs. Parents that are out of range or
+    /// would close a loop are dropped, the joint then hangs off the model root instead.
+    pub fn from_links(links: impl IntoIterator<Item = (i16, Vec3)>) -> Self {
+        let links: Vec<(i16, Vec3)> = links.into_iter().collect();
+        let mut parents: Vec<Option<usize>> = links
+            .iter()
+            .map(|(parent, _)| usize::try_from(*parent).ok())
+            .map(|parent| parent.filter(|parent| *parent < links.len()))
+            .collect();
+
+        for i in 0..parents.len() {
+            let mut ancestor = parents[i];
+            let mut steps = 0;
+            while let Some(current) = ancestor {
+                if current == i || steps > parents.len() {
+                    parents[i] = None;
+                    break;
+                }
+                ancestor = parents[current];
+                steps += 1;
+            }
+        }
+
+        let joints = links
+            .iter()
+            .zip(&parents)
+            .map(|((_, pivot), parent)| M2Joint {
+                parent: *parent,
+                pivot: *pivot,
+                rest_translation: match parent {
+                    Some(parent) => *pivot - links[*parent].1,
+                    None => *pivot,
+                },
+            })
+            .collect();
+
+        Self {
+            joints,
+            inverse_bindposes: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn inverse_bindposes(&self) -> SkinnedMeshInverseBindposes {
+        self.joints
+            .iter()
+            .map(|joint| Mat4::from_translation(-joint.pivot))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// Identifies a joint in the model's animation clips, by the names from its topmost ancestor
+    /// down to itself.
+    pub fn target_id(&self, index: usize) -> AnimationTargetId {
+        let mut path = vec![M2Joint::name(index)];
+        let mut joint = &self.joints[index];
+        while let Some(parent) = joint.parent {
+            path.push(M2Joint::name(parent));
+            joint = &self.joints[parent];
+        }
+        AnimationTargetId::from_names(path.iter().rev())
+    }
+
+    /// Spawns one entity per joint below `root`, in rest pose and animated by the
+    /// `AnimationPlayer` on `root`. The returned entities are in bone order, ready for
+    /// `skinned_mesh`.
+    pub fn spawn(&self, commands: &mut Commands, root: Entity) -> Vec<Entity> {
+        let entities: Vec<Entity> = self
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                commands
+                    .spawn((
+                        M2Joint::name(i),
+                        Transform::from_translation(joint.rest_translation),
+                        Visibility::default(),
+                        AnimationTarget {
+                            id: self.target_id(i),
+                            player: root,
+                        },
+                    ))
+                    .id()
+            })
+            .collect();
+
+        for (joint, entity) in self.joints.iter().zip(&entities) {
+            let parent = joint.parent.map_or(root, |parent| entities[parent]);
+            commands.entity(*entity).insert(ChildOf(parent));
+        }
+
+        entities
+    }
+
+    /// The entity something at `position` in model space hangs from, among the `joints`
+    /// spawned by `spawn`: the joint of `bone`, or `root` without one, with its translation
+    /// from there.
+    pub fn parent_of(
+        &self,
+        joints: &[Entity],
+        root: Entity,
+        bone: Option<usize>,
+        position: Vec3,
+    ) -> (Entity, Vec3) {
+        match bone.filter(|bone| *bone < joints.len().min(self.joints.len())) {
+            Some(bone) => (joints[bone], position - self.joints[bone].pivot),
+            None => (root, position),
+        }
+    }
+
+    /// The component that binds a mesh to joints spawned by `spawn`.
+    pub fn skinned_mesh(&self, joints: &[Entity]) -> Option<SkinnedMesh> {
+        Some(SkinnedMesh {
+            inverse_bindposes: self.inverse_bindposes.clone()?,
+            joints: joints.to_vec(),
+        })
+    }
+}
+
+/// Converts an M2 vertex's bone influences to Bevy joint attributes. Weights are stored as
+/// bytes summing to 255, vertices without any weight are pinned to the first joint.
+pub fn vertex_joints(indices: [u8; 4], weights: [u8; 4]) -> ([u16; 4], [f32; 4]) {
+    let total: u32 = weights.iter().map(|weight| *weight as u32).sum();
+    if total == 0 {
+        return ([0; 4], [1., 0., 0., 0.]);
+    }
+
+    (
+        indices.map(u16::from),
+        weights.map(|weight| weight as f32 / total as f32),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joints_are_relative_to_their_parent_pivot() {
+        let skeleton = M2Skeleton::from_links([
+            (-1, Vec3::new(0., 1., 0.)),
+            (0, Vec3::new(0., 2., 0.)),
+            (1, Vec3::new(1., 2., 0.)),
+        ]);
+
+        assert_eq!(skeleton.joints[0].parent, None);
+        assert_eq!(skeleton.joints[0].rest_translation, Vec3::new(0., 1., 0.));
+        assert_eq!(skeleton.joints[1].parent, Some(0));
+        assert_eq!(skeleton.joints[1].rest_translation, Vec3::new(0., 1., 0.));
+        assert_eq!(skeleton.joints[2].rest_translation, Vec3::new(1., 0., 0.));
+
+        // At rest every joint sits on its pivot, so the skinning matrices are identities.
+        let inverse_bindposes = skeleton.inverse_bindposes();
+        let mut globals = Vec::new();
+        for joint in &skeleton.joints {
+            let local = Mat4::from_translation(joint.rest_translation);
+            globals.push(joint.parent.map_or(local, |parent| globals[parent] * local));
+        }
+        for (global, inverse) in globals.iter().zip(inverse_bindposes.iter()) {
+            assert!((*global * *inverse).abs_diff_eq(Mat4::IDENTITY, 1e-6));
+        }
+    }
+
+    #[test]
+    fn broken_parent_links_fall_back_to_the_root() {
+        let skeleton = M2Skeleton::from_links([
+            (1, Vec3::ZERO),
+            (0, Vec3::ONE),
+            (7, Vec3::ONE),
+            (2, Vec3::ONE),
+        ]);
+
+        assert_eq!(skeleton.joints[0].parent, None);
+        assert_eq!(skeleton.joints[1].parent, Some(0));
+        assert_eq!(skeleton.joints[2].parent, None);
+        assert_eq!(skeleton.joints[3].parent, Some(2));
+    }
+
+    #[test]
+    fn vertex_weights_are_normalized() {
+        let (indices, weights) = vertex_joints([3, 1, 0, 0], [204, 51, 0, 0]);
+        assert_eq!(indices, [3, 1, 0, 0]);
+        assert_eq!(weights, [0.8, 0.2, 0., 0.]);
+
+        assert_eq!(
+            vertex_joints([5, 0, 0, 0], [0; 4]),
+            ([0; 4], [1., 0., 0., 0.])
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use bevy::prelude::*;
+use bevy_animation::{
+    AnimationClip, AnimationTarget, AnimationTargetId, animated_field,
+    animation_curves::AnimatedField,
+};
+
+use super::{
+    animation::{TransformTracks, add_rotation_channel, add_vector_channel},
+    material::M2Material,
+};
+
+/// The current value of one of the model's texture transforms, animated by the model's clips
+/// like a joint. Draws pick it up through `M2TextureAnimation`.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct M2TextureTransform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for M2TextureTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl M2TextureTransform {
+    pub fn name(index: usize) -> Name {
+        Name::new(format!("texture_transform{}", index))
+    }
+
+    pub fn target_id(index: usize) -> AnimationTargetId {
+        AnimationTargetId::from_names([Self::name(index)].iter())
+    }
+
+    /// The UV matrix, rotating and scaling around the texture center like the client.
+    pub fn matrix(&self) -> Mat3 {
+        let center = Mat3::from_translation(Vec2::splat(0.5));
+        let rotation = Mat3::from_mat2(Mat2::from_cols(
+            (self.rotation * Vec3::X).truncate(),
+            (self.rotation * Vec3::Y).truncate(),
+        ));
+        Mat3::from_translation(self.translation.truncate())
+            * center
+            * rotation
+            * Mat3::from_scale(self.scale.truncate())
+            * center.inverse()
+    }
+}
+
+/// On a draw with moving textures, the texture transform entity of each of its textures.
+#[derive(Component, Debug, Clone, Default)]
+pub struct M2TextureAnimation {
+    pub transforms: Vec<Option<Entity>>,
+}
+
+/// Spawns one `M2TextureTransform` entity per texture transform below `root`, animated by the
+/// `AnimationPlayer` on `root`.
+pub fn spawn_texture_transforms(
+    commands: &mut Commands,
+    root: Entity,
+    count: usize,
+) -> Vec<Entity> {
+    (0..count)
+        .map(|i| {
+            commands
+                .spawn((
+                    M2TextureTransform::name(i),
+                    M2TextureTransform::default(),
+                    AnimationTarget {
+                        id: M2TextureTransform::target_id(i),
+                        player: root,
+                    },
+                    ChildOf(root),
+                ))
+                .id()
+        })
+        .collect()
+}
+
+/// Adds the curves of the texture transforms to the clip of `sequence`.
+pub fn add_texture_transform_curves(
+    clip: &mut AnimationClip,
+    transforms: &[TransformTracks],
+    sequence: usize,
+    global_sequences: &[u32],
+) {
+    for (index, tracks) in transforms.iter().enumerate() {
+        let target = M2TextureTransform::target_id(index);
+
+        if tracks.translation.is_animated() {
+            add_vector_channel(
+                clip,
+                target,
+                animated_field!(M2TextureTransform::translation),
+                tracks.translation.interpolation,
+                tracks.translation.keys(sequence, global_sequences),
+                Vec3::ZERO,
+            );
+        }
+
+        if tracks.rotation.is_animated() {
+            add_rotation_channel(
+                clip,
+                target,
+                animated_field!(M2TextureTransform::rotation),
+                tracks.rotation.interpolation,
+                tracks.rotation.keys(sequence, global_sequences),
+            );
+        }
+
+        if tracks.scale.is_animated() {
+            add_vector_channel(
+                clip,
+                target,
+                animated_field!(M2TextureTransform::scale),
+                tracks.scale.interpolation,
+                tracks.scale.keys(sequence, global_sequences),
+                Vec3::ONE,
+            );
+        }
+    }
+}
+
+/// Copies the animated texture transforms into the materials of the draws using them. Those
+/// materials belong to a single spawned model, see `M2Asset::spawn`.
+pub fn update_texture_transforms(
+    draws: Query<(&M2TextureAnimation, &MeshMaterial3d<M2Material>)>,
+    transforms: Query<&M2TextureTransform>,
+    mut materials: ResMut<Assets<M2Material>>,
+) {
+    for (animation, material) in &draws {
+        let Some(current) = materials.get(&material.0) else {
+            continue;
+        };
+
+        let mut matrices = current.extension.combiner.transforms;
+        for (matrix, entity) in matrices.iter_mut().zip(&animation.transforms) {
+            if let Some(transform) = entity.and_then(|entity| transforms.get(entity).ok()) {
+                *matrix = transform.matrix();
+            }
+        }
+
+        if matrices == current.extension.combiner.transforms {
+            continue;
+        }
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.extension.combiner.transforms = matrices;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rest_transform_keeps_coordinates() {
+        let uv = Vec3::new(0.25, 0.75, 1.);
+        assert!((M2TextureTransform::default().matrix() * uv).abs_diff_eq(uv, 1e-6));
+    }
+
+    #[test]
+    fn transforms_turn_around_the_texture_center() {
+        let transform = M2TextureTransform {
+            translation: Vec3::new(0.1, 0., 0.),
+            rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+            scale: Vec3::ONE,
+        };
+        let center = transform.matrix() * Vec3::new(0.5, 0.5, 1.);
+        assert!(center.abs_diff_eq(Vec3::new(0.6, 0.5, 1.), 1e-6));
+
+        let corner = transform.matrix() * Vec3::new(1., 0.5, 1.);
+        assert!(corner.abs_diff_eq(Vec3::new(0.6, 1., 1.), 1e-6));
+    }
+}
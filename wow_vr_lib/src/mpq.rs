@@ -1,41 +1,46 @@
+pub mod archive;
+pub mod cache;
+pub mod client;
+pub mod directory;
+pub mod overlay;
+pub mod provenance;
+pub mod ptch;
+mod sector;
+pub mod stream;
+pub mod verify;
+pub mod writer;
+
 use std::{
-    collections::HashMap,
-    fmt, io,
+    collections::{HashMap, HashSet},
+    fs,
     path::{Path, PathBuf},
-    result::Result as StdResult,
-    sync::Mutex,
 };
 
 use bevy::prelude::*;
-use bevy_asset::io::{AssetReader, AssetReaderError, PathStream, VecReader};
 use custom_debug::Debug;
-use wow_mpq::Archive;
-
-use crate::errors::{Error, Result};
+use wow_mpq::{BlockEntry, FileInfo};
 
-pub fn header_fmt(archives: &Vec<Mutex<Archive>>, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "[\n")?;
-    for (i, am) in archives.iter().enumerate() {
-        if let Ok(a) = am.try_lock() {
-            write!(f, "{:?}: {:#?},\n", a.path(), a.header())?;
-        } else {
-            write!(f, "locked_archive({}),\n", i)?;
-        }
-    }
-    write!(f, "]")?;
-    Ok(())
-}
+use crate::data::GameDataAssetReader;
+use crate::errors::Result;
+use archive::MpqArchive;
+use cache::{ArchiveFingerprint, IndexCache};
+use client::ClientData;
+use directory::DirectoryIndex;
+use stream::MpqFileReader;
+use verify::SPECIAL_FILES;
 
 #[derive(Debug)]
 pub struct MpqCollection {
-    #[debug(with = header_fmt)]
-    pub archives: Vec<Mutex<Archive>>,
+    pub archives: Vec<MpqArchive>,
 
     #[debug(skip)]
     pub file_map: HashMap<String, usize>,
+
+    #[debug(skip)]
+    pub directories: DirectoryIndex,
 }
 
-fn format_file_name(val: &str) -> String {
+pub fn format_file_name(val: &str) -> String {
     val.to_lowercase().replace("\\", "/")
 }
 
@@ -43,81 +48,245 @@ impl MpqCollection {
     pub fn load(paths: Vec<PathBuf>) -> Result<MpqCollection> {
         let mut archives = Vec::with_capacity(paths.len());
         let mut file_map = HashMap::new();
+        let mut unlisted = Vec::with_capacity(paths.len());
         for idx in 0..paths.len() {
-            let archive = Mutex::new(wow_mpq::OpenOptions::new().open(&paths[idx])?);
-            for file_info in &archive.lock().unwrap().list()? {
-                file_map.insert(format_file_name(&file_info.name), idx);
+            let mut archive = MpqArchive::open(&paths[idx])?;
+            // Without a (listfile) the listing only has placeholder names no lookup can find.
+            let listing = if archive.find_file("(listfile)").is_some() {
+                archive.archive_mut().list()?
+            } else {
+                Vec::new()
+            };
+            let mut listed = HashSet::with_capacity(listing.len());
+            for file_info in &listing {
+                let fname = format_file_name(&file_info.name);
+                if file_info.flags & BlockEntry::FLAG_DELETE_MARKER != 0 {
+                    file_map.remove(&fname);
+                } else {
+                    file_map.insert(fname.clone(), idx);
+                }
+                listed.insert(fname);
             }
+            unlisted.push(count_unlisted(&mut archive, &listed)?);
             archives.push(archive);
         }
 
-        Ok(MpqCollection { archives, file_map })
+        let mut collection = MpqCollection {
+            archives,
+            file_map,
+            directories: DirectoryIndex::default(),
+        };
+        collection.resolve_shadowed_names(&unlisted);
+        collection.directories = DirectoryIndex::from_files(collection.file_map.keys());
+
+        Ok(collection)
+    }
+
+    /// Like `load`, but reuses the index stored at `cache_path` when every archive still has the
+    /// same size, modification time and header. Otherwise the index is rebuilt and rewritten.
+    pub fn load_cached(paths: Vec<PathBuf>, cache_path: impl AsRef<Path>) -> Result<MpqCollection> {
+        let cache_path = cache_path.as_ref();
+        let fingerprints = paths
+            .iter()
+            .map(ArchiveFingerprint::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Ok(cache) = IndexCache::read(cache_path) {
+            if cache.is_valid_for(&fingerprints) {
+                let archives = paths
+                    .iter()
+                    .map(MpqArchive::open)
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok(MpqCollection {
+                    archives,
+                    directories: DirectoryIndex::from_files(cache.file_map.keys()),
+                    file_map: cache.file_map,
+                });
+            }
+        }
+
+        let collection = Self::load(paths)?;
+        let cache = IndexCache {
+            fingerprints,
+            file_map: collection.file_map.clone(),
+        };
+        if let Err(err) = cache.write(cache_path) {
+            warn!(
+                "failed to write MPQ index cache {:?}: {:?}",
+                cache_path, err
+            );
+        }
+
+        Ok(collection)
+    }
+
+    /// Discovers the client `Data` directory under `root` and loads its archives in client order.
+    pub fn load_client(root: impl AsRef<Path>) -> Result<MpqCollection> {
+        Self::load(ClientData::discover(root)?.load_order())
+    }
+
+    // A later archive can hold a file without listing it in its own (listfile), so known names
+    // are checked against the hash tables of the archives loaded after them. Only archives with
+    // `unlisted` entries are probed, each until all of those entries are found.
+    fn resolve_shadowed_names(&mut self, unlisted: &[usize]) {
+        for (archive_index, count) in unlisted.iter().enumerate() {
+            let mut remaining = *count;
+            if remaining == 0 {
+                continue;
+            }
+
+            let archive = &self.archives[archive_index];
+            let mut deleted = Vec::new();
+            for (name, index) in self.file_map.iter_mut() {
+                if *index >= archive_index {
+                    continue;
+                }
+                let Some(info) = archive.find_file(name) else {
+                    continue;
+                };
+                if is_deleted(&info) {
+                    deleted.push(name.clone());
+                } else {
+                    *index = archive_index;
+                }
+                remaining -= 1;
+                if remaining == 0 {
+                    break;
+                }
+            }
+
+            for name in deleted {
+                self.file_map.remove(&name);
+            }
+        }
+    }
+
+    pub fn find_archive(&self, name: &str) -> Option<usize> {
+        match find_entry(&self.archives, &format_file_name(name)) {
+            Some((index, info)) if !is_deleted(&info) => Some(index),
+            _ => None,
+        }
+    }
+
+    pub fn load_listfile(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        let data = fs::read(path)?;
+        let names = wow_mpq::special_files::parse_listfile(&data)?;
+        Ok(self.add_file_names(names))
+    }
+
+    pub fn add_file_names<S: AsRef<str>>(&mut self, names: impl IntoIterator<Item = S>) -> usize {
+        let mut added = 0;
+        for name in names {
+            let fname = format_file_name(name.as_ref());
+            if self.file_map.contains_key(&fname) {
+                continue;
+            }
+            if let Some(index) = self.find_archive(&fname) {
+                self.directories.insert_file(&fname);
+                self.file_map.insert(fname, index);
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    /// Opens a file for incremental reading. Regular entries are decoded sector by sector as they
+    /// are read, patched files go through `read_file` first.
+    pub fn open_file(&self, name: &str) -> Result<MpqFileReader<'_>> {
+        let fname = format_file_name(name);
+        let search_end = match self.file_map.get(&fname) {
+            Some(index) => *index + 1,
+            None => self.archives.len(),
+        };
+
+        match find_entry(&self.archives[..search_end], &fname) {
+            Some((_, info)) if is_deleted(&info) => Err(wow_mpq::Error::FileNotFound(fname).into()),
+            Some((_, info)) if info.flags & BlockEntry::FLAG_PATCH_FILE != 0 => {
+                Ok(MpqFileReader::from_bytes(self.read_file(name)?))
+            }
+            Some((index, info)) => MpqFileReader::open(&self.archives[index], name, &info),
+            None => Err(wow_mpq::Error::FileNotFound(fname).into()),
+        }
     }
 
+    /// Reads a file the way the client sees it: the topmost archive entry wins, deletion
+    /// markers hide everything below them and incremental `PTCH` entries are applied in load
+    /// order on top of the closest full copy of the file.
     pub fn read_file(&self, name: &str) -> Result<Vec<u8>> {
         let fname = format_file_name(name);
-        let index = self
-            .file_map
-            .get(&fname)
-            .ok_or_else(|| wow_mpq::Error::FileNotFound(fname))?;
-
-        if let Some(archive) = self.archives.get(*index) {
-            Ok(archive.lock().unwrap().read_file(name)?)
-        } else {
-            Err(Error::Generic("self.archives is invalid"))
+        let mut search_end = match self.file_map.get(&fname) {
+            Some(index) => *index + 1,
+            None => self.archives.len(),
+        };
+
+        let mut patches = Vec::new();
+        loop {
+            let (index, info) = find_entry(&self.archives[..search_end], &fname)
+                .ok_or_else(|| wow_mpq::Error::FileNotFound(fname.clone()))?;
+
+            if is_deleted(&info) {
+                return Err(wow_mpq::Error::FileNotFound(fname).into());
+            }
+
+            if info.flags & BlockEntry::FLAG_PATCH_FILE != 0 {
+                patches.push((index, info));
+                search_end = index;
+                continue;
+            }
+
+            let mut data = self.archives[index].read_entry(name, &info)?;
+            for (index, info) in patches.iter().rev() {
+                data = ptch::apply_patch(&data, &self.archives[*index].read_entry(name, info)?)?;
+            }
+
+            return Ok(data);
         }
     }
 
     pub fn file_list(&self) -> Vec<&String> {
         self.file_map.iter().map(|val| val.0).collect()
     }
-}
 
-pub struct MpqAssetReader {
-    mpq_collection: MpqCollection,
-}
+    pub fn is_directory(&self, name: &str) -> bool {
+        self.directories.is_directory(&format_file_name(name))
+    }
 
-impl MpqAssetReader {
-    pub fn new(mpq_paths: Vec<PathBuf>) -> Self {
-        Self {
-            mpq_collection: MpqCollection::load(mpq_paths).unwrap(),
-        }
+    pub fn read_directory(&self, name: &str) -> Option<Vec<PathBuf>> {
+        self.directories
+            .read_directory(&format_file_name(name))
+            .map(|children| children.map(PathBuf::from).collect())
     }
 }
 
-impl AssetReader for MpqAssetReader {
-    async fn read<'a>(&'a self, path: &'a Path) -> StdResult<VecReader, AssetReaderError> {
-        let bytes: Vec<u8> = self
-            .mpq_collection
-            .read_file(&path.to_str().unwrap())
-            .map_err(|err| match err {
-                Error::MpqError(err) => match err {
-                    wow_mpq::Error::Io(err) => err,
-                    _ => io::Error::new(io::ErrorKind::Other, err),
-                },
-                _ => io::Error::new(io::ErrorKind::Other, err),
-            })?;
+fn find_entry(archives: &[MpqArchive], name: &str) -> Option<(usize, FileInfo)> {
+    archives
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(idx, archive)| archive.find_file(name).map(|info| (idx, info)))
+}
 
-        Ok(VecReader::new(bytes))
-    }
+/// Counts the entries of `archive` missing from its `listed` names, besides the special files.
+fn count_unlisted(archive: &mut MpqArchive, listed: &HashSet<String>) -> Result<usize> {
+    let specials = SPECIAL_FILES
+        .iter()
+        .filter(|name| !listed.contains(**name) && archive.find_file(name).is_some())
+        .count();
+    let entries = archive.archive_mut().list_all()?.len();
+    Ok(entries.saturating_sub(listed.len() + specials))
+}
 
-    async fn read_meta<'a>(&'a self, path: &'a Path) -> StdResult<VecReader, AssetReaderError> {
-        Err(AssetReaderError::NotFound(path.into()))
-    }
+fn is_deleted(info: &FileInfo) -> bool {
+    info.flags & BlockEntry::FLAG_DELETE_MARKER != 0
+}
 
-    async fn read_directory<'a>(
-        &'a self,
-        _path: &'a Path,
-    ) -> StdResult<Box<PathStream>, AssetReaderError> {
-        Err(io::Error::new(
-            io::ErrorKind::Unsupported,
-            "unsupported operation read_directory",
-        )
-        .into())
-    }
+/// Asset reader over the client MPQs, the most common `GameDataAssetReader`.
+pub type MpqAssetReader = GameDataAssetReader<MpqCollection>;
 
-    async fn is_directory<'a>(&'a self, _path: &'a Path) -> StdResult<bool, AssetReaderError> {
-        Ok(false)
+impl GameDataAssetReader<MpqCollection> {
+    pub fn new(mpq_paths: Vec<PathBuf>) -> Self {
+        Self::from_collection(MpqCollection::load(mpq_paths).unwrap())
     }
 }
 
@@ -126,18 +295,15 @@ mod tests {
     use std::path::PathBuf;
 
     use super::*;
+    use writer::MpqWriter;
+
+    fn load_test_client() -> MpqCollection {
+        MpqCollection::load_client(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..")).unwrap()
+    }
 
     #[test]
     fn load_m2_with_skins() {
-        let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("..")
-            .join("Data");
-
-        let mpq_col = MpqCollection::load(vec![
-            base_path.join("common.MPQ"),
-            base_path.join("common-2.MPQ"),
-        ])
-        .unwrap();
+        let mpq_col = load_test_client();
 
         let fname = "World\\GENERIC\\HUMAN\\PASSIVE DOODADS\\Bottles\\Bottle01.m2";
         let data: Vec<u8> = mpq_col.read_file(fname).unwrap();
@@ -147,4 +313,257 @@ mod tests {
         let data: Vec<u8> = mpq_col.read_file(fname).unwrap();
         assert!(data.len() > 0);
     }
+
+    #[test]
+    fn read_from_many_threads() {
+        let path = std::env::temp_dir().join(format!("wow_vr_threads_{}.MPQ", std::process::id()));
+        let expected: Vec<u8> = (0..50_000u32).map(|i| (i % 253) as u8).collect();
+        MpqWriter::new()
+            .add_file("World\\Generic\\Bottle01.m2", expected.clone())
+            .write(&path)
+            .unwrap();
+
+        let mpq_col = MpqCollection::load(vec![path.clone()]).unwrap();
+        let fname = "WORLD\\GENERIC\\BOTTLE01.M2";
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| assert_eq!(mpq_col.read_file(fname).unwrap(), expected));
+            }
+        });
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn copy_patch(result: &[u8]) -> Vec<u8> {
+        let mut patch = Vec::new();
+        patch.extend_from_slice(b"PTCH");
+        patch.extend_from_slice(&(0x44 + result.len() as u32).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        patch.extend_from_slice(&(result.len() as u32).to_le_bytes());
+        patch.extend_from_slice(b"MD5_");
+        patch.extend_from_slice(&40u32.to_le_bytes());
+        patch.extend_from_slice(&[0u8; 32]);
+        patch.extend_from_slice(b"XFRM");
+        patch.extend_from_slice(&(12 + result.len() as u32).to_le_bytes());
+        patch.extend_from_slice(b"COPY");
+        patch.extend_from_slice(result);
+        patch
+    }
+
+    #[test]
+    fn patch_chain_applies_patches_and_deletions() {
+        let dir = std::env::temp_dir().join(format!("wow_vr_chain_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        MpqWriter::new()
+            .add_file("a.txt", b"base a".to_vec())
+            .add_file("b.txt", b"base b".to_vec())
+            .add_file("c.txt", b"base c".to_vec())
+            .write(dir.join("base.MPQ"))
+            .unwrap();
+        MpqWriter::new()
+            .add_file_with_options(
+                "a.txt",
+                copy_patch(b"patched a"),
+                writer::FileOptions {
+                    patch: true,
+                    ..Default::default()
+                },
+            )
+            .add_delete_marker("b.txt")
+            .write(dir.join("patch.MPQ"))
+            .unwrap();
+        // Hash-only entry, so the override has to be found without a listfile.
+        MpqWriter::new()
+            .with_listfile(false)
+            .add_file("c.txt", b"new c".to_vec())
+            .write(dir.join("patch-2.MPQ"))
+            .unwrap();
+
+        let mpq_col = MpqCollection::load(vec![
+            dir.join("base.MPQ"),
+            dir.join("patch.MPQ"),
+            dir.join("patch-2.MPQ"),
+        ])
+        .unwrap();
+
+        assert_eq!(mpq_col.read_file("a.txt").unwrap(), b"patched a");
+        assert!(mpq_col.read_file("b.txt").is_err());
+        assert!(!mpq_col.file_map.contains_key("b.txt"));
+        assert_eq!(mpq_col.read_file("c.txt").unwrap(), b"new c");
+        assert_eq!(mpq_col.file_map["c.txt"], 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unlisted_entries_are_found_by_hash() {
+        let dir = std::env::temp_dir().join(format!("wow_vr_unlisted_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let unlisted = writer::FileOptions {
+            unlisted: true,
+            ..Default::default()
+        };
+        MpqWriter::new()
+            .add_file("Textures\\Skin.blp", b"old skin".to_vec())
+            .write(dir.join("base.MPQ"))
+            .unwrap();
+        MpqWriter::new()
+            .add_file("Textures\\Listed.blp", b"listed".to_vec())
+            .add_file_with_options("Textures\\Skin.blp", b"new skin".to_vec(), unlisted)
+            .add_file_with_options("Textures\\Hidden.blp", b"hidden".to_vec(), unlisted)
+            .write(dir.join("patch.MPQ"))
+            .unwrap();
+
+        let mut mpq_col =
+            MpqCollection::load(vec![dir.join("base.MPQ"), dir.join("patch.MPQ")]).unwrap();
+
+        assert_eq!(mpq_col.file_map["textures/skin.blp"], 1);
+        assert_eq!(mpq_col.find_archive("TEXTURES/SKIN.BLP"), Some(1));
+        assert_eq!(
+            mpq_col.read_file("textures\\skin.blp").unwrap(),
+            b"new skin"
+        );
+
+        assert!(!mpq_col.file_map.contains_key("textures/hidden.blp"));
+        assert_eq!(mpq_col.find_archive("Textures\\Hidden.blp"), Some(1));
+        assert_eq!(
+            mpq_col.read_file("Textures\\Hidden.blp").unwrap(),
+            b"hidden"
+        );
+        assert_eq!(mpq_col.add_file_names(["Textures\\Hidden.blp"]), 1);
+        assert_eq!(mpq_col.file_map["textures/hidden.blp"], 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_cache_is_reused_until_an_archive_changes() {
+        let dir = std::env::temp_dir().join(format!("wow_vr_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("base.MPQ");
+        let cache_path = dir.join("index.cache");
+
+        MpqWriter::new()
+            .add_file("a.txt", b"a".to_vec())
+            .write(&archive)
+            .unwrap();
+        let mpq_col = MpqCollection::load_cached(vec![archive.clone()], &cache_path).unwrap();
+        assert!(mpq_col.file_map.contains_key("a.txt"));
+
+        let cache = IndexCache::read(&cache_path).unwrap();
+        assert_eq!(cache.file_map, mpq_col.file_map);
+
+        MpqWriter::new()
+            .add_file("b.txt", b"bb".to_vec())
+            .write(&archive)
+            .unwrap();
+        let fingerprints = vec![ArchiveFingerprint::new(&archive).unwrap()];
+        assert!(!cache.is_valid_for(&fingerprints));
+
+        let mpq_col = MpqCollection::load_cached(vec![archive.clone()], &cache_path).unwrap();
+        assert!(mpq_col.file_map.contains_key("b.txt"));
+        assert!(!mpq_col.file_map.contains_key("a.txt"));
+        assert!(
+            IndexCache::read(&cache_path)
+                .unwrap()
+                .is_valid_for(&fingerprints)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn provenance_lists_every_copy() {
+        use provenance::EntryRole;
+
+        let dir = std::env::temp_dir().join(format!("wow_vr_provenance_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        MpqWriter::new()
+            .add_file("a.txt", b"old a".to_vec())
+            .add_file("b.txt", b"old b".to_vec())
+            .write(dir.join("base.MPQ"))
+            .unwrap();
+        MpqWriter::new()
+            .add_file("a.txt", b"new a".to_vec())
+            .write(dir.join("patch.MPQ"))
+            .unwrap();
+        MpqWriter::new()
+            .add_file_with_options(
+                "a.txt",
+                copy_patch(b"patched a"),
+                writer::FileOptions {
+                    patch: true,
+                    ..Default::default()
+                },
+            )
+            .add_delete_marker("b.txt")
+            .write(dir.join("patch-2.MPQ"))
+            .unwrap();
+
+        let mpq_col = MpqCollection::load(vec![
+            dir.join("base.MPQ"),
+            dir.join("patch.MPQ"),
+            dir.join("patch-2.MPQ"),
+        ])
+        .unwrap();
+
+        let roles: Vec<(usize, EntryRole)> = mpq_col
+            .provenance("A.TXT")
+            .iter()
+            .map(|entry| (entry.archive_index, entry.role))
+            .collect();
+        assert_eq!(
+            roles,
+            vec![
+                (0, EntryRole::Shadowed),
+                (1, EntryRole::Base),
+                (2, EntryRole::AppliedPatch)
+            ]
+        );
+        assert_eq!(mpq_col.winning_archive("a.txt"), Some(2));
+
+        let roles: Vec<EntryRole> = mpq_col
+            .provenance("b.txt")
+            .iter()
+            .map(|entry| entry.role)
+            .collect();
+        assert_eq!(roles, vec![EntryRole::Shadowed, EntryRole::DeleteMarker]);
+        assert_eq!(mpq_col.winning_archive("b.txt"), None);
+        assert!(mpq_col.provenance("missing.txt").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_file_streams_sectors() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let path = std::env::temp_dir().join(format!("wow_vr_stream_{}.MPQ", std::process::id()));
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 241) as u8).collect();
+        MpqWriter::new()
+            .add_file("music/zone.mp3", data.clone())
+            .write(&path)
+            .unwrap();
+
+        let mpq_col = MpqCollection::load(vec![path.clone()]).unwrap();
+        let mut reader = mpq_col.open_file("Music\\Zone.mp3").unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+
+        let mut chunk = vec![0u8; 10_000];
+        reader.seek(SeekFrom::Start(40_000)).unwrap();
+        reader.read_exact(&mut chunk).unwrap();
+        assert_eq!(chunk, data[40_000..50_000]);
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        drop(reader);
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,86 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use wow_mpq::{Archive, FileInfo};
+
+use crate::errors::Result;
+
+use super::sector::StoredFile;
+
+/// An opened archive that can be read from many tasks at once. The parsed `Archive` is only used
+/// for its tables, while file data is decoded through `StoredFile` on a pooled file handle, so
+/// the pool lock is only held to check a handle out and back in.
+pub struct MpqArchive {
+    archive: Archive,
+    path: PathBuf,
+    handles: Mutex<Vec<BufReader<File>>>,
+}
+
+impl MpqArchive {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            archive: wow_mpq::OpenOptions::new().open(&path)?,
+            path,
+            handles: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn archive(&self) -> &Archive {
+        &self.archive
+    }
+
+    pub fn archive_mut(&mut self) -> &mut Archive {
+        &mut self.archive
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn find_file(&self, name: &str) -> Option<FileInfo> {
+        self.archive.find_file(name).ok().flatten()
+    }
+
+    /// Reads the stored bytes of an entry. For entries flagged as patches this is the raw `PTCH`
+    /// payload, the caller is responsible for applying it.
+    pub fn read_entry(&self, name: &str, info: &FileInfo) -> Result<Vec<u8>> {
+        self.with_reader(|reader| {
+            StoredFile::new(reader, &self.archive, name, info)?.read_all(reader)
+        })
+    }
+
+    pub(crate) fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&mut BufReader<File>) -> Result<T>,
+    ) -> Result<T> {
+        let mut reader = self.take_handle()?;
+        let result = f(&mut reader);
+        self.return_handle(reader);
+        result
+    }
+
+    /// Checks a file handle out of the pool, opening a new one when all are in use.
+    pub(crate) fn take_handle(&self) -> Result<BufReader<File>> {
+        let pooled = self.handles.lock().unwrap().pop();
+        match pooled {
+            Some(reader) => Ok(reader),
+            None => Ok(BufReader::new(File::open(&self.path)?)),
+        }
+    }
+
+    pub(crate) fn return_handle(&self, reader: BufReader<File>) {
+        self.handles.lock().unwrap().push(reader);
+    }
+}
+
+impl fmt::Debug for MpqArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {:#?}", self.path, self.archive.header())
+    }
+}
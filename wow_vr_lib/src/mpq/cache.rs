@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use md5::{Digest, Md5};
+
+use crate::errors::{Error, Result};
+
+const CACHE_MAGIC: &[u8; 4] = b"WVIC";
+const CACHE_VERSION: u32 = 1;
+
+// Covers the MPQ header and a leading user data header, which hold the table positions.
+const HEADER_HASH_SIZE: usize = 1024;
+
+/// What an archive looked like when the index was built, used to tell whether a cache is stale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFingerprint {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+    pub header_md5: [u8; 16],
+}
+
+impl ArchiveFingerprint {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut header = Vec::with_capacity(HEADER_HASH_SIZE);
+        File::open(path)?
+            .take(HEADER_HASH_SIZE as u64)
+            .read_to_end(&mut header)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            header_md5: Md5::digest(&header).into(),
+        })
+    }
+}
+
+/// Serialized `MpqCollection::file_map` together with the fingerprints of the archives it was
+/// built from.
+#[derive(Debug, Clone)]
+pub struct IndexCache {
+    pub fingerprints: Vec<ArchiveFingerprint>,
+    pub file_map: HashMap<String, usize>,
+}
+
+impl IndexCache {
+    pub fn is_valid_for(&self, fingerprints: &[ArchiveFingerprint]) -> bool {
+        self.fingerprints == fingerprints
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CACHE_MAGIC || reader.read_u32::<LittleEndian>()? != CACHE_VERSION {
+            return Err(Error::Generic("unsupported index cache"));
+        }
+
+        let archive_count = reader.read_u32::<LittleEndian>()? as usize;
+        let mut fingerprints = Vec::with_capacity(archive_count);
+        for _ in 0..archive_count {
+            let path = PathBuf::from(read_string(&mut reader)?);
+            let size = reader.read_u64::<LittleEndian>()?;
+            let modified_secs = reader.read_u64::<LittleEndian>()?;
+            let modified_nanos = reader.read_u32::<LittleEndian>()?;
+            let mut header_md5 = [0u8; 16];
+            reader.read_exact(&mut header_md5)?;
+            fingerprints.push(ArchiveFingerprint {
+                path,
+                size,
+                modified_secs,
+                modified_nanos,
+                header_md5,
+            });
+        }
+
+        let entry_count = reader.read_u32::<LittleEndian>()? as usize;
+        let mut file_map = HashMap::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            let name = read_string(&mut reader)?;
+            let index = reader.read_u32::<LittleEndian>()? as usize;
+            if index >= archive_count {
+                return Err(Error::Generic("index cache references a missing archive"));
+            }
+            file_map.insert(name, index);
+        }
+
+        Ok(Self {
+            fingerprints,
+            file_map,
+        })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Written next to the destination and renamed, so a crash never leaves a torn cache.
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_u32::<LittleEndian>(CACHE_VERSION)?;
+
+        writer.write_u32::<LittleEndian>(self.fingerprints.len() as u32)?;
+        for fingerprint in &self.fingerprints {
+            write_string(&mut writer, &fingerprint.path.to_string_lossy())?;
+            writer.write_u64::<LittleEndian>(fingerprint.size)?;
+            writer.write_u64::<LittleEndian>(fingerprint.modified_secs)?;
+            writer.write_u32::<LittleEndian>(fingerprint.modified_nanos)?;
+            writer.write_all(&fingerprint.header_md5)?;
+        }
+
+        writer.write_u32::<LittleEndian>(self.file_map.len() as u32)?;
+        for (name, index) in &self.file_map {
+            write_string(&mut writer, name)?;
+            writer.write_u32::<LittleEndian>(*index as u32)?;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    writer.write_u32::<LittleEndian>(value.len() as u32)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::errors::{Error, Result};
+
+const BASE_ARCHIVES: [&str; 4] = ["common", "common-2", "expansion", "lichking"];
+
+const LOCALE_ARCHIVES: [&str; 6] = [
+    "locale",
+    "speech",
+    "expansion-locale",
+    "expansion-speech",
+    "lichking-locale",
+    "lichking-speech",
+];
+
+/// A 3.3.5 client `Data` directory and the locales installed in it.
+#[derive(Debug, Clone)]
+pub struct ClientData {
+    pub data_dir: PathBuf,
+    /// Installed locales, with the one selected in `WTF/Config.wtf` first when it is set.
+    pub locales: Vec<String>,
+}
+
+impl ClientData {
+    /// Accepts either the client root or its `Data` directory.
+    pub fn discover(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let data_dir = match find_entry(root, "data") {
+            Some(path) if path.is_dir() => path,
+            _ if find_entry(root, "common.mpq").is_some() => root.to_path_buf(),
+            _ => return Err(Error::ClientDataNotFound(root.display().to_string())),
+        };
+
+        let mut locales = Vec::new();
+        for entry in fs::read_dir(&data_dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_dir()
+                && name.len() == 4
+                && find_entry(&path, &format!("locale-{}.mpq", name)).is_some()
+            {
+                locales.push(name.to_owned());
+            }
+        }
+        locales.sort();
+
+        let configured = data_dir
+            .parent()
+            .and_then(|client_root| find_entry(client_root, "wtf"))
+            .and_then(|wtf| find_entry(&wtf, "config.wtf"))
+            .and_then(|config| configured_locale(&fs::read_to_string(config).ok()?));
+        if let Some(pos) = configured.and_then(|configured| {
+            locales
+                .iter()
+                .position(|locale| locale.eq_ignore_ascii_case(&configured))
+        }) {
+            let locale = locales.remove(pos);
+            locales.insert(0, locale);
+        }
+
+        Ok(Self { data_dir, locales })
+    }
+
+    pub fn locale(&self) -> Option<&str> {
+        self.locales.first().map(String::as_str)
+    }
+
+    /// Archives in the order the client mounts them for its primary locale, lowest priority
+    /// first, so it can be passed straight to `MpqCollection::load`.
+    pub fn load_order(&self) -> Vec<PathBuf> {
+        self.load_order_for_locale(self.locale())
+    }
+
+    pub fn load_order_for_locale(&self, locale: Option<&str>) -> Vec<PathBuf> {
+        let data_files = list_dir(&self.data_dir);
+        let locale_files = locale
+            .and_then(|locale| data_files.get(&locale.to_lowercase()))
+            .map(|dir| list_dir(dir))
+            .unwrap_or_default();
+
+        let mut paths = Vec::new();
+        for name in BASE_ARCHIVES {
+            paths.extend(data_files.get(&format!("{}.mpq", name)).cloned());
+        }
+
+        if let Some(locale) = locale {
+            for name in LOCALE_ARCHIVES {
+                let file_name = format!("{}-{}.mpq", name, locale).to_lowercase();
+                paths.extend(locale_files.get(&file_name).cloned());
+            }
+        }
+
+        paths.extend(patch_archives(&data_files, "patch"));
+        if let Some(locale) = locale {
+            let stem = format!("patch-{}", locale).to_lowercase();
+            paths.extend(patch_archives(&locale_files, &stem));
+        }
+
+        paths
+    }
+}
+
+// Matches `<stem>.MPQ` followed by the single character `<stem>-N.MPQ` archives, digits before
+// letters, which is how the client picks up both its own and custom patch archives.
+fn patch_archives(files: &HashMap<String, PathBuf>, stem: &str) -> Vec<PathBuf> {
+    let mut numbered: Vec<(char, &PathBuf)> = files
+        .iter()
+        .filter_map(|(name, path)| {
+            let suffix = name
+                .strip_prefix(stem)?
+                .strip_prefix('-')?
+                .strip_suffix(".mpq")?;
+            let mut chars = suffix.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => Some((c, path)),
+                _ => None,
+            }
+        })
+        .collect();
+    numbered.sort_by_key(|(c, _)| (!c.is_ascii_digit(), *c));
+
+    files
+        .get(&format!("{}.mpq", stem))
+        .into_iter()
+        .chain(numbered.into_iter().map(|(_, path)| path))
+        .cloned()
+        .collect()
+}
+
+fn configured_locale(config: &str) -> Option<String> {
+    config.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("SET ")?.trim_start();
+        let value = rest.strip_prefix("locale")?.trim();
+        Some(value.trim_matches('"').to_owned())
+    })
+}
+
+// Client installs often come from Windows, so names are matched case-insensitively.
+fn list_dir(dir: &Path) -> HashMap<String, PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.to_lowercase();
+            Some((name, path))
+        })
+        .collect()
+}
+
+fn find_entry(dir: &Path, lowercase_name: &str) -> Option<PathBuf> {
+    list_dir(dir).remove(lowercase_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_client_load_order() {
+        let root = std::env::temp_dir().join(format!("wow_vr_client_{}", std::process::id()));
+        let data = root.join("Data");
+        fs::create_dir_all(data.join("enUS")).unwrap();
+        fs::create_dir_all(data.join("deDE")).unwrap();
+        fs::create_dir_all(root.join("WTF")).unwrap();
+        fs::write(
+            root.join("WTF/Config.wtf"),
+            "SET gxApi \"d3d9\"\nSET locale \"enUS\"\n",
+        )
+        .unwrap();
+
+        for name in [
+            "common.MPQ",
+            "common-2.MPQ",
+            "expansion.MPQ",
+            "lichking.MPQ",
+            "patch.MPQ",
+            "patch-3.MPQ",
+            "patch-2.MPQ",
+            "Patch-x.MPQ",
+            "patch-old.MPQ",
+            "enUS/locale-enUS.MPQ",
+            "enUS/lichking-locale-enUS.MPQ",
+            "enUS/patch-enUS.MPQ",
+            "enUS/patch-enUS-2.MPQ",
+            "deDE/locale-deDE.MPQ",
+        ] {
+            fs::write(data.join(name), b"").unwrap();
+        }
+
+        let client = ClientData::discover(&root).unwrap();
+        assert_eq!(client.locales, vec!["enUS", "deDE"]);
+
+        let order: Vec<String> = client
+            .load_order()
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&data)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "common.MPQ",
+                "common-2.MPQ",
+                "expansion.MPQ",
+                "lichking.MPQ",
+                "enUS/locale-enUS.MPQ",
+                "enUS/lichking-locale-enUS.MPQ",
+                "patch.MPQ",
+                "patch-2.MPQ",
+                "patch-3.MPQ",
+                "Patch-x.MPQ",
+                "enUS/patch-enUS.MPQ",
+                "enUS/patch-enUS-2.MPQ",
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
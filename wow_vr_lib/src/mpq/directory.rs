@@ -0,0 +1,92 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Virtual directory tree over the normalized names in `MpqCollection::file_map`. Keys are
+/// lowercase directory paths using `/` separators, with `""` for the archive root, and values are
+/// the full paths of the files and directories directly inside them.
+#[derive(Debug, Default, Clone)]
+pub struct DirectoryIndex {
+    entries: HashMap<String, BTreeSet<String>>,
+}
+
+impl DirectoryIndex {
+    pub fn from_files<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Self {
+        let mut index = Self::default();
+        for name in names {
+            index.insert_file(name.as_ref());
+        }
+        index
+    }
+
+    /// Adds a file and every missing parent directory. `name` must already be normalized.
+    pub fn insert_file(&mut self, name: &str) {
+        let mut child = name;
+        loop {
+            let parent = match child.rfind('/') {
+                Some(pos) => &child[..pos],
+                None => "",
+            };
+
+            let children = self.entries.entry(parent.to_owned()).or_default();
+            let is_new_dir = children.is_empty() && !parent.is_empty();
+            children.insert(child.to_owned());
+
+            if !is_new_dir {
+                break;
+            }
+            child = parent;
+        }
+    }
+
+    pub fn is_directory(&self, path: &str) -> bool {
+        self.entries.contains_key(trim_dir(path))
+    }
+
+    pub fn read_directory(&self, path: &str) -> Option<impl Iterator<Item = &String>> {
+        self.entries
+            .get(trim_dir(path))
+            .map(|children| children.iter())
+    }
+}
+
+fn trim_dir(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_nested_directories() {
+        let index = DirectoryIndex::from_files([
+            "world/generic/human/bottle01.m2",
+            "world/generic/human/bottle01.blp",
+            "world/generic/orc/axe.m2",
+            "dbfilesclient/map.dbc",
+        ]);
+
+        assert!(index.is_directory(""));
+        assert!(index.is_directory("world/generic/"));
+        assert!(!index.is_directory("world/generic/orc/axe.m2"));
+
+        let root: Vec<&String> = index.read_directory("").unwrap().collect();
+        assert_eq!(root, vec!["dbfilesclient", "world"]);
+
+        let generic: Vec<&String> = index.read_directory("world/generic").unwrap().collect();
+        assert_eq!(generic, vec!["world/generic/human", "world/generic/orc"]);
+
+        let human: Vec<&String> = index
+            .read_directory("world/generic/human")
+            .unwrap()
+            .collect();
+        assert_eq!(
+            human,
+            vec![
+                "world/generic/human/bottle01.blp",
+                "world/generic/human/bottle01.m2"
+            ]
+        );
+
+        assert!(index.read_directory("world/missing").is_none());
+    }
+}
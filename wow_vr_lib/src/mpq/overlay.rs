@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    result::Result as StdResult,
+    sync::{Arc, RwLock},
+};
+
+use bevy::tasks::futures_lite::stream;
+use bevy_asset::io::{AssetReader, AssetReaderError, PathStream, Reader, VecReader};
+
+use super::{MpqCollection, format_file_name};
+use crate::data::{GameData, GameDataAssetReader};
+
+/// Normalized names of everything under the loose directory with their paths on disk, so lookups
+/// don't touch the file system. Clones share the same entries, `OverlayWatcher` keeps them in
+/// sync with the disk.
+#[derive(Debug, Clone)]
+pub struct LooseIndex {
+    root: PathBuf,
+    entries: Arc<RwLock<HashMap<String, PathBuf>>>,
+}
+
+impl LooseIndex {
+    /// Walks `root` once and indexes every file and directory below it.
+    pub fn scan(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let mut entries = HashMap::new();
+        insert_tree(&mut entries, String::new(), &root);
+        Self {
+            root,
+            entries: Arc::new(RwLock::new(entries)),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The on-disk path of a name in any case and with either separator.
+    pub fn get(&self, name: &str) -> Option<PathBuf> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(&normalize_name(name))
+            .cloned()
+    }
+
+    /// Re-reads `path`, relative to the root, and everything below it after a change on disk.
+    pub fn refresh(&self, path: &Path) {
+        let name = normalize_name(&path.to_string_lossy());
+        let prefix = format!("{}/", name);
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|key, _| *key != name && !key.starts_with(&prefix));
+
+        let full_path = self.root.join(path);
+        if !full_path.exists() {
+            return;
+        }
+        for ancestor in path.ancestors().skip(1) {
+            let ancestor_name = normalize_name(&ancestor.to_string_lossy());
+            if entries.contains_key(&ancestor_name) {
+                break;
+            }
+            entries.insert(ancestor_name, self.root.join(ancestor));
+        }
+        insert_tree(&mut entries, name, &full_path);
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    format_file_name(name)
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn insert_tree(entries: &mut HashMap<String, PathBuf>, name: String, path: &Path) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            let child = format_file_name(&entry.file_name().to_string_lossy());
+            let child_name = if name.is_empty() {
+                child
+            } else {
+                format!("{}/{}", name, child)
+            };
+            insert_tree(entries, child_name, &entry.path());
+        }
+    }
+    entries.insert(name, path.to_path_buf());
+}
+
+/// Asset reader that serves loose files from a directory before falling back to the MPQs, the
+/// way the client honors loose files under `Data/`. Loose paths are matched with the same
+/// normalization as archive names, so `World\Foo.BLP` on disk replaces `world/foo.blp`.
+pub struct OverlayAssetReader<D: GameData = MpqCollection> {
+    loose: LooseIndex,
+    mpq_reader: GameDataAssetReader<D>,
+}
+
+impl<D: GameData> OverlayAssetReader<D> {
+    pub fn new(loose_dir: impl Into<PathBuf>, mpq_reader: GameDataAssetReader<D>) -> Self {
+        Self::with_index(LooseIndex::scan(loose_dir), mpq_reader)
+    }
+
+    /// Serves the loose files of an index shared with an `OverlayWatcher`.
+    pub fn with_index(loose: LooseIndex, mpq_reader: GameDataAssetReader<D>) -> Self {
+        Self { loose, mpq_reader }
+    }
+
+    pub fn loose_dir(&self) -> &Path {
+        self.loose.root()
+    }
+
+    /// Finds the on-disk path for a normalized name. Loose files usually keep the mixed case
+    /// they were extracted with, so they are looked up in the lowercase index.
+    pub fn find_loose(&self, name: &str) -> Option<PathBuf> {
+        self.loose.get(name)
+    }
+
+    fn loose_children(&self, name: &str) -> Option<Vec<PathBuf>> {
+        let dir = self.find_loose(name).filter(|path| path.is_dir())?;
+        let prefix = format_file_name(name).trim_matches('/').to_owned();
+
+        let children = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let child = format_file_name(&entry.file_name().to_string_lossy());
+                if prefix.is_empty() {
+                    PathBuf::from(child)
+                } else {
+                    PathBuf::from(format!("{}/{}", prefix, child))
+                }
+            })
+            .collect();
+
+        Some(children)
+    }
+}
+
+impl<D: GameData> AssetReader for OverlayAssetReader<D> {
+    async fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<dyn Reader + 'a>, AssetReaderError> {
+        match self.find_loose(&path.to_string_lossy()) {
+            Some(loose) if loose.is_file() => Ok(Box::new(VecReader::new(fs::read(loose)?))),
+            _ => self.mpq_reader.read(path).await,
+        }
+    }
+
+    async fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<dyn Reader + 'a>, AssetReaderError> {
+        let meta_name = format!("{}.meta", path.to_string_lossy());
+        match self.find_loose(&meta_name) {
+            Some(loose) if loose.is_file() => Ok(Box::new(VecReader::new(fs::read(loose)?))),
+            _ => self.mpq_reader.read_meta(path).await,
+        }
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> StdResult<Box<PathStream>, AssetReaderError> {
+        let name = path.to_string_lossy();
+        let loose = self.loose_children(&name);
+        let packed = self.mpq_reader.collection().read_directory(&name);
+        if loose.is_none() && packed.is_none() {
+            return Err(AssetReaderError::NotFound(path.into()));
+        }
+
+        let children: BTreeSet<PathBuf> = loose
+            .into_iter()
+            .chain(packed)
+            .flatten()
+            .filter(|child| !child.to_string_lossy().ends_with(".meta"))
+            .collect();
+
+        Ok(Box::new(stream::iter(children)))
+    }
+
+    async fn is_directory<'a>(&'a self, path: &'a Path) -> StdResult<bool, AssetReaderError> {
+        let name = path.to_string_lossy();
+        Ok(self.find_loose(&name).is_some_and(|loose| loose.is_dir())
+            || self.mpq_reader.collection().is_directory(&name))
+    }
+}
+
+#[cfg(feature = "file_watcher")]
+pub use watcher::OverlayWatcher;
+
+#[cfg(feature = "file_watcher")]
+mod watcher {
+    use std::{path::PathBuf, thread, time::Duration};
+
+    use bevy_asset::io::{AssetSourceEvent, AssetWatcher, file::FileWatcher};
+    use crossbeam_channel::Sender;
+
+    use super::{LooseIndex, format_file_name};
+
+    /// Watches the loose directory, keeps `index` current and reports changes under their
+    /// normalized names, so they match the paths assets were loaded with.
+    pub struct OverlayWatcher {
+        _watcher: FileWatcher,
+    }
+
+    impl AssetWatcher for OverlayWatcher {}
+
+    impl OverlayWatcher {
+        pub fn new(
+            index: LooseIndex,
+            sender: Sender<AssetSourceEvent>,
+            debounce_wait_time: Duration,
+        ) -> Option<Self> {
+            let (loose_sender, receiver) = crossbeam_channel::unbounded();
+            let watcher =
+                FileWatcher::new(index.root().to_path_buf(), loose_sender, debounce_wait_time)
+                    .ok()?;
+
+            // Ends once the file watcher is dropped and its sender with it.
+            thread::spawn(move || {
+                for event in receiver {
+                    for path in event_paths(&event) {
+                        index.refresh(path);
+                    }
+                    if sender.send(normalize_event(event)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Some(Self { _watcher: watcher })
+        }
+    }
+
+    fn event_paths(event: &AssetSourceEvent) -> Vec<&PathBuf> {
+        use AssetSourceEvent::*;
+
+        match event {
+            AddedAsset(path)
+            | ModifiedAsset(path)
+            | RemovedAsset(path)
+            | AddedMeta(path)
+            | ModifiedMeta(path)
+            | RemovedMeta(path)
+            | AddedFolder(path)
+            | RemovedFolder(path)
+            | RemovedUnknown { path, .. } => vec![path],
+            RenamedAsset { old, new } | RenamedMeta { old, new } | RenamedFolder { old, new } => {
+                vec![old, new]
+            }
+        }
+    }
+
+    fn normalize(path: PathBuf) -> PathBuf {
+        PathBuf::from(format_file_name(&path.to_string_lossy()))
+    }
+
+    fn normalize_event(event: AssetSourceEvent) -> AssetSourceEvent {
+        use AssetSourceEvent::*;
+
+        match event {
+            AddedAsset(path) => AddedAsset(normalize(path)),
+            ModifiedAsset(path) => ModifiedAsset(normalize(path)),
+            RemovedAsset(path) => RemovedAsset(normalize(path)),
+            RenamedAsset { old, new } => RenamedAsset {
+                old: normalize(old),
+                new: normalize(new),
+            },
+            AddedMeta(path) => AddedMeta(normalize(path)),
+            ModifiedMeta(path) => ModifiedMeta(normalize(path)),
+            RemovedMeta(path) => RemovedMeta(normalize(path)),
+            RenamedMeta { old, new } => RenamedMeta {
+                old: normalize(old),
+                new: normalize(new),
+            },
+            AddedFolder(path) => AddedFolder(normalize(path)),
+            RemovedFolder(path) => RemovedFolder(normalize(path)),
+            RenamedFolder { old, new } => RenamedFolder {
+                old: normalize(old),
+                new: normalize(new),
+            },
+            RemovedUnknown { path, is_meta } => RemovedUnknown {
+                path: normalize(path),
+                is_meta,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::block_on;
+
+    use super::*;
+    use crate::mpq::{MpqAssetReader, directory::DirectoryIndex};
+
+    #[test]
+    fn loose_files_shadow_archives() {
+        let loose_dir = std::env::temp_dir().join(format!("wow_vr_overlay_{}", std::process::id()));
+        fs::create_dir_all(loose_dir.join("World/Generic")).unwrap();
+        fs::write(loose_dir.join("World/Generic/Bottle01.BLP"), b"loose").unwrap();
+
+        let collection = MpqCollection {
+            archives: Vec::new(),
+            file_map: Default::default(),
+            directories: DirectoryIndex::default(),
+        };
+        let reader =
+            OverlayAssetReader::new(&loose_dir, MpqAssetReader::from_collection(collection));
+
+        assert_eq!(
+            reader.find_loose("WORLD\\GENERIC\\bottle01.blp"),
+            Some(loose_dir.join("World/Generic/Bottle01.BLP"))
+        );
+        assert!(reader.find_loose("world/generic/missing.blp").is_none());
+        assert!(block_on(reader.is_directory(Path::new("world/generic"))).unwrap());
+        assert_eq!(
+            reader.loose_children("world"),
+            Some(vec![PathBuf::from("world/generic")])
+        );
+
+        fs::remove_dir_all(&loose_dir).unwrap();
+    }
+
+    #[test]
+    fn loose_index_follows_refreshed_paths() {
+        let loose_dir =
+            std::env::temp_dir().join(format!("wow_vr_overlay_index_{}", std::process::id()));
+        fs::create_dir_all(loose_dir.join("World")).unwrap();
+        let index = LooseIndex::scan(&loose_dir);
+        assert_eq!(index.get(""), Some(loose_dir.clone()));
+        assert!(index.get("world/generic/new.blp").is_none());
+
+        fs::create_dir_all(loose_dir.join("World/Generic")).unwrap();
+        fs::write(loose_dir.join("World/Generic/New.BLP"), b"new").unwrap();
+        index.refresh(Path::new("World/Generic/New.BLP"));
+        assert_eq!(
+            index.get("WORLD\\generic\\new.blp"),
+            Some(loose_dir.join("World/Generic/New.BLP"))
+        );
+        assert_eq!(
+            index.get("world/generic/"),
+            Some(loose_dir.join("World/Generic"))
+        );
+
+        fs::remove_dir_all(loose_dir.join("World")).unwrap();
+        index.refresh(Path::new("World"));
+        assert!(index.get("world").is_none());
+        assert!(index.get("world/generic/new.blp").is_none());
+
+        fs::remove_dir_all(&loose_dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use wow_mpq::{BlockEntry, FileInfo};
+
+use super::{MpqCollection, format_file_name};
+
+/// What an archive entry contributes to the bytes `MpqCollection::read_file` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryRole {
+    /// The full copy that is read, before any patches are applied.
+    Base,
+    /// An incremental `PTCH` entry applied on top of the base copy.
+    AppliedPatch,
+    /// A deletion marker that hides every entry below it.
+    DeleteMarker,
+    /// An older entry overridden by something above it.
+    Shadowed,
+}
+
+/// One archive entry for a file, as returned by `MpqCollection::provenance`.
+#[derive(Debug, Clone)]
+pub struct FileProvenance {
+    pub archive_index: usize,
+    pub archive_path: PathBuf,
+    pub file_size: u64,
+    pub compressed_size: u64,
+    pub flags: u32,
+    pub role: EntryRole,
+}
+
+impl FileProvenance {
+    pub fn is_compressed(&self) -> bool {
+        self.flags & (BlockEntry::FLAG_COMPRESS | BlockEntry::FLAG_IMPLODE) != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & BlockEntry::FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_patch(&self) -> bool {
+        self.flags & BlockEntry::FLAG_PATCH_FILE != 0
+    }
+
+    /// Whether this entry affects what is read, as opposed to being shadowed.
+    pub fn is_used(&self) -> bool {
+        self.role != EntryRole::Shadowed
+    }
+}
+
+impl MpqCollection {
+    /// Lists every archive holding `name` in load order, marking which entries are read, patched
+    /// in, deleted or shadowed, following the same rules as `read_file`.
+    pub fn provenance(&self, name: &str) -> Vec<FileProvenance> {
+        let fname = format_file_name(name);
+        let entries: Vec<(usize, FileInfo)> = self
+            .archives
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, archive)| archive.find_file(&fname).map(|info| (idx, info)))
+            .collect();
+
+        let mut resolved = false;
+        let mut result: Vec<FileProvenance> = entries
+            .into_iter()
+            .rev()
+            .map(|(archive_index, info)| {
+                let role = if resolved {
+                    EntryRole::Shadowed
+                } else if info.flags & BlockEntry::FLAG_DELETE_MARKER != 0 {
+                    resolved = true;
+                    EntryRole::DeleteMarker
+                } else if info.flags & BlockEntry::FLAG_PATCH_FILE != 0 {
+                    EntryRole::AppliedPatch
+                } else {
+                    resolved = true;
+                    EntryRole::Base
+                };
+
+                FileProvenance {
+                    archive_index,
+                    archive_path: self.archives[archive_index].path().to_path_buf(),
+                    file_size: info.file_size,
+                    compressed_size: info.compressed_size,
+                    flags: info.flags,
+                    role,
+                }
+            })
+            .collect();
+
+        result.reverse();
+        result
+    }
+
+    /// The entry `read_file` starts from, if the file is visible at all.
+    pub fn winning_archive(&self, name: &str) -> Option<usize> {
+        self.provenance(name)
+            .iter()
+            .rev()
+            .find(|entry| entry.is_used())
+            .filter(|entry| entry.role != EntryRole::DeleteMarker)
+            .map(|entry| entry.archive_index)
+    }
+}
@@ -0,0 +1,312 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use md5::{Digest, Md5};
+
+use crate::errors::{Error, Result};
+
+const PTCH_SIGNATURE: u32 = 0x48435450;
+const MD5_SIGNATURE: u32 = 0x5f35444d;
+const XFRM_SIGNATURE: u32 = 0x4d524658;
+const BSD0_SIGNATURE: u32 = 0x30445342;
+const COPY_SIGNATURE: u32 = 0x59504f43;
+const BSDIFF40_SIGNATURE: &[u8; 8] = b"BSDIFF40";
+
+const PTCH_HEADER_SIZE: usize = 0x44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchType {
+    Bsd0,
+    Copy,
+}
+
+#[derive(Debug, Clone)]
+pub struct PatchHeader {
+    pub patch_data_size: u32,
+    pub size_before: u32,
+    pub size_after: u32,
+    pub md5_before: [u8; 16],
+    pub md5_after: [u8; 16],
+    pub patch_type: PatchType,
+    pub xfrm_block_size: u32,
+}
+
+impl PatchHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < PTCH_HEADER_SIZE {
+            return Err(Error::PatchError("truncated PTCH header".into()));
+        }
+
+        let mut reader = Cursor::new(data);
+        if reader.read_u32::<LittleEndian>()? != PTCH_SIGNATURE {
+            return Err(Error::PatchError("missing PTCH signature".into()));
+        }
+        let patch_data_size = reader.read_u32::<LittleEndian>()?;
+        let size_before = reader.read_u32::<LittleEndian>()?;
+        let size_after = reader.read_u32::<LittleEndian>()?;
+
+        if reader.read_u32::<LittleEndian>()? != MD5_SIGNATURE {
+            return Err(Error::PatchError("missing MD5_ block".into()));
+        }
+        let _md5_block_size = reader.read_u32::<LittleEndian>()?;
+        let mut md5_before = [0u8; 16];
+        reader.read_exact(&mut md5_before)?;
+        let mut md5_after = [0u8; 16];
+        reader.read_exact(&mut md5_after)?;
+
+        if reader.read_u32::<LittleEndian>()? != XFRM_SIGNATURE {
+            return Err(Error::PatchError("missing XFRM block".into()));
+        }
+        let xfrm_block_size = reader.read_u32::<LittleEndian>()?;
+        let patch_type = match reader.read_u32::<LittleEndian>()? {
+            BSD0_SIGNATURE => PatchType::Bsd0,
+            COPY_SIGNATURE => PatchType::Copy,
+            other => {
+                return Err(Error::PatchError(format!(
+                    "unsupported patch type 0x{:08x}",
+                    other
+                )));
+            }
+        };
+
+        Ok(Self {
+            patch_data_size,
+            size_before,
+            size_after,
+            md5_before,
+            md5_after,
+            patch_type,
+            xfrm_block_size,
+        })
+    }
+}
+
+/// Applies a `PTCH` payload from a patch archive on top of `base`, the way the client does when
+/// it walks the patch chain. Both the before and after MD5s stored in the patch are checked.
+pub fn apply_patch(base: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let header = PatchHeader::parse(patch)?;
+
+    if header.md5_before != [0u8; 16] && Md5::digest(base).as_slice() != header.md5_before {
+        return Err(Error::PatchError("base file md5 mismatch".into()));
+    }
+
+    // The XFRM block size includes its own 12 byte header.
+    let data_end = (header.xfrm_block_size as usize)
+        .checked_sub(12)
+        .map(|size| (PTCH_HEADER_SIZE + size).min(patch.len()))
+        .filter(|end| *end >= PTCH_HEADER_SIZE)
+        .ok_or_else(|| Error::PatchError("corrupt XFRM block size".into()))?;
+    let patch_data = &patch[PTCH_HEADER_SIZE..data_end];
+    let size_after = header.size_after as usize;
+
+    let result = match header.patch_type {
+        PatchType::Copy => patch_data.to_vec(),
+        PatchType::Bsd0 => {
+            let expanded_size = (header.patch_data_size as usize).saturating_sub(PTCH_HEADER_SIZE);
+            if patch_data.len() < expanded_size {
+                apply_bsdiff(base, &decompress_rle(patch_data, expanded_size), size_after)?
+            } else {
+                apply_bsdiff(base, patch_data, size_after)?
+            }
+        }
+    };
+
+    if result.len() != size_after {
+        return Err(Error::PatchError(format!(
+            "patched size {} does not match expected {}",
+            result.len(),
+            header.size_after
+        )));
+    }
+
+    if header.md5_after != [0u8; 16] && Md5::digest(&result).as_slice() != header.md5_after {
+        return Err(Error::PatchError("patched file md5 mismatch".into()));
+    }
+
+    Ok(result)
+}
+
+pub fn is_patch_data(data: &[u8]) -> bool {
+    data.len() >= 4 && u32::from_le_bytes([data[0], data[1], data[2], data[3]]) == PTCH_SIGNATURE
+}
+
+// Blizzard's RLE variant: a leading u32 with the decompressed size, then runs where a byte with
+// the high bit set copies the next `(b & 0x7f) + 1` bytes and anything else skips `b + 1` zeros.
+fn decompress_rle(data: &[u8], size: usize) -> Vec<u8> {
+    let mut out = vec![0u8; size];
+    let mut src = 4.min(data.len());
+    let mut dst = 0;
+
+    while src < data.len() && dst < size {
+        let byte = data[src];
+        src += 1;
+
+        if byte & 0x80 != 0 {
+            let count = ((byte & 0x7f) as usize) + 1;
+            for _ in 0..count {
+                if dst == size || src == data.len() {
+                    break;
+                }
+                out[dst] = data[src];
+                dst += 1;
+                src += 1;
+            }
+        } else {
+            dst += byte as usize + 1;
+        }
+    }
+
+    out
+}
+
+// bsdiff 4.0 with uncompressed control, diff and extra blocks and 32 bit control values.
+// `expected_size` is the size the PTCH header promises, checked before allocating.
+fn apply_bsdiff(old: &[u8], patch: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+    if patch.len() < 32 || &patch[..8] != BSDIFF40_SIGNATURE {
+        return Err(Error::PatchError("missing BSDIFF40 signature".into()));
+    }
+
+    let mut reader = Cursor::new(&patch[8..32]);
+    let ctrl_size = reader.read_u64::<LittleEndian>()? as usize;
+    let data_size = reader.read_u64::<LittleEndian>()? as usize;
+    let new_size = reader.read_u64::<LittleEndian>()? as usize;
+
+    if new_size != expected_size {
+        return Err(Error::PatchError(format!(
+            "bsdiff size {} does not match expected {}",
+            new_size, expected_size
+        )));
+    }
+
+    let ctrl_start = 32usize;
+    let Some(data_start) = ctrl_start.checked_add(ctrl_size) else {
+        return Err(Error::PatchError("corrupt bsdiff control size".into()));
+    };
+    let extra_start = match data_start.checked_add(data_size) {
+        Some(extra_start) if extra_start <= patch.len() => extra_start,
+        _ => return Err(Error::PatchError("truncated bsdiff blocks".into())),
+    };
+
+    let mut ctrl = Cursor::new(&patch[ctrl_start..data_start]);
+    let mut diff = &patch[data_start..extra_start];
+    let mut extra = &patch[extra_start..];
+
+    let mut new = vec![0u8; new_size];
+    let mut new_offset = 0usize;
+    let mut old_offset = 0u32;
+
+    while new_offset < new_size {
+        let add_len = ctrl.read_u32::<LittleEndian>()? as usize;
+        let copy_len = ctrl.read_u32::<LittleEndian>()? as usize;
+        let seek = ctrl.read_u32::<LittleEndian>()?;
+
+        if new_offset + add_len > new_size || add_len > diff.len() {
+            return Err(Error::PatchError("corrupt bsdiff diff block".into()));
+        }
+        new[new_offset..new_offset + add_len].copy_from_slice(&diff[..add_len]);
+        diff = &diff[add_len..];
+        for i in 0..add_len {
+            if let Some(byte) = old.get(old_offset as usize + i) {
+                new[new_offset + i] = new[new_offset + i].wrapping_add(*byte);
+            }
+        }
+        new_offset += add_len;
+        old_offset = old_offset.wrapping_add(add_len as u32);
+
+        if new_offset + copy_len > new_size || copy_len > extra.len() {
+            return Err(Error::PatchError("corrupt bsdiff extra block".into()));
+        }
+        new[new_offset..new_offset + copy_len].copy_from_slice(&extra[..copy_len]);
+        extra = &extra[copy_len..];
+        new_offset += copy_len;
+
+        // Seek values are sign-magnitude rather than two's complement.
+        let seek = if seek & 0x80000000 != 0 {
+            0x80000000u32.wrapping_sub(seek)
+        } else {
+            seek
+        };
+        old_offset = old_offset.wrapping_add(seek);
+    }
+
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_patch(base: &[u8], result: &[u8], patch_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut patch = Vec::new();
+        patch.extend_from_slice(b"PTCH");
+        patch.extend_from_slice(&((PTCH_HEADER_SIZE + data.len()) as u32).to_le_bytes());
+        patch.extend_from_slice(&(base.len() as u32).to_le_bytes());
+        patch.extend_from_slice(&(result.len() as u32).to_le_bytes());
+        patch.extend_from_slice(b"MD5_");
+        patch.extend_from_slice(&40u32.to_le_bytes());
+        patch.extend_from_slice(Md5::digest(base).as_slice());
+        patch.extend_from_slice(Md5::digest(result).as_slice());
+        patch.extend_from_slice(b"XFRM");
+        patch.extend_from_slice(&((12 + data.len()) as u32).to_le_bytes());
+        patch.extend_from_slice(patch_type);
+        patch.extend_from_slice(data);
+        patch
+    }
+
+    #[test]
+    fn apply_copy_and_bsd0_patches() {
+        let base = b"hello world";
+
+        let copy = build_patch(base, b"replaced", b"COPY", b"replaced");
+        assert!(is_patch_data(&copy));
+        assert_eq!(apply_patch(base, &copy).unwrap(), b"replaced");
+
+        // "hello world" -> "hello WORLD!": add 6 bytes from old, add 5 adjusted bytes, copy "!".
+        let result = b"hello WORLD!";
+        let mut bsdiff = Vec::new();
+        bsdiff.extend_from_slice(BSDIFF40_SIGNATURE);
+        bsdiff.extend_from_slice(&12u64.to_le_bytes());
+        bsdiff.extend_from_slice(&11u64.to_le_bytes());
+        bsdiff.extend_from_slice(&(result.len() as u64).to_le_bytes());
+        bsdiff.extend_from_slice(&11u32.to_le_bytes());
+        bsdiff.extend_from_slice(&1u32.to_le_bytes());
+        bsdiff.extend_from_slice(&0u32.to_le_bytes());
+        for (new, old) in result[..11].iter().zip(base.iter()) {
+            bsdiff.push(new.wrapping_sub(*old));
+        }
+        bsdiff.push(b'!');
+
+        let bsd0 = build_patch(base, result, b"BSD0", &bsdiff);
+        assert_eq!(apply_patch(base, &bsd0).unwrap(), result);
+        assert!(apply_patch(b"other base", &bsd0).is_err());
+    }
+
+    #[test]
+    fn malformed_patches_are_rejected() {
+        let base = b"hello world";
+
+        let mut short_xfrm = build_patch(base, b"replaced", b"COPY", b"replaced");
+        short_xfrm[0x3c..0x40].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(
+            apply_patch(base, &short_xfrm),
+            Err(Error::PatchError(_))
+        ));
+
+        let mut bsdiff = Vec::new();
+        bsdiff.extend_from_slice(BSDIFF40_SIGNATURE);
+        bsdiff.extend_from_slice(&u64::MAX.to_le_bytes());
+        bsdiff.extend_from_slice(&u64::MAX.to_le_bytes());
+        bsdiff.extend_from_slice(&4u64.to_le_bytes());
+        assert!(apply_bsdiff(base, &bsdiff, 4).is_err());
+
+        bsdiff[8..24].fill(0);
+        bsdiff[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(apply_bsdiff(base, &bsdiff, 4).is_err());
+    }
+
+    #[test]
+    fn rle_expands_zero_runs() {
+        let data = [0, 0, 0, 0, 0x81, 7, 8, 2, 0x80, 9];
+        assert_eq!(decompress_rle(&data, 7), vec![7, 8, 0, 0, 0, 9, 0]);
+    }
+}
@@ -0,0 +1,278 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use wow_mpq::{
+    Archive, BlockEntry, FileInfo, compression::flags as compression_flags, decrypt_file_data,
+    hash_string, hash_type,
+};
+
+use crate::errors::{Error, Result};
+
+/// Set on files whose sector offset table is followed by an Adler-32 checksum per sector.
+pub const FLAG_SECTOR_CRC: u32 = 0x04000000;
+
+/// Raw block table values of an entry, with `file_pos` absolute in the file.
+#[derive(Debug, Clone, Copy)]
+pub struct EntryLocation {
+    pub file_pos: u64,
+    pub compressed_size: u64,
+    pub file_size: u64,
+    pub flags: u32,
+}
+
+impl From<&FileInfo> for EntryLocation {
+    fn from(info: &FileInfo) -> Self {
+        Self {
+            file_pos: info.file_pos,
+            compressed_size: info.compressed_size,
+            file_size: info.file_size,
+            flags: info.flags,
+        }
+    }
+}
+
+/// Location and encoding of a file's stored bytes inside an archive. Unlike `Archive::read_file`
+/// this also understands the patch info header that prefixes files flagged as patches.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub data_pos: u64,
+    pub file_size: u64,
+    pub stored_size: u64,
+    pub flags: u32,
+    pub key: u32,
+    pub sector_size: usize,
+}
+
+fn file_key(name: &str, location: &EntryLocation, archive_offset: u64) -> u32 {
+    let plain_name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+    let key = hash_string(plain_name, hash_type::FILE_KEY);
+    if location.flags & BlockEntry::FLAG_FIX_KEY != 0 {
+        key.wrapping_add((location.file_pos - archive_offset) as u32) ^ location.file_size as u32
+    } else {
+        key
+    }
+}
+
+impl StoredFile {
+    pub fn new<R: Read + Seek>(
+        reader: &mut R,
+        archive: &Archive,
+        name: &str,
+        info: &FileInfo,
+    ) -> Result<Self> {
+        Self::locate(
+            reader,
+            &EntryLocation::from(info),
+            Some(name),
+            archive.archive_offset(),
+            archive.header().sector_size(),
+        )
+    }
+
+    /// Like `new`, for entries read straight from the block table. The name is only needed to
+    /// derive the key of encrypted entries.
+    pub fn locate<R: Read + Seek>(
+        reader: &mut R,
+        location: &EntryLocation,
+        name: Option<&str>,
+        archive_offset: u64,
+        sector_size: usize,
+    ) -> Result<Self> {
+        let mut data_pos = location.file_pos;
+        let mut file_size = location.file_size;
+        let mut stored_size = location.compressed_size;
+
+        if location.flags & BlockEntry::FLAG_PATCH_FILE != 0 {
+            reader.seek(SeekFrom::Start(location.file_pos))?;
+            let length = reader.read_u32::<LittleEndian>()?;
+            let _flags = reader.read_u32::<LittleEndian>()?;
+            let data_size = reader.read_u32::<LittleEndian>()?;
+
+            data_pos += length as u64;
+            stored_size = stored_size.saturating_sub(length as u64);
+            file_size = data_size as u64;
+        }
+
+        let key = if location.flags & BlockEntry::FLAG_ENCRYPTED != 0 {
+            let name = name.ok_or(Error::Generic("encrypted entry without a known name"))?;
+            file_key(name, location, archive_offset)
+        } else {
+            0
+        };
+
+        Ok(Self {
+            data_pos,
+            file_size,
+            stored_size,
+            flags: location.flags,
+            key,
+            sector_size,
+        })
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & (BlockEntry::FLAG_COMPRESS | BlockEntry::FLAG_IMPLODE) != 0
+    }
+
+    pub fn is_single_unit(&self) -> bool {
+        self.flags & BlockEntry::FLAG_SINGLE_UNIT != 0
+    }
+
+    pub fn has_sector_crc(&self) -> bool {
+        self.is_compressed() && self.flags & FLAG_SECTOR_CRC != 0
+    }
+
+    pub fn sector_count(&self) -> usize {
+        (self.file_size as usize).div_ceil(self.sector_size)
+    }
+
+    fn decompress(&self, data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+        if data.len() >= expected_size || !self.is_compressed() {
+            return Ok(data[..expected_size.min(data.len())].to_vec());
+        }
+
+        if self.flags & BlockEntry::FLAG_IMPLODE != 0 {
+            return Ok(wow_mpq::decompress(
+                data,
+                compression_flags::PKWARE,
+                expected_size,
+            )?);
+        }
+
+        match data.split_first() {
+            Some((method, compressed)) => {
+                Ok(wow_mpq::decompress(compressed, *method, expected_size)?)
+            }
+            None => Err(Error::Generic("empty compressed sector")),
+        }
+    }
+
+    /// Reads the sector offset table, including the trailing checksum table offset when the
+    /// file has sector checksums.
+    pub fn read_sector_offsets<R: Read + Seek>(&self, reader: &mut R) -> Result<Vec<u32>> {
+        let count = self.sector_count() + 1 + self.has_sector_crc() as usize;
+        let mut table = vec![0u8; count * 4];
+        reader.seek(SeekFrom::Start(self.data_pos))?;
+        reader.read_exact(&mut table)?;
+
+        if self.key != 0 {
+            decrypt_file_data(&mut table, self.key.wrapping_sub(1));
+        }
+
+        let offsets: Vec<u32> = table
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        if offsets.windows(2).any(|w| w[1] < w[0]) || offsets[count - 1] as u64 > self.stored_size {
+            return Err(Error::Generic("invalid sector offset table"));
+        }
+
+        Ok(offsets)
+    }
+
+    fn expected_sector_size(&self, index: usize) -> usize {
+        (self.file_size as usize - index * self.sector_size).min(self.sector_size)
+    }
+
+    /// Reads and decodes a single sector. `offsets` is only used for compressed files and must
+    /// come from `read_sector_offsets`.
+    pub fn read_sector<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offsets: &[u32],
+        index: usize,
+    ) -> Result<Vec<u8>> {
+        let data = self.read_raw_sector(reader, offsets, index)?;
+        self.decompress_sector(&data, index)
+    }
+
+    /// Reads a sector as stored, decrypted but still compressed, which is what sector checksums
+    /// cover.
+    pub fn read_raw_sector<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offsets: &[u32],
+        index: usize,
+    ) -> Result<Vec<u8>> {
+        let start = index * self.sector_size;
+        let expected_size = self.expected_sector_size(index);
+
+        let (pos, size) = if self.is_compressed() {
+            (
+                offsets[index] as u64,
+                (offsets[index + 1] - offsets[index]) as usize,
+            )
+        } else {
+            (start as u64, expected_size)
+        };
+
+        let mut data = vec![0u8; size];
+        reader.seek(SeekFrom::Start(self.data_pos + pos))?;
+        reader.read_exact(&mut data)?;
+
+        if self.key != 0 {
+            decrypt_file_data(&mut data, self.key.wrapping_add(index as u32));
+        }
+
+        Ok(data)
+    }
+
+    pub fn decompress_sector(&self, data: &[u8], index: usize) -> Result<Vec<u8>> {
+        self.decompress(data, self.expected_sector_size(index))
+    }
+
+    /// Reads the per-sector Adler-32 checksums stored after the last sector. A zero checksum
+    /// means the sector wasn't checksummed.
+    pub fn read_sector_crcs<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offsets: &[u32],
+    ) -> Result<Vec<u32>> {
+        let count = self.sector_count();
+        let (start, end) = (offsets[count], offsets[count + 1]);
+        let mut data = vec![0u8; (end - start) as usize];
+        reader.seek(SeekFrom::Start(self.data_pos + start as u64))?;
+        reader.read_exact(&mut data)?;
+
+        let table_size = count * 4;
+        let table = match data.split_first() {
+            Some((method, compressed)) if data.len() < table_size => {
+                wow_mpq::decompress(compressed, *method, table_size)?
+            }
+            _ => data,
+        };
+
+        Ok(table
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect())
+    }
+
+    pub fn read_all<R: Read + Seek>(&self, reader: &mut R) -> Result<Vec<u8>> {
+        if self.is_single_unit() {
+            let mut data = vec![0u8; self.stored_size as usize];
+            reader.seek(SeekFrom::Start(self.data_pos))?;
+            reader.read_exact(&mut data)?;
+
+            if self.key != 0 {
+                decrypt_file_data(&mut data, self.key);
+            }
+
+            return self.decompress(&data, self.file_size as usize);
+        }
+
+        let offsets = if self.is_compressed() {
+            self.read_sector_offsets(reader)?
+        } else {
+            Vec::new()
+        };
+
+        let mut result = Vec::with_capacity(self.file_size as usize);
+        for index in 0..self.sector_count() {
+            result.extend_from_slice(&self.read_sector(reader, &offsets, index)?);
+        }
+
+        Ok(result)
+    }
+}
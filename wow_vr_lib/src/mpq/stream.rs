@@ -0,0 +1,232 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bevy::tasks::futures_lite::io::{AsyncRead, AsyncSeek};
+use bevy_asset::io::{AsyncSeekForward, Reader, STACK_FUTURE_SIZE, StackFuture};
+use wow_mpq::FileInfo;
+
+use crate::errors::{Error, Result};
+
+use super::{archive::MpqArchive, sector::StoredFile};
+
+struct SectorSource<'a> {
+    archive: &'a MpqArchive,
+    stored: StoredFile,
+    offsets: Vec<u32>,
+    handle: Option<BufReader<File>>,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl SectorSource<'_> {
+    fn sector(&mut self, index: usize) -> io::Result<&[u8]> {
+        if self
+            .cached
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != index)
+        {
+            let handle = self
+                .handle
+                .as_mut()
+                .expect("sector source without a file handle");
+            let data = self
+                .stored
+                .read_sector(handle, &self.offsets, index)
+                .map_err(to_io_error)?;
+            self.cached = Some((index, data));
+        }
+
+        Ok(&self.cached.as_ref().unwrap().1)
+    }
+}
+
+impl Drop for SectorSource<'_> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.archive.return_handle(handle);
+        }
+    }
+}
+
+enum Source<'a> {
+    Memory(Vec<u8>),
+    Sectors(SectorSource<'a>),
+}
+
+/// Seekable reader over a file in the collection. Sectored files are decoded one sector at a
+/// time as they are read, while single unit and patched files are decoded up front because
+/// they can't be read partially.
+pub struct MpqFileReader<'a> {
+    source: Source<'a>,
+    size: u64,
+    pos: u64,
+}
+
+impl<'a> MpqFileReader<'a> {
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            size: data.len() as u64,
+            source: Source::Memory(data),
+            pos: 0,
+        }
+    }
+
+    /// Opens a regular entry for streaming. Patch entries must be resolved by the caller.
+    pub fn open(archive: &'a MpqArchive, name: &str, info: &FileInfo) -> Result<Self> {
+        let mut handle = archive.take_handle()?;
+        let stored = match StoredFile::new(&mut handle, archive.archive(), name, info) {
+            Ok(stored) => stored,
+            Err(err) => {
+                archive.return_handle(handle);
+                return Err(err);
+            }
+        };
+
+        let offsets = if stored.is_single_unit() {
+            let data = stored.read_all(&mut handle);
+            archive.return_handle(handle);
+            return Ok(Self::from_bytes(data?));
+        } else if stored.is_compressed() {
+            stored.read_sector_offsets(&mut handle)
+        } else {
+            Ok(Vec::new())
+        };
+
+        let size = stored.file_size;
+        let mut source = SectorSource {
+            archive,
+            stored,
+            offsets: Vec::new(),
+            handle: Some(handle),
+            cached: None,
+        };
+        // The source returns the handle to the pool when dropped, including on this error.
+        source.offsets = offsets?;
+
+        Ok(Self {
+            source: Source::Sectors(source),
+            size,
+            pos: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl Read for MpqFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let read = match &mut self.source {
+            Source::Memory(data) => (&data[self.pos as usize..]).read(buf)?,
+            Source::Sectors(source) => {
+                let sector_size = source.stored.sector_size as u64;
+                let offset = (self.pos % sector_size) as usize;
+                let sector = source.sector((self.pos / sector_size) as usize)?;
+                let available = sector.len().saturating_sub(offset);
+                if available == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "sector shorter than expected",
+                    ));
+                }
+
+                let count = available.min(buf.len());
+                buf[..count].copy_from_slice(&sector[offset..offset + count]);
+                count
+            }
+        };
+
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for MpqFileReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        match target {
+            Some(target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek position is out of range",
+            )),
+        }
+    }
+}
+
+// Sector decoding is synchronous, like the rest of the MPQ reads, so every poll completes
+// immediately.
+impl AsyncRead for MpqFileReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().read(buf))
+    }
+}
+
+impl AsyncSeek for MpqFileReader<'_> {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        Poll::Ready(self.get_mut().seek(pos))
+    }
+}
+
+impl AsyncSeekForward for MpqFileReader<'_> {
+    fn poll_seek_forward(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        offset: u64,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let target = this.pos.saturating_add(offset);
+        Poll::Ready(this.seek(SeekFrom::Start(target)))
+    }
+}
+
+impl Reader for MpqFileReader<'_> {
+    fn read_to_end<'a>(
+        &'a mut self,
+        buf: &'a mut Vec<u8>,
+    ) -> StackFuture<'a, io::Result<usize>, STACK_FUTURE_SIZE> {
+        StackFuture::from(async {
+            buf.reserve(self.size.saturating_sub(self.pos) as usize);
+            Read::read_to_end(self, buf)
+        })
+    }
+}
+
+pub(crate) fn to_io_error(err: Error) -> io::Error {
+    match err {
+        Error::MpqError(wow_mpq::Error::Io(err)) => err,
+        Error::Io(err) => err,
+        Error::MpqError(wow_mpq::Error::FileNotFound(name)) | Error::AssetNotFound(name) => {
+            io::Error::new(io::ErrorKind::NotFound, name)
+        }
+        _ => io::Error::other(err),
+    }
+}
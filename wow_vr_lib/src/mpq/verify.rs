@@ -0,0 +1,884 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::Crc;
+use md5::{Digest, Md5};
+use num_bigint::BigUint;
+use sha1::Sha1;
+use wow_mpq::{BlockEntry, decrypt_file_data, hash_string, hash_type};
+
+use super::{
+    MpqCollection,
+    archive::MpqArchive,
+    sector::{EntryLocation, StoredFile},
+};
+use crate::errors::{Error, Result};
+
+const MPQ_MAGIC: &[u8; 4] = b"MPQ\x1A";
+const USER_DATA_MAGIC: &[u8; 4] = b"MPQ\x1B";
+const HEADER_ALIGNMENT: u64 = 512;
+// Sector sizes are `512 << shift`. The client never goes past 8 MB sectors.
+const MAX_SECTOR_SHIFT: u16 = 23;
+
+const HASH_ENTRY_EMPTY: u32 = 0xFFFFFFFF;
+const HASH_ENTRY_DELETED: u32 = 0xFFFFFFFE;
+
+const ATTRIBUTES_NAME: &str = "(attributes)";
+const ATTRIBUTE_CRC32: u32 = 0x1;
+const ATTRIBUTE_FILETIME: u32 = 0x2;
+const ATTRIBUTE_MD5: u32 = 0x4;
+
+// Internal files that aren't always in the listfile but are worth checking.
+pub(super) const SPECIAL_FILES: [&str; 3] = ["(listfile)", "(attributes)", "(signature)"];
+
+const STRONG_SIGNATURE_MAGIC: &[u8; 4] = b"NGIS";
+const STRONG_SIGNATURE_SIZE: usize = 256;
+const STRONG_SIGNATURE_EXPONENT: u32 = 0x10001;
+
+/// Adler-32 with a zero seed, as used for MPQ sector checksums.
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (0u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The file couldn't be opened or has no MPQ header.
+    Unreadable,
+    /// The archive or one of its tables ends past the end of the file.
+    ArchiveTruncated,
+    /// The hash or block table contradicts itself or fails its checksum.
+    TableCorrupt,
+    /// An entry's data ends past the end of the archive.
+    EntryTruncated,
+    SectorCrcMismatch,
+    Crc32Mismatch,
+    Md5Mismatch,
+    /// The entry couldn't be decrypted or decompressed.
+    DecodeFailed,
+    SignatureInvalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyIssue {
+    pub kind: IssueKind,
+    pub file: Option<String>,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Absent,
+    /// A strong signature is present but no public key was given to check it.
+    Unchecked,
+    Valid,
+    Invalid,
+    Truncated,
+}
+
+/// Result of verifying one archive. `files_skipped` counts encrypted entries whose name isn't
+/// known, since their key can't be derived.
+#[derive(Debug, Clone)]
+pub struct ArchiveReport {
+    pub path: PathBuf,
+    pub files_checked: usize,
+    pub files_skipped: usize,
+    pub signature: SignatureStatus,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl ArchiveReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn issue(&mut self, kind: IssueKind, file: Option<&str>, detail: impl Into<String>) {
+        self.issues.push(VerifyIssue {
+            kind,
+            file: file.map(str::to_owned),
+            detail: detail.into(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// Big endian RSA modulus used to check strong signatures. Without it signatures are only
+    /// checked for being complete.
+    pub strong_signature_key: Option<Vec<u8>>,
+}
+
+/// Header fields as stored on disk, read independently of the archive parser so damaged
+/// archives can still be described.
+#[derive(Debug, Default)]
+struct RawHeader {
+    header_size: u64,
+    sector_size: usize,
+    archive_size: u64,
+    hash_table_pos: u64,
+    block_table_pos: u64,
+    hash_table_entries: usize,
+    block_table_entries: usize,
+    hi_block_table_pos: u64,
+    hash_table_stored: Option<u64>,
+    block_table_stored: Option<u64>,
+    het_table_pos: u64,
+    md5s: Option<[[u8; 16]; 6]>,
+    raw: Vec<u8>,
+}
+
+impl RawHeader {
+    fn read(reader: &mut BufReader<File>, archive_offset: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(archive_offset))?;
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MPQ_MAGIC {
+            return Err(Error::Generic("missing MPQ header"));
+        }
+
+        let header_size = reader.read_u32::<LittleEndian>()? as u64;
+        let mut header = Self {
+            header_size,
+            archive_size: reader.read_u32::<LittleEndian>()? as u64,
+            ..Default::default()
+        };
+        let _format_version = reader.read_u16::<LittleEndian>()?;
+        let sector_shift = reader.read_u16::<LittleEndian>()?;
+        header.sector_size = Some(sector_shift)
+            .filter(|shift| *shift <= MAX_SECTOR_SHIFT)
+            .and_then(|shift| 512usize.checked_shl(shift as u32))
+            .ok_or(Error::Generic("invalid sector size shift"))?;
+        header.hash_table_pos = reader.read_u32::<LittleEndian>()? as u64;
+        header.block_table_pos = reader.read_u32::<LittleEndian>()? as u64;
+        header.hash_table_entries = reader.read_u32::<LittleEndian>()? as usize;
+        header.block_table_entries = reader.read_u32::<LittleEndian>()? as usize;
+
+        if header_size >= 44 {
+            header.hi_block_table_pos = reader.read_u64::<LittleEndian>()?;
+            header.hash_table_pos |= (reader.read_u16::<LittleEndian>()? as u64) << 32;
+            header.block_table_pos |= (reader.read_u16::<LittleEndian>()? as u64) << 32;
+        }
+
+        if header_size >= 68 {
+            header.archive_size = reader.read_u64::<LittleEndian>()?;
+            let _bet_table_pos = reader.read_u64::<LittleEndian>()?;
+            header.het_table_pos = reader.read_u64::<LittleEndian>()?;
+        }
+
+        if header_size >= 208 {
+            header.hash_table_stored = Some(reader.read_u64::<LittleEndian>()?);
+            header.block_table_stored = Some(reader.read_u64::<LittleEndian>()?);
+            let mut sizes = [0u8; 3 * 8 + 4];
+            reader.read_exact(&mut sizes)?;
+            let mut md5s = [[0u8; 16]; 6];
+            for md5 in md5s.iter_mut() {
+                reader.read_exact(md5)?;
+            }
+            header.md5s = Some(md5s);
+
+            // The header checksum covers everything before it.
+            reader.seek(SeekFrom::Start(archive_offset))?;
+            header.raw = vec![0u8; 208 - 16];
+            reader.read_exact(&mut header.raw)?;
+        }
+
+        Ok(header)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HashEntry {
+    name_a: u32,
+    name_b: u32,
+    block_index: u32,
+}
+
+struct RawTables {
+    hashes: Vec<HashEntry>,
+    blocks: Vec<EntryLocation>,
+}
+
+impl MpqArchive {
+    /// Checks the tables, every entry and the strong signature of the archive, see
+    /// `verify_archive`.
+    pub fn verify<S: AsRef<str>>(
+        &self,
+        names: impl IntoIterator<Item = S>,
+        options: &VerifyOptions,
+    ) -> ArchiveReport {
+        verify_archive(self.path(), names, options)
+    }
+}
+
+/// Checks the tables, every entry and the strong signature of the archive at `path`. The file
+/// is read directly rather than through the archive parser, so archives too damaged to open
+/// still get a report. `names` are used to label entries and to derive the keys of encrypted
+/// ones, other entries are still checked but reported without a name.
+pub fn verify_archive<S: AsRef<str>>(
+    path: impl AsRef<Path>,
+    names: impl IntoIterator<Item = S>,
+    options: &VerifyOptions,
+) -> ArchiveReport {
+    let path = path.as_ref();
+    let mut report = ArchiveReport {
+        path: path.to_path_buf(),
+        files_checked: 0,
+        files_skipped: 0,
+        signature: SignatureStatus::Absent,
+        issues: Vec::new(),
+    };
+
+    let result = File::open(path)
+        .map_err(Error::from)
+        .and_then(|file| verify_with(&mut BufReader::new(file), names, options, &mut report));
+    if let Err(err) = result {
+        report.issue(IssueKind::Unreadable, None, format!("{:?}", err));
+    }
+
+    report
+}
+
+/// Finds the MPQ header, which sits on a 512 byte boundary and may be pointed to by a user data
+/// header.
+fn find_archive_offset(reader: &mut BufReader<File>, file_size: u64) -> Result<u64> {
+    let mut offset = 0;
+    while offset + 4 <= file_size {
+        let mut magic = [0u8; 4];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut magic)?;
+        if &magic == MPQ_MAGIC {
+            return Ok(offset);
+        }
+        if &magic == USER_DATA_MAGIC {
+            let _user_data_size = reader.read_u32::<LittleEndian>()?;
+            return Ok(offset + reader.read_u32::<LittleEndian>()? as u64);
+        }
+        offset += HEADER_ALIGNMENT;
+    }
+
+    Err(Error::Generic("missing MPQ header"))
+}
+
+fn verify_with<S: AsRef<str>>(
+    reader: &mut BufReader<File>,
+    names: impl IntoIterator<Item = S>,
+    options: &VerifyOptions,
+    report: &mut ArchiveReport,
+) -> Result<()> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let archive_offset = find_archive_offset(reader, file_size)?;
+    let header = RawHeader::read(reader, archive_offset)?;
+    let archive_end = archive_offset + header.archive_size;
+
+    if archive_end > file_size {
+        report.issue(
+            IssueKind::ArchiveTruncated,
+            None,
+            format!(
+                "archive needs {} bytes but the file has {}",
+                archive_end, file_size
+            ),
+        );
+    }
+
+    if header
+        .md5s
+        .is_some_and(|md5s| !md5_matches(&md5s[5], &header.raw))
+    {
+        report.issue(IssueKind::TableCorrupt, None, "header MD5 mismatch");
+    }
+
+    let Some(tables) = read_tables(reader, &header, archive_offset, file_size, report)? else {
+        return Ok(());
+    };
+    check_table_consistency(&tables, report);
+
+    let mut block_names: HashMap<u32, String> = HashMap::new();
+    for name in names
+        .into_iter()
+        .map(|name| name.as_ref().replace('/', "\\"))
+        .chain(SPECIAL_FILES.iter().map(|name| name.to_string()))
+    {
+        for block_index in lookup(&tables.hashes, &name) {
+            block_names
+                .entry(block_index)
+                .or_insert_with(|| name.clone());
+        }
+    }
+
+    let attributes = block_names
+        .iter()
+        .find(|(_, name)| name.as_str() == ATTRIBUTES_NAME)
+        .and_then(|(index, _)| tables.blocks.get(*index as usize))
+        .map(|block| {
+            let stored = StoredFile::locate(
+                reader,
+                block,
+                Some(ATTRIBUTES_NAME),
+                archive_offset,
+                header.sector_size,
+            )?;
+            parse_attributes(&stored.read_all(reader)?, tables.blocks.len())
+        })
+        .transpose()
+        .unwrap_or_else(|err| {
+            report.issue(
+                IssueKind::DecodeFailed,
+                Some(ATTRIBUTES_NAME),
+                format!("{:?}", err),
+            );
+            None
+        });
+
+    let mut referenced: Vec<u32> = tables
+        .hashes
+        .iter()
+        .map(|entry| entry.block_index)
+        .filter(|index| (*index as usize) < tables.blocks.len())
+        .collect();
+    referenced.sort_unstable();
+    referenced.dedup();
+
+    for block_index in referenced {
+        let block = &tables.blocks[block_index as usize];
+        let name = block_names.get(&block_index).map(String::as_str);
+        if block.flags & BlockEntry::FLAG_EXISTS == 0 {
+            continue;
+        }
+        if block.flags & BlockEntry::FLAG_DELETE_MARKER != 0 {
+            report.files_checked += 1;
+            continue;
+        }
+
+        let end = block.file_pos + block.compressed_size;
+        if end > archive_end.min(file_size) {
+            report.issue(
+                IssueKind::EntryTruncated,
+                name,
+                format!("block {} ends at {} past the archive", block_index, end),
+            );
+            continue;
+        }
+
+        if block.flags & BlockEntry::FLAG_ENCRYPTED != 0 && name.is_none() {
+            report.files_skipped += 1;
+            continue;
+        }
+
+        report.files_checked += 1;
+        let data = match StoredFile::locate(reader, block, name, archive_offset, header.sector_size)
+            .and_then(|stored| read_checked(reader, &stored, block_index, name, report))
+        {
+            Ok(data) => data,
+            Err(err) => {
+                report.issue(IssueKind::DecodeFailed, name, format!("{:?}", err));
+                continue;
+            }
+        };
+
+        // Checksums of patch entries don't describe the stored patch data.
+        if let Some(attributes) = attributes
+            .as_ref()
+            .filter(|_| block.flags & BlockEntry::FLAG_PATCH_FILE == 0)
+        {
+            attributes.check(block_index as usize, &data, name, report);
+        }
+    }
+
+    report.signature =
+        check_strong_signature(reader, archive_offset, archive_end, file_size, options)?;
+    match report.signature {
+        SignatureStatus::Invalid => report.issue(
+            IssueKind::SignatureInvalid,
+            None,
+            "strong signature mismatch",
+        ),
+        SignatureStatus::Truncated => report.issue(
+            IssueKind::ArchiveTruncated,
+            None,
+            "strong signature is incomplete",
+        ),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+impl MpqCollection {
+    /// Verifies every archive in load order, labeling entries with the names in the index.
+    pub fn verify(&self, options: &VerifyOptions) -> Vec<ArchiveReport> {
+        self.archives
+            .iter()
+            .map(|archive| archive.verify(self.file_map.keys(), options))
+            .collect()
+    }
+}
+
+fn md5_matches(expected: &[u8; 16], data: &[u8]) -> bool {
+    *expected == [0; 16] || Md5::digest(data).as_slice() == expected
+}
+
+fn read_table(
+    reader: &mut BufReader<File>,
+    pos: u64,
+    entries: usize,
+    stored_size: Option<u64>,
+    key_name: &str,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let size = entries * 16;
+    let mut stored = vec![0u8; stored_size.map_or(size, |stored| stored as usize)];
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(&mut stored)?;
+
+    let mut table = match stored.split_first() {
+        Some((method, compressed)) if stored.len() < size => {
+            wow_mpq::decompress(compressed, *method, size)?
+        }
+        _ => stored[..size.min(stored.len())].to_vec(),
+    };
+    decrypt_file_data(&mut table, hash_string(key_name, hash_type::FILE_KEY));
+
+    Ok((stored, table))
+}
+
+fn read_tables(
+    reader: &mut BufReader<File>,
+    header: &RawHeader,
+    archive_offset: u64,
+    file_size: u64,
+    report: &mut ArchiveReport,
+) -> Result<Option<RawTables>> {
+    if header.hash_table_entries == 0 {
+        if header.het_table_pos == 0 {
+            report.issue(IssueKind::TableCorrupt, None, "archive has no hash table");
+        }
+        return Ok(None);
+    }
+
+    let hash_pos = archive_offset + header.hash_table_pos;
+    let block_pos = archive_offset + header.block_table_pos;
+    let hash_size = header
+        .hash_table_stored
+        .unwrap_or(header.hash_table_entries as u64 * 16);
+    let block_size = header
+        .block_table_stored
+        .unwrap_or(header.block_table_entries as u64 * 16);
+    if hash_pos + hash_size > file_size || block_pos + block_size > file_size {
+        report.issue(
+            IssueKind::ArchiveTruncated,
+            None,
+            "hash or block table lies past the end of the file",
+        );
+        return Ok(None);
+    }
+
+    if !header.hash_table_entries.is_power_of_two() {
+        report.issue(
+            IssueKind::TableCorrupt,
+            None,
+            format!(
+                "hash table size {} isn't a power of two",
+                header.hash_table_entries
+            ),
+        );
+    }
+
+    let (hash_stored, hash_data) = read_table(
+        reader,
+        hash_pos,
+        header.hash_table_entries,
+        header.hash_table_stored,
+        "(hash table)",
+    )?;
+    let (block_stored, block_data) = read_table(
+        reader,
+        block_pos,
+        header.block_table_entries,
+        header.block_table_stored,
+        "(block table)",
+    )?;
+
+    let mut hi_positions = vec![0u16; header.block_table_entries];
+    if header.hi_block_table_pos != 0 {
+        reader.seek(SeekFrom::Start(archive_offset + header.hi_block_table_pos))?;
+        reader.read_u16_into::<LittleEndian>(&mut hi_positions)?;
+    }
+
+    if let Some(md5s) = &header.md5s {
+        if !md5_matches(&md5s[0], &block_stored) {
+            report.issue(IssueKind::TableCorrupt, None, "block table MD5 mismatch");
+        }
+        if !md5_matches(&md5s[1], &hash_stored) {
+            report.issue(IssueKind::TableCorrupt, None, "hash table MD5 mismatch");
+        }
+    }
+
+    let hashes = hash_data
+        .chunks_exact(16)
+        .map(|entry| {
+            let value = |index: usize| {
+                u32::from_le_bytes(entry[index * 4..index * 4 + 4].try_into().unwrap())
+            };
+            HashEntry {
+                name_a: value(0),
+                name_b: value(1),
+                block_index: value(3),
+            }
+        })
+        .collect();
+
+    let blocks = block_data
+        .chunks_exact(16)
+        .zip(hi_positions)
+        .map(|(entry, hi)| {
+            let value = |index: usize| {
+                u32::from_le_bytes(entry[index * 4..index * 4 + 4].try_into().unwrap()) as u64
+            };
+            EntryLocation {
+                file_pos: archive_offset + (value(0) | ((hi as u64) << 32)),
+                compressed_size: value(1),
+                file_size: value(2),
+                flags: value(3) as u32,
+            }
+        })
+        .collect();
+
+    Ok(Some(RawTables { hashes, blocks }))
+}
+
+fn check_table_consistency(tables: &RawTables, report: &mut ArchiveReport) {
+    for (slot, entry) in tables.hashes.iter().enumerate() {
+        if entry.block_index == HASH_ENTRY_EMPTY || entry.block_index == HASH_ENTRY_DELETED {
+            continue;
+        }
+
+        match tables.blocks.get(entry.block_index as usize) {
+            None => report.issue(
+                IssueKind::TableCorrupt,
+                None,
+                format!(
+                    "hash entry {} points to missing block {}",
+                    slot, entry.block_index
+                ),
+            ),
+            Some(block) if block.flags & BlockEntry::FLAG_EXISTS == 0 => report.issue(
+                IssueKind::TableCorrupt,
+                None,
+                format!(
+                    "hash entry {} points to unused block {}",
+                    slot, entry.block_index
+                ),
+            ),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Block indices of every entry for `name`, across all locales.
+fn lookup(hashes: &[HashEntry], name: &str) -> Vec<u32> {
+    let count = hashes.len();
+    let start = hash_string(name, hash_type::TABLE_OFFSET) as usize % count;
+    let name_a = hash_string(name, hash_type::NAME_A);
+    let name_b = hash_string(name, hash_type::NAME_B);
+
+    (0..count)
+        .map(|step| &hashes[(start + step) % count])
+        .take_while(|entry| entry.block_index != HASH_ENTRY_EMPTY)
+        .filter(|entry| {
+            entry.block_index != HASH_ENTRY_DELETED
+                && entry.name_a == name_a
+                && entry.name_b == name_b
+        })
+        .map(|entry| entry.block_index)
+        .collect()
+}
+
+/// Reads a whole entry sector by sector, reporting sectors whose checksum doesn't match.
+fn read_checked(
+    reader: &mut BufReader<File>,
+    stored: &StoredFile,
+    block_index: u32,
+    name: Option<&str>,
+    report: &mut ArchiveReport,
+) -> Result<Vec<u8>> {
+    if stored.is_single_unit() || !stored.is_compressed() {
+        return stored.read_all(reader);
+    }
+
+    let offsets = stored.read_sector_offsets(reader)?;
+    let crcs = if stored.has_sector_crc() {
+        stored.read_sector_crcs(reader, &offsets)?
+    } else {
+        Vec::new()
+    };
+
+    let mut data = Vec::with_capacity(stored.file_size as usize);
+    for index in 0..stored.sector_count() {
+        let raw = stored.read_raw_sector(reader, &offsets, index)?;
+        if crcs
+            .get(index)
+            .is_some_and(|crc| *crc != 0 && *crc != adler32(&raw))
+        {
+            report.issue(
+                IssueKind::SectorCrcMismatch,
+                name,
+                format!("block {} sector {}", block_index, index),
+            );
+        }
+        data.extend_from_slice(&stored.decompress_sector(&raw, index)?);
+    }
+
+    if data.len() as u64 != stored.file_size {
+        return Err(Error::Generic("decoded size doesn't match the block table"));
+    }
+
+    Ok(data)
+}
+
+/// Per-block checksums from the `(attributes)` file.
+struct Attributes {
+    crc32: Vec<u32>,
+    md5: Vec<[u8; 16]>,
+}
+
+impl Attributes {
+    fn check(
+        &self,
+        block_index: usize,
+        data: &[u8],
+        name: Option<&str>,
+        report: &mut ArchiveReport,
+    ) {
+        let mut crc = Crc::new();
+        crc.update(data);
+        if self
+            .crc32
+            .get(block_index)
+            .is_some_and(|expected| *expected != 0 && *expected != crc.sum())
+        {
+            report.issue(
+                IssueKind::Crc32Mismatch,
+                name,
+                format!("block {}", block_index),
+            );
+        }
+
+        if self
+            .md5
+            .get(block_index)
+            .is_some_and(|expected| !md5_matches(expected, data))
+        {
+            report.issue(
+                IssueKind::Md5Mismatch,
+                name,
+                format!("block {}", block_index),
+            );
+        }
+    }
+}
+
+fn parse_attributes(data: &[u8], block_count: usize) -> Result<Attributes> {
+    let mut cursor = data;
+    let _version = cursor.read_u32::<LittleEndian>()?;
+    let flags = cursor.read_u32::<LittleEndian>()?;
+
+    let entry_size = |flags: u32| {
+        (flags & ATTRIBUTE_CRC32 != 0) as usize * 4
+            + (flags & ATTRIBUTE_FILETIME != 0) as usize * 8
+            + (flags & ATTRIBUTE_MD5 != 0) as usize * 16
+    };
+
+    // Some tools leave out the entry for `(attributes)` itself.
+    let count = if cursor.len() >= block_count * entry_size(flags) {
+        block_count
+    } else {
+        block_count.saturating_sub(1)
+    };
+
+    let mut crc32 = Vec::new();
+    if flags & ATTRIBUTE_CRC32 != 0 {
+        crc32 = vec![0u32; count];
+        cursor.read_u32_into::<LittleEndian>(&mut crc32)?;
+    }
+    if flags & ATTRIBUTE_FILETIME != 0 {
+        let mut filetimes = vec![0u64; count];
+        cursor.read_u64_into::<LittleEndian>(&mut filetimes)?;
+    }
+
+    let mut md5 = Vec::new();
+    if flags & ATTRIBUTE_MD5 != 0 {
+        md5 = vec![[0u8; 16]; count];
+        for entry in md5.iter_mut() {
+            cursor.read_exact(entry)?;
+        }
+    }
+
+    Ok(Attributes { crc32, md5 })
+}
+
+/// The strong signature follows the archive as `NGIS` and a 2048 bit RSA signature, stored
+/// little endian, of the SHA-1 of the archive padded as `0B BB .. BB <digest>`.
+fn check_strong_signature(
+    reader: &mut BufReader<File>,
+    archive_offset: u64,
+    archive_end: u64,
+    file_size: u64,
+    options: &VerifyOptions,
+) -> Result<SignatureStatus> {
+    if archive_end + 4 > file_size {
+        return Ok(SignatureStatus::Absent);
+    }
+
+    let mut magic = [0u8; 4];
+    reader.seek(SeekFrom::Start(archive_end))?;
+    reader.read_exact(&mut magic)?;
+    if &magic != STRONG_SIGNATURE_MAGIC {
+        return Ok(SignatureStatus::Absent);
+    }
+    if archive_end + 4 + STRONG_SIGNATURE_SIZE as u64 > file_size {
+        return Ok(SignatureStatus::Truncated);
+    }
+
+    let Some(key) = &options.strong_signature_key else {
+        return Ok(SignatureStatus::Unchecked);
+    };
+
+    let mut signature = [0u8; STRONG_SIGNATURE_SIZE];
+    reader.read_exact(&mut signature)?;
+
+    let mut sha1 = Sha1::new();
+    reader.seek(SeekFrom::Start(archive_offset))?;
+    let mut remaining = archive_end - archive_offset;
+    let mut buffer = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let chunk = remaining.min(buffer.len() as u64) as usize;
+        reader.read_exact(&mut buffer[..chunk])?;
+        sha1.update(&buffer[..chunk]);
+        remaining -= chunk as u64;
+    }
+
+    let mut expected = vec![0xBBu8; STRONG_SIGNATURE_SIZE];
+    expected[0] = 0x0B;
+    expected[STRONG_SIGNATURE_SIZE - 20..].copy_from_slice(&sha1.finalize());
+
+    let modulus = BigUint::from_bytes_be(key);
+    let decrypted = BigUint::from_bytes_le(&signature)
+        .modpow(&BigUint::from(STRONG_SIGNATURE_EXPONENT), &modulus)
+        .to_bytes_be();
+
+    Ok(if decrypted == expected {
+        SignatureStatus::Valid
+    } else {
+        SignatureStatus::Invalid
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::mpq::writer::{FileOptions, MpqWriter};
+
+    fn write_test_archive(path: &Path) -> Vec<u8> {
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 7) as u8).collect();
+        MpqWriter::new()
+            .with_attributes(true)
+            .add_file_with_options(
+                "World/Generic/crc.bin",
+                data.clone(),
+                FileOptions {
+                    sector_crc: true,
+                    encrypt: true,
+                    ..Default::default()
+                },
+            )
+            .add_file("World/Generic/plain.txt", b"hello world".to_vec())
+            .write(path)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn adler32_matches_reference() {
+        // zlib's adler32 starts at 1, which adds the length to the high half.
+        assert_eq!(adler32(b"Wikipedia") + (9 << 16) + 1, 0x11E60398);
+    }
+
+    #[test]
+    fn intact_archive_passes() {
+        let path =
+            std::env::temp_dir().join(format!("wow_vr_verify_ok_{}.MPQ", std::process::id()));
+        let data = write_test_archive(&path);
+
+        let collection = MpqCollection::load(vec![path.clone()]).unwrap();
+        assert_eq!(collection.read_file("world/generic/crc.bin").unwrap(), data);
+
+        let reports = collection.verify(&VerifyOptions::default());
+        assert!(reports[0].is_ok(), "{:?}", reports[0].issues);
+        assert_eq!(reports[0].files_checked, 4);
+        assert_eq!(reports[0].signature, SignatureStatus::Absent);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_corrupt_and_truncated_archives() {
+        let path =
+            std::env::temp_dir().join(format!("wow_vr_verify_bad_{}.MPQ", std::process::id()));
+        write_test_archive(&path);
+        let names = ["World/Generic/crc.bin", "World/Generic/plain.txt"];
+
+        // Flip a byte inside the first sector of the first file, which starts after the header.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[32 + 100] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+
+        let report = verify_archive(&path, &names, &VerifyOptions::default());
+        assert!(
+            report
+                .issues
+                .iter()
+                .any(|issue| issue.kind == IssueKind::SectorCrcMismatch
+                    && issue.file.as_deref() == Some("World\\Generic\\crc.bin")),
+            "{:?}",
+            report.issues
+        );
+
+        // A half copied archive loses its tables, which live at the end, and can't be opened.
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let report = verify_archive(&path, &names, &VerifyOptions::default());
+        assert!(
+            report
+                .issues
+                .iter()
+                .any(|issue| issue.kind == IssueKind::ArchiveTruncated)
+        );
+
+        // A corrupt sector size shift is reported instead of overflowing.
+        bytes[14..16].copy_from_slice(&64u16.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let report = verify_archive(&path, &names, &VerifyOptions::default());
+        assert!(
+            report
+                .issues
+                .iter()
+                .any(|issue| issue.kind == IssueKind::Unreadable)
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use flate2::{Compression, Crc, write::ZlibEncoder};
+use md5::{Digest, Md5};
+use once_cell::sync::Lazy;
+use wow_mpq::{BlockEntry, compression::flags as compression_flags, hash_string, hash_type};
+
+use super::{sector::FLAG_SECTOR_CRC, verify::adler32};
+use crate::errors::Result;
+
+const HEADER_SIZE: u32 = 32;
+const PATCH_INFO_SIZE: u32 = 28;
+const LISTFILE_NAME: &str = "(listfile)";
+const ATTRIBUTES_NAME: &str = "(attributes)";
+const ATTRIBUTES_VERSION: u32 = 100;
+const ATTRIBUTE_CRC32: u32 = 0x1;
+const ATTRIBUTE_MD5: u32 = 0x4;
+
+static CRYPT_TABLE: Lazy<[u32; 0x500]> = Lazy::new(|| {
+    let mut table = [0u32; 0x500];
+    let mut seed: u32 = 0x00100001;
+    for index1 in 0..0x100 {
+        let mut index2 = index1;
+        for _ in 0..5 {
+            seed = (seed * 125 + 3) % 0x2AAAAB;
+            let high = (seed & 0xFFFF) << 0x10;
+            seed = (seed * 125 + 3) % 0x2AAAAB;
+            table[index2] = high | (seed & 0xFFFF);
+            index2 += 0x100;
+        }
+    }
+    table
+});
+
+// Only whole dwords are encrypted, a trailing partial dword is stored as is.
+fn encrypt(data: &mut [u8], mut key: u32) {
+    let mut seed: u32 = 0xEEEEEEEE;
+    for chunk in data.chunks_exact_mut(4) {
+        let plain = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        seed = seed.wrapping_add(CRYPT_TABLE[0x400 + (key & 0xFF) as usize]);
+        let encrypted = plain ^ key.wrapping_add(seed);
+        key = ((!key << 0x15).wrapping_add(0x11111111)) | (key >> 0x0B);
+        seed = plain
+            .wrapping_add(seed)
+            .wrapping_add(seed << 5)
+            .wrapping_add(3);
+        chunk.copy_from_slice(&encrypted.to_le_bytes());
+    }
+}
+
+/// How a single file is stored by `MpqWriter`.
+#[derive(Debug, Clone, Copy)]
+pub struct FileOptions {
+    pub compress: bool,
+    pub encrypt: bool,
+    /// Adjusts the encryption key by the file position, like `BlockEntry::FLAG_FIX_KEY`.
+    pub fix_key: bool,
+    /// Stores the data as an incremental patch entry, the data must then be a `PTCH` payload.
+    pub patch: bool,
+    /// Stores an Adler-32 checksum per sector, only used for compressed files.
+    pub sector_crc: bool,
+    /// Leaves the file out of the `(listfile)`, so it can only be found by its hash.
+    pub unlisted: bool,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            compress: true,
+            encrypt: false,
+            fix_key: false,
+            patch: false,
+            sector_crc: false,
+            unlisted: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PendingData {
+    File(Vec<u8>, FileOptions),
+    DeleteMarker,
+}
+
+#[derive(Debug)]
+struct PendingFile {
+    name: String,
+    data: PendingData,
+}
+
+impl PendingFile {
+    fn is_listed(&self) -> bool {
+        !matches!(self.data, PendingData::File(_, options) if options.unlisted)
+    }
+}
+
+/// Builds version 1 MPQ archives, which is what the 3.3.5 client reads for patch archives. Files
+/// are stored in sectors compressed with zlib and can optionally be encrypted.
+#[derive(Debug)]
+pub struct MpqWriter {
+    files: Vec<PendingFile>,
+    sector_size_shift: u16,
+    listfile: bool,
+    attributes: bool,
+}
+
+impl Default for MpqWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MpqWriter {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            sector_size_shift: 3,
+            listfile: true,
+            attributes: false,
+        }
+    }
+
+    /// Sectors are `512 << shift` bytes long, the client uses a shift of 3.
+    pub fn sector_size_shift(mut self, shift: u16) -> Self {
+        self.sector_size_shift = shift;
+        self
+    }
+
+    pub fn with_listfile(mut self, listfile: bool) -> Self {
+        self.listfile = listfile;
+        self
+    }
+
+    /// Adds an `(attributes)` file with the CRC32 and MD5 of every file.
+    pub fn with_attributes(mut self, attributes: bool) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn add_file(self, name: &str, data: impl Into<Vec<u8>>) -> Self {
+        self.add_file_with_options(name, data, FileOptions::default())
+    }
+
+    pub fn add_file_with_options(
+        mut self,
+        name: &str,
+        data: impl Into<Vec<u8>>,
+        options: FileOptions,
+    ) -> Self {
+        self.files.push(PendingFile {
+            name: archive_name(name),
+            data: PendingData::File(data.into(), options),
+        });
+        self
+    }
+
+    pub fn add_file_from_disk(self, name: &str, path: impl AsRef<Path>) -> Result<Self> {
+        let data = fs::read(path)?;
+        Ok(self.add_file(name, data))
+    }
+
+    /// Adds an entry that hides the file from every archive loaded before this one.
+    pub fn add_delete_marker(mut self, name: &str) -> Self {
+        self.files.push(PendingFile {
+            name: archive_name(name),
+            data: PendingData::DeleteMarker,
+        });
+        self
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> Result<()> {
+        let sector_size = 512usize << self.sector_size_shift;
+        let start = writer.stream_position()?;
+        writer.write_all(&[0u8; HEADER_SIZE as usize])?;
+
+        let listfile = self.listfile.then(|| {
+            let names: Vec<&str> = self
+                .files
+                .iter()
+                .filter(|file| file.is_listed())
+                .map(|file| file.name.as_str())
+                .collect();
+            PendingFile {
+                name: LISTFILE_NAME.into(),
+                data: PendingData::File(names.join("\r\n").into_bytes(), FileOptions::default()),
+            }
+        });
+
+        let mut blocks = Vec::new();
+        let mut checksums = Vec::new();
+        for file in self.files.iter().chain(listfile.as_ref()) {
+            let file_pos = (writer.stream_position()? - start) as u32;
+            let block = match &file.data {
+                PendingData::DeleteMarker => {
+                    checksums.push(None);
+                    Block {
+                        file_pos,
+                        compressed_size: 0,
+                        file_size: 0,
+                        flags: BlockEntry::FLAG_EXISTS | BlockEntry::FLAG_DELETE_MARKER,
+                    }
+                }
+                PendingData::File(data, options) => {
+                    checksums.push(Some(data.as_slice()));
+                    let stored = encode_file(&file.name, data, *options, file_pos, sector_size);
+                    writer.write_all(&stored.data)?;
+                    Block {
+                        file_pos,
+                        compressed_size: stored.data.len() as u32,
+                        file_size: data.len() as u32,
+                        flags: stored.flags,
+                    }
+                }
+            };
+            blocks.push((file.name.as_str(), block));
+        }
+
+        if self.attributes {
+            // The attributes file has an entry for itself, left empty like the client does.
+            checksums.push(None);
+            let data = encode_attributes(&checksums);
+            let file_pos = (writer.stream_position()? - start) as u32;
+            let stored = encode_file(
+                ATTRIBUTES_NAME,
+                &data,
+                FileOptions::default(),
+                file_pos,
+                sector_size,
+            );
+            writer.write_all(&stored.data)?;
+            blocks.push((
+                ATTRIBUTES_NAME,
+                Block {
+                    file_pos,
+                    compressed_size: stored.data.len() as u32,
+                    file_size: data.len() as u32,
+                    flags: stored.flags,
+                },
+            ));
+        }
+
+        let hash_table_size = (blocks.len() * 2).next_power_of_two().max(16);
+        let mut hash_table = vec![[0xFFFFFFFFu32; 4]; hash_table_size];
+        for (block_index, (name, _)) in blocks.iter().enumerate() {
+            let mut slot = hash_string(name, hash_type::TABLE_OFFSET) as usize % hash_table_size;
+            while hash_table[slot][3] != 0xFFFFFFFF {
+                slot = (slot + 1) % hash_table_size;
+            }
+            hash_table[slot] = [
+                hash_string(name, hash_type::NAME_A),
+                hash_string(name, hash_type::NAME_B),
+                // Neutral locale and platform.
+                0,
+                block_index as u32,
+            ];
+        }
+
+        let mut hash_data = Vec::with_capacity(hash_table_size * 16);
+        for entry in &hash_table {
+            for value in entry {
+                hash_data.write_u32::<LittleEndian>(*value)?;
+            }
+        }
+        encrypt(
+            &mut hash_data,
+            hash_string("(hash table)", hash_type::FILE_KEY),
+        );
+
+        let mut block_data = Vec::with_capacity(blocks.len() * 16);
+        for (_, block) in &blocks {
+            block_data.write_u32::<LittleEndian>(block.file_pos)?;
+            block_data.write_u32::<LittleEndian>(block.compressed_size)?;
+            block_data.write_u32::<LittleEndian>(block.file_size)?;
+            block_data.write_u32::<LittleEndian>(block.flags)?;
+        }
+        encrypt(
+            &mut block_data,
+            hash_string("(block table)", hash_type::FILE_KEY),
+        );
+
+        let hash_table_pos = (writer.stream_position()? - start) as u32;
+        writer.write_all(&hash_data)?;
+        let block_table_pos = (writer.stream_position()? - start) as u32;
+        writer.write_all(&block_data)?;
+        let archive_size = (writer.stream_position()? - start) as u32;
+
+        writer.seek(SeekFrom::Start(start))?;
+        writer.write_all(b"MPQ\x1A")?;
+        writer.write_u32::<LittleEndian>(HEADER_SIZE)?;
+        writer.write_u32::<LittleEndian>(archive_size)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_u16::<LittleEndian>(self.sector_size_shift)?;
+        writer.write_u32::<LittleEndian>(hash_table_pos)?;
+        writer.write_u32::<LittleEndian>(block_table_pos)?;
+        writer.write_u32::<LittleEndian>(hash_table_size as u32)?;
+        writer.write_u32::<LittleEndian>(blocks.len() as u32)?;
+        writer.seek(SeekFrom::Start(start + archive_size as u64))?;
+
+        Ok(())
+    }
+}
+
+struct Block {
+    file_pos: u32,
+    compressed_size: u32,
+    file_size: u32,
+    flags: u32,
+}
+
+struct StoredData {
+    data: Vec<u8>,
+    flags: u32,
+}
+
+fn archive_name(name: &str) -> String {
+    name.replace('/', "\\")
+}
+
+fn compress_sector(sector: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(vec![compression_flags::ZLIB], Compression::default());
+    let compressed = encoder
+        .write_all(sector)
+        .and_then(|_| encoder.finish())
+        .unwrap_or_default();
+
+    // Sectors that don't shrink are stored raw, readers detect them by their size.
+    if compressed.is_empty() || compressed.len() >= sector.len() {
+        sector.to_vec()
+    } else {
+        compressed
+    }
+}
+
+fn encode_attributes(files: &[Option<&[u8]>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&ATTRIBUTES_VERSION.to_le_bytes());
+    data.extend_from_slice(&(ATTRIBUTE_CRC32 | ATTRIBUTE_MD5).to_le_bytes());
+    for file in files {
+        let crc = file.map_or(0, |file| {
+            let mut crc = Crc::new();
+            crc.update(file);
+            crc.sum()
+        });
+        data.extend_from_slice(&crc.to_le_bytes());
+    }
+    for file in files {
+        let md5: [u8; 16] = file.map_or([0; 16], |file| Md5::digest(file).into());
+        data.extend_from_slice(&md5);
+    }
+    data
+}
+
+fn encode_file(
+    name: &str,
+    data: &[u8],
+    options: FileOptions,
+    file_pos: u32,
+    sector_size: usize,
+) -> StoredData {
+    let mut flags = BlockEntry::FLAG_EXISTS;
+    let mut sectors: Vec<Vec<u8>> = data.chunks(sector_size).map(<[u8]>::to_vec).collect();
+
+    let mut offsets = Vec::new();
+    let mut sector_crcs = Vec::new();
+    if options.compress && !data.is_empty() {
+        flags |= BlockEntry::FLAG_COMPRESS;
+        sectors = sectors
+            .iter()
+            .map(|sector| compress_sector(sector))
+            .collect();
+
+        let table_entries = sectors.len() + 1 + options.sector_crc as usize;
+        let mut offset = (table_entries * 4) as u32;
+        offsets.push(offset);
+        for sector in &sectors {
+            offset += sector.len() as u32;
+            offsets.push(offset);
+        }
+
+        // Checksums cover the stored sectors before encryption and are stored uncompressed.
+        if options.sector_crc {
+            flags |= FLAG_SECTOR_CRC;
+            sector_crcs = sectors
+                .iter()
+                .flat_map(|sector| adler32(sector).to_le_bytes())
+                .collect();
+            offsets.push(offset + sector_crcs.len() as u32);
+        }
+    }
+
+    if options.encrypt {
+        flags |= BlockEntry::FLAG_ENCRYPTED;
+        let plain_name = name.rsplit('\\').next().unwrap_or(name);
+        let mut key = hash_string(plain_name, hash_type::FILE_KEY);
+        if options.fix_key {
+            flags |= BlockEntry::FLAG_FIX_KEY;
+            key = key.wrapping_add(file_pos) ^ data.len() as u32;
+        }
+
+        for (index, sector) in sectors.iter_mut().enumerate() {
+            encrypt(sector, key.wrapping_add(index as u32));
+        }
+
+        if !offsets.is_empty() {
+            let mut table: Vec<u8> = offsets.iter().flat_map(|o| o.to_le_bytes()).collect();
+            encrypt(&mut table, key.wrapping_sub(1));
+            offsets = table
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+        }
+    }
+
+    let mut stored = Vec::new();
+    if options.patch {
+        flags |= BlockEntry::FLAG_PATCH_FILE;
+        stored.extend_from_slice(&PATCH_INFO_SIZE.to_le_bytes());
+        stored.extend_from_slice(&0x80000000u32.to_le_bytes());
+        stored.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        stored.extend_from_slice(Md5::digest(data).as_slice());
+    }
+    for offset in offsets {
+        stored.extend_from_slice(&offset.to_le_bytes());
+    }
+    for sector in sectors {
+        stored.extend_from_slice(&sector);
+    }
+    stored.extend_from_slice(&sector_crcs);
+
+    StoredData {
+        data: stored,
+        flags,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpq::MpqCollection;
+
+    #[test]
+    fn written_archive_reads_back() {
+        let path = std::env::temp_dir().join(format!("wow_vr_writer_{}.MPQ", std::process::id()));
+        let large: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        let noise: Vec<u8> = (0..5_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+
+        MpqWriter::new()
+            .add_file("World/Generic/small.txt", b"hello world".to_vec())
+            .add_file("World/Generic/large.bin", large.clone())
+            .add_file_with_options(
+                "World/Generic/secret.bin",
+                noise.clone(),
+                FileOptions {
+                    encrypt: true,
+                    fix_key: true,
+                    ..Default::default()
+                },
+            )
+            .add_file("empty.txt", Vec::new())
+            .write(&path)
+            .unwrap();
+
+        let collection = MpqCollection::load(vec![path.clone()]).unwrap();
+        assert_eq!(collection.file_map.len(), 4);
+        assert_eq!(
+            collection.read_file("WORLD\\GENERIC\\SMALL.TXT").unwrap(),
+            b"hello world"
+        );
+        assert_eq!(
+            collection.read_file("world/generic/large.bin").unwrap(),
+            large
+        );
+        assert_eq!(
+            collection.read_file("world/generic/secret.bin").unwrap(),
+            noise
+        );
+        assert!(collection.read_file("empty.txt").unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encryption_matches_reader() {
+        let plain = b"0123456789abcdef!".to_vec();
+        let mut data = plain.clone();
+        encrypt(&mut data, 0x1234);
+        assert_ne!(data[..16], plain[..16]);
+        assert_eq!(data[16], b'!');
+
+        wow_mpq::decrypt_file_data(&mut data[..16], 0x1234);
+        assert_eq!(data, plain);
+    }
+}